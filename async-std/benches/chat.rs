@@ -0,0 +1,49 @@
+//! Chat server fan-out benchmark
+//!
+//! Runs the [`Server`] and the [`Load`] generator in-process with a small
+//! fixed scenario.
+//!
+//! [`server`]: ../src/server/mod.rs
+//! [`load`]: ../src/load.rs
+use async_std::net::TcpStream;
+use async_std::task;
+use async_std_book::load::Load;
+use async_std_book::Server;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+fn server() -> String {
+    // Let the kernel pick the free port for the server.
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|s| s.local_addr())
+        .expect("cannot find a free port")
+        .to_string();
+    task::spawn(Server::new(addr.clone()).run());
+    task::block_on(async {
+        while TcpStream::connect(&addr).await.is_err() {
+            task::sleep(Duration::from_millis(10)).await;
+        }
+    });
+    addr
+}
+
+fn fan_out(c: &mut Criterion) {
+    let addr = server();
+    c.bench_function("chat 4 clients 10 messages", |b| {
+        b.iter(|| {
+            let load = Load::new(addr.clone())
+                .clients(4)
+                .rate(0)
+                .messages(10)
+                .warmup(Duration::from_millis(10));
+            task::block_on(load.run()).expect("load failed")
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = fan_out
+}
+criterion_main!(benches);
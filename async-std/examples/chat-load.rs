@@ -0,0 +1,27 @@
+//! Chat load generator
+//!
+//! Drives simulated clients against the [chat server] and reports
+//! the throughput and the fan-out latency percentiles.
+//!
+//! ```sh
+//! cargo run --release --example chat-load -- [::1]:8000 100 10 1000
+//! ```
+//! [chat server]: chat-server.rs
+use async_std::task;
+use async_std_book::load::Load;
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| String::from("[::1]:8000"));
+    let clients = args.next().map(|n| n.parse()).unwrap_or(Ok(10))?;
+    let rate = args.next().map(|n| n.parse()).unwrap_or(Ok(10))?;
+    let messages = args.next().map(|n| n.parse()).unwrap_or(Ok(100))?;
+    let load = Load::new(addr)
+        .clients(clients)
+        .rate(rate)
+        .messages(messages);
+    println!("{}", task::block_on(load.run())?);
+    Ok(())
+}
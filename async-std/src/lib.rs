@@ -34,11 +34,29 @@
 //!     task::block_on(Server::new(addr).run())
 //! }
 //! ```
+//!
+//! Chat load generator
+//!
+//! ```no_run
+//! use async_std::task;
+//! use async_std_book::load::Load;
+//!
+//! type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+//!
+//! fn main() -> Result<(), Error> {
+//!     let addr = std::env::args()
+//!         .nth(1)
+//!         .unwrap_or_else(|| String::from("[::1]:8000"));
+//!     println!("{}", task::block_on(Load::new(addr).run())?);
+//!     Ok(())
+//! }
+//! ```
 //! [async-std]: https://book.async.rs
 #![recursion_limit = "1024"]
 pub use client::Client;
 pub use server::Server;
 pub mod client;
+pub mod load;
 pub mod server;
 
 // crate local alias types.
@@ -0,0 +1,361 @@
+//! Chat load generator
+//!
+//! [`Load`] drives a number of simulated chat clients against a running
+//! [`Server`] and measures the message throughput and the end-to-end
+//! fan-out latency, e.g. the time between a client sending a message
+//! and each of the other clients receiving it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use async_std::task;
+//! use async_std_book::load::Load;
+//!
+//! type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+//!
+//! fn main() -> Result<(), Error> {
+//!     let addr = std::env::args()
+//!         .nth(1)
+//!         .unwrap_or_else(|| String::from("[::1]:8000"));
+//!     let load = Load::new(addr).clients(10).rate(100).messages(1_000);
+//!     let report = task::block_on(load.run())?;
+//!     println!("{}", report);
+//!     Ok(())
+//! }
+//! ```
+//! [`load`]: struct.Load.html
+//! [`server`]: ../server/struct.Server.html
+use async_std::io::{self, BufReader};
+use async_std::net::TcpStream;
+use async_std::task;
+use futures::io::{AsyncBufReadExt, AsyncWriteExt};
+use futures::stream::StreamExt;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::Result;
+
+/// Load generator run counter, to give each run unique client names.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// A chat `Load` generator.
+pub struct Load {
+    addr: String,
+    clients: usize,
+    rate: u32,
+    messages: usize,
+    warmup: Duration,
+    drain: Duration,
+}
+
+impl Load {
+    /// `new` creates a new load generator against the server on `addr`.
+    ///
+    /// It defaults to 10 clients, each sending 100 messages at the rate
+    /// of 10 messages per second.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use async_std_book::load::Load;
+    ///
+    /// let addr = std::env::args()
+    ///     .nth(1)
+    ///     .unwrap_or_else(|| String::from("localhost:8000"));
+    /// let _load = Load::new(addr);
+    /// ```
+    pub fn new(addr: String) -> Self {
+        Self {
+            addr,
+            clients: 10,
+            rate: 10,
+            messages: 100,
+            warmup: Duration::from_millis(200),
+            drain: Duration::from_secs(1),
+        }
+    }
+    /// `clients` sets the number of the simulated clients.
+    pub fn clients(mut self, clients: usize) -> Self {
+        self.clients = clients;
+        self
+    }
+    /// `rate` sets the messages per second sent by each client.
+    ///
+    /// The zero `rate` sends messages as fast as possible.
+    pub fn rate(mut self, rate: u32) -> Self {
+        self.rate = rate;
+        self
+    }
+    /// `messages` sets the number of messages sent by each client.
+    pub fn messages(mut self, messages: usize) -> Self {
+        self.messages = messages;
+        self
+    }
+    /// `warmup` sets the time to wait for the server to register
+    /// all the clients before sending messages.
+    pub fn warmup(mut self, warmup: Duration) -> Self {
+        self.warmup = warmup;
+        self
+    }
+    /// `drain` sets the idle time for each client to wait for the
+    /// messages still in flight before giving up.
+    pub fn drain(mut self, drain: Duration) -> Self {
+        self.drain = drain;
+        self
+    }
+    /// `run` connects all the clients, sends the messages and returns
+    /// the [`Report`] once all the messages are delivered or drained.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use async_std::task;
+    /// use async_std_book::load::Load;
+    ///
+    /// let addr = std::env::args()
+    ///     .nth(1)
+    ///     .unwrap_or_else(|| String::from("localhost:8000"));
+    /// let report = task::block_on(Load::new(addr).run()).unwrap();
+    /// println!("{}", report);
+    /// ```
+    /// [`report`]: struct.Report.html
+    pub async fn run(self) -> Result<Report> {
+        if self.clients < 2 {
+            return Err("load needs at least two clients".into());
+        }
+        let run = RUNS.fetch_add(1, Ordering::Relaxed);
+        let mut streams = Vec::with_capacity(self.clients);
+        for i in 0..self.clients {
+            let s = TcpStream::connect(&self.addr).await?;
+            let name = format!("load{}-{}\n", run, i);
+            (&s).write_all(name.as_bytes()).await?;
+            streams.push(Arc::new(s));
+        }
+        task::sleep(self.warmup).await;
+
+        let period = match self.rate {
+            0 => Duration::from_secs(0),
+            rate => Duration::from_secs(1) / rate,
+        };
+        let expected = (self.clients - 1) * self.messages;
+        let idle = self.drain + period;
+        let start = Instant::now();
+        let mut senders = Vec::with_capacity(self.clients);
+        let mut receivers = Vec::with_capacity(self.clients);
+        for s in &streams {
+            receivers.push(task::spawn(receive(s.clone(), start, expected, idle)));
+            senders.push(task::spawn(send(s.clone(), start, period, self.messages)));
+        }
+        let mut sent = 0;
+        for sender in senders {
+            sent += sender.await?;
+        }
+        let mut latencies = Vec::with_capacity(expected * self.clients);
+        let mut elapsed = Duration::from_secs(0);
+        for receiver in receivers {
+            let (mut received, last) = receiver.await?;
+            latencies.append(&mut received);
+            elapsed = elapsed.max(last);
+        }
+        latencies.sort();
+        Ok(Report {
+            clients: self.clients,
+            sent,
+            expected: expected * self.clients,
+            elapsed,
+            latencies,
+        })
+    }
+}
+
+/// `send` sends `messages` messages, one every `period`, stamped with
+/// the time since `start`.
+async fn send(
+    stream: Arc<TcpStream>,
+    start: Instant,
+    period: Duration,
+    messages: usize,
+) -> Result<usize> {
+    let mut stream = &*stream;
+    for seq in 0..messages {
+        let at = start + period * seq as u32;
+        let now = Instant::now();
+        if at > now {
+            task::sleep(at - now).await;
+        }
+        let msg = format!("{} {}\n", seq, start.elapsed().as_micros());
+        stream.write_all(msg.as_bytes()).await?;
+    }
+    Ok(messages)
+}
+
+/// `receive` receives up to `expected` messages and returns their
+/// latencies as well as the time of the last message since `start`.
+async fn receive(
+    stream: Arc<TcpStream>,
+    start: Instant,
+    expected: usize,
+    idle: Duration,
+) -> Result<(Vec<Duration>, Duration)> {
+    let mut lines = BufReader::new(&*stream).lines();
+    let mut latencies = Vec::with_capacity(expected);
+    let mut last = Duration::from_secs(0);
+    while latencies.len() < expected {
+        let line = match io::timeout(idle, async { lines.next().await.transpose() }).await {
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+            Err(err) => return Err(err.into()),
+            Ok(None) => break,
+            Ok(Some(line)) => line,
+        };
+        let now = start.elapsed();
+        // The broker sends "from: seq micros" and the server error
+        // messages doesn't have the time stamp.
+        let sent = match line.rsplit(' ').next().map(str::parse::<u64>) {
+            Some(Ok(micros)) => Duration::from_micros(micros),
+            _ => continue,
+        };
+        latencies.push(now.checked_sub(sent).unwrap_or_default());
+        last = now;
+    }
+    Ok((latencies, last))
+}
+
+/// A load generator `Report`.
+pub struct Report {
+    clients: usize,
+    sent: usize,
+    expected: usize,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    /// `sent` returns the number of messages sent by all the clients.
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+    /// `expected` returns the number of messages expected to be received
+    /// by all the clients.
+    pub fn expected(&self) -> usize {
+        self.expected
+    }
+    /// `delivered` returns the number of messages received by all the
+    /// clients.
+    pub fn delivered(&self) -> usize {
+        self.latencies.len()
+    }
+    /// `elapsed` returns the time between the first message sent and
+    /// the last message received.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    /// `throughput` returns the delivered messages per second.
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.delivered() as f64 / secs,
+            _ => 0.0,
+        }
+    }
+    /// `percentile` returns the `p`th percentile of the fan-out latency,
+    /// or `None` in case no message was delivered.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let n = self.latencies.len();
+        if n == 0 {
+            return None;
+        }
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * n as f64).ceil() as usize;
+        Some(self.latencies[rank.max(1) - 1])
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "clients:    {}", self.clients)?;
+        writeln!(f, "sent:       {}", self.sent)?;
+        writeln!(f, "delivered:  {}/{}", self.delivered(), self.expected)?;
+        writeln!(f, "elapsed:    {:?}", self.elapsed)?;
+        writeln!(f, "throughput: {:.1} msg/s", self.throughput())?;
+        for &p in &[50.0, 90.0, 99.0, 100.0] {
+            match self.percentile(p) {
+                Some(latency) => writeln!(f, "{:<11} {:?}", format!("p{}:", p), latency)?,
+                None => writeln!(f, "{:<11} -", format!("p{}:", p))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use std::time::Duration;
+    #[test]
+    fn percentile_and_throughput() {
+        struct Test {
+            name: &'static str,
+            latencies: Vec<u64>,
+            elapsed: Duration,
+            throughput: f64,
+            percentiles: Vec<(f64, Option<u64>)>,
+        }
+        let tests = [
+            Test {
+                name: "empty",
+                latencies: vec![],
+                elapsed: Duration::from_secs(0),
+                throughput: 0.0,
+                percentiles: vec![(0.0, None), (50.0, None), (100.0, None)],
+            },
+            Test {
+                name: "empty over a second",
+                latencies: vec![],
+                elapsed: Duration::from_secs(1),
+                throughput: 0.0,
+                percentiles: vec![(50.0, None)],
+            },
+            Test {
+                name: "single sample",
+                latencies: vec![7],
+                elapsed: Duration::from_millis(500),
+                throughput: 2.0,
+                percentiles: vec![(0.0, Some(7)), (50.0, Some(7)), (100.0, Some(7))],
+            },
+            Test {
+                name: "100 samples",
+                latencies: (1..=100).collect(),
+                elapsed: Duration::from_secs(2),
+                throughput: 50.0,
+                percentiles: vec![
+                    (0.0, Some(1)),
+                    (0.5, Some(1)),
+                    (50.0, Some(50)),
+                    (90.0, Some(90)),
+                    (99.0, Some(99)),
+                    (99.5, Some(100)),
+                    (100.0, Some(100)),
+                    (150.0, Some(100)),
+                ],
+            },
+        ];
+        for t in &tests {
+            let report = Report {
+                clients: 1,
+                sent: t.latencies.len(),
+                expected: t.latencies.len(),
+                elapsed: t.elapsed,
+                latencies: t
+                    .latencies
+                    .iter()
+                    .map(|ms| Duration::from_millis(*ms))
+                    .collect(),
+            };
+            assert_eq!(t.throughput, report.throughput(), "{}", t.name);
+            for (p, want) in &t.percentiles {
+                let want = want.map(Duration::from_millis);
+                assert_eq!(want, report.percentile(*p), "{}: p{}", t.name, p);
+            }
+        }
+    }
+}
@@ -1,10 +1,9 @@
 //! [`Reader`] type
 //!
 //! [`reader`]: struct.Reader.html
-use async_std::io::BufReader;
 use async_std::net::TcpStream;
+use codec_book::{Framed, LinesCodec};
 use futures::channel::mpsc;
-use futures::io::AsyncBufReadExt;
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use std::sync::Arc;
@@ -13,6 +12,9 @@ use super::message::Event;
 use super::Result;
 use super::Sender;
 
+/// Max length of the name and the message lines.
+const MAX_LINE: usize = 4 * 1024;
+
 /// `Reader` polls on the `TcpStream` and send transfer received message
 /// to the [`Broker`].
 ///
@@ -37,7 +39,7 @@ impl Reader {
         let peer = format!("{}@{}", self.name, peer);
         eprintln!("[{}] starting", peer);
         let stream = Arc::new(stream);
-        let mut lines = BufReader::new(&*stream).lines();
+        let mut lines = Framed::new(&*stream, LinesCodec::with_max_length(MAX_LINE));
         let name = match lines.next().await {
             None => return Err(format!("[{}] premature close", peer).into()),
             Some(name) => name?.trim().to_string(),
@@ -52,7 +54,14 @@ impl Reader {
             .await?;
         eprintln!("[{}] started for {}", peer, name);
         while let Some(line) = lines.next().await {
-            let msg = line?.trim().to_string();
+            let msg = match line {
+                Ok(line) => line.trim().to_string(),
+                Err(err) if err.is_recoverable() => {
+                    eprintln!("[{}] dropped message: {}", peer, err);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
             let from = name.clone();
             self.broker.send(Event::Message { from, msg }).await?;
         }
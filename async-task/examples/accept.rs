@@ -1,33 +1,34 @@
-//! TCP server accept timeout example
+//! TCP server accept example with the connection limit
+//!
+//! It keeps accepting across the idle periods, echoes back to each client
+//! on its own task, and drains the clients once the stdin is closed.
 //!
 //! # Examples
 //!
-//! Default 5sec timeout
+//! Up to 2 concurrent clients
 //!
-//! ```
-//! $ cargo run --example listen localhost:8000 5
+//! ```sh
+//! $ cargo run --example accept localhost:8000 2
 //! Finished dev [unoptimized + debuginfo] target(s) in 0.03s
-//! Running `/home/kei/git/books-rs/target/debug/examples/listen 'localhost:8000' 5`
-//! listening on [::1]:8000 with 5s timeout
-//! (TcpStream { watcher: Watcher { entry: Entry { token: Token(2), readers: Mutex { data: Readers { ready:
-//! false, wakers: [] } }, writers: Mutex { data: Writers { ready: true, wakers: [] } } }, source: Some(TcpStream { addr: V6([::1]:8000), peer: V6([::1]:42854), fd: 7 }) } }, V6([::1]:42854))
-//! (TcpStream { watcher: Watcher { entry: Entry { token: Token(2), readers: Mutex { data: Readers { ready:
-//! false, wakers: [] } }, writers: Mutex { data: Writers { ready: true, wakers: [] } } }, source: Some(TcpStream { addr: V6([::1]:8000), peer: V6([::1]:42856), fd: 7 }) } }, V6([::1]:42856))
-//! (TcpStream { watcher: Watcher { entry: Entry { token: Token(2), readers: Mutex { data: Readers { ready:
-//! false, wakers: [] } }, writers: Mutex { data: Writers { ready: true, wakers: [] } } }, source: Some(TcpStream { addr: V6([::1]:8000), peer: V6([::1]:42858), fd: 7 }) } }, V6([::1]:42858))
-//! Error: Custom { kind: TimedOut, error: "future timed out" }
+//! Running `/home/kei/git/books-rs/target/debug/examples/accept 'localhost:8000' 2`
+//! listening on [::1]:8000 up to 2 connections
+//! [[::1]:42854] connected
+//! [[::1]:42856] connected
+//! [[::1]:42854] echoed 6 bytes
+//! [[::1]:42858] connected
+//! [[::1]:42856] echoed 12 bytes
+//! [[::1]:42858] echoed 0 bytes
+//! ^D
+//! served 3 connections
 //! ```
 use core::{
     future::Future,
     task::{Context, Poll},
-    time::Duration,
 };
 use std::env::args;
 
-use async_std::{
-    io,
-    net::{TcpListener, ToSocketAddrs},
-};
+use async_std::{io, net::ToSocketAddrs};
+use async_task_book::acceptor::Acceptor;
 use crossbeam_utils::sync::Parker;
 use pin_utils::pin_mut;
 
@@ -36,25 +37,38 @@ type Result<R> = std::result::Result<R, Box<dyn std::error::Error + Send + Sync
 fn main() -> Result<()> {
     let mut args = args();
     let addr = args.nth(1).unwrap_or(String::from("localhost:8080"));
-    let sec: u64 = args
+    let max: usize = args
         .next()
-        .unwrap_or(String::from("5"))
+        .unwrap_or(String::from("10"))
         .parse()
-        .unwrap_or(5);
-    let timeout = Duration::from_secs(sec);
-    block_on(listen(addr, timeout))
+        .unwrap_or(10);
+    block_on(listen(addr, max))
 }
 
-async fn listen(addr: impl ToSocketAddrs, timeout: Duration) -> Result<()> {
-    let listener = TcpListener::bind(addr).await?;
-    let addr = listener.local_addr()?;
-    println!("listening on {} with {:?} timeout", addr, timeout);
-    loop {
-        match io::timeout(timeout, listener.accept()).await {
-            Ok(client) => println!("{:?}", client),
-            Err(err) => return Err(err.into()),
+async fn listen(addr: impl ToSocketAddrs, max: usize) -> Result<()> {
+    let acceptor = Acceptor::bind(addr).await?.max_connections(max);
+    let addr = acceptor.local_addr()?;
+    println!("listening on {} up to {} connections", addr, max);
+    let handler = |stream, peer| async move {
+        println!("[{}] connected", peer);
+        let reader = &stream;
+        match io::copy(reader, &mut &stream).await {
+            Ok(n) => println!("[{}] echoed {} bytes", peer, n),
+            Err(err) => eprintln!("[{}] {}", peer, err),
         }
-    }
+    };
+    // Shuts down once the stdin is closed.
+    let shutdown = async {
+        let mut buf = String::new();
+        while let Ok(n) = io::stdin().read_line(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    };
+    let served = acceptor.serve(handler, shutdown).await?;
+    println!("served {} connections", served);
+    Ok(())
 }
 
 /// Simplest `block_on` without the reentrance check.
@@ -30,24 +30,17 @@
 //! [client] run
 //! ```
 //!
-//! Timed out example
+//! Timed out example, with the 500ms timeout and three retries backed off
+//! exponentially
 //!
 //! ```sh
-//! $ cargo run --example connect google.com:800
+//! $ cargo run --example connect google.com:800 500 3
 //! Finished dev [unoptimized + debuginfo] target(s) in 0.05s
-//! Running `/home/kei/git/books-rs/target/debug/examples/connect 'google.com:800'`
-//! [client] run
-//! [client] run
-//! [client] run
-//! future timed out
-//! [client] run
-//! [client] run
+//! Running `/home/kei/git/books-rs/target/debug/examples/connect 'google.com:800' 500 3`
 //! [client] run
-//! future timed out
 //! [client] run
 //! [client] run
-//! [client] run
-//! future timed out
+//! Error: Custom { kind: TimedOut, error: "connect to 172.217.0.46:800 timed out" }
 //! ```
 use core::{
     fmt,
@@ -55,16 +48,16 @@ use core::{
     task::{Context, Poll},
     time::Duration,
 };
-use std::{error::Error, panic::catch_unwind, thread};
+use std::error::Error;
 
 use async_std::{io, net::TcpStream};
-use crossbeam_channel::{unbounded, Sender};
+use async_task_book::connector::{Backoff, Connector};
+use async_task_book::executor::{dump, spawn, JoinHandle};
 use crossbeam_utils::sync::Parker;
 use futures::{
-    future::{abortable, pending, FutureExt},
+    future::{pending, FutureExt},
     select,
 };
-use once_cell::sync::Lazy;
 use pin_utils::pin_mut;
 
 type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync + 'static>>;
@@ -81,7 +74,7 @@ fn main() -> Result<()> {
         .next()
         .map(|ms| {
             ms.parse()
-                .map(|ms| Duration::from_millis(ms))
+                .map(Duration::from_millis)
                 .unwrap_or(Duration::from_millis(TIMEOUT))
         })
         .unwrap_or(Duration::from_millis(TIMEOUT));
@@ -89,48 +82,8 @@ fn main() -> Result<()> {
         .next()
         .map(|nr| nr.parse().unwrap_or(RETRY))
         .unwrap_or(RETRY);
-    block_on(supervisor(addr, timeout, retry))
-}
-
-struct Connector {
-    retry: usize,
-    timeout: Duration,
-}
-
-impl Connector {
-    fn new() -> Self {
-        Self {
-            retry: RETRY,
-            timeout: Duration::from_millis(TIMEOUT),
-        }
-    }
-    fn timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = timeout;
-        self
-    }
-    fn retry(mut self, retry: usize) -> Self {
-        // Five retry max.
-        self.retry = retry.max(1).min(5);
-        self
-    }
-    async fn connect(self, addr: String) -> Result<Peer> {
-        let mut timeout = self.timeout;
-        let mut ret = None;
-        for i in 1..=self.retry {
-            timeout *= i as u32;
-            match io::timeout(timeout, TcpStream::connect(&addr)).await {
-                Err(err) => ret = Some(err),
-                Ok(s) => return Ok(Peer::new(s)),
-            }
-        }
-        Err(Box::new(ret.take().unwrap()))
-    }
-}
-
-impl Drop for Connector {
-    fn drop(&mut self) {
-        eprintln!("[connector] dropped");
-    }
+    // Five retry max.
+    block_on(supervisor(addr, timeout, retry.min(5)))
 }
 
 struct Peer {
@@ -143,7 +96,7 @@ impl Peer {
         // dummy tasks.
         let mut tasks = vec![];
         for i in 0..100 {
-            let task = spawn(pending(), format!("task{}", i));
+            let task = spawn(format!("task{}", i), pending());
             tasks.push(task);
         }
         Self { stream, tasks }
@@ -174,8 +127,16 @@ impl Drop for Peer {
 }
 
 async fn supervisor(addr: String, timeout: Duration, retry: usize) -> Result<()> {
-    let (peer, abort) = abortable(Connector::new().timeout(timeout).retry(retry).connect(addr));
-    let peer = spawn(peer, String::from("connector")).fuse();
+    let connector = Connector::new()
+        .timeout(timeout)
+        .retry(retry)
+        .backoff(Backoff::Exponential {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(2),
+        })
+        .jitter(0.2);
+    let (stream, abort) = connector.connect_abortable(addr);
+    let peer = spawn("connector", stream.map(|stream| stream.map(Peer::new))).fuse();
     pin_mut!(peer);
     let mut buf = String::new();
     let cancel = io::stdin();
@@ -187,10 +148,12 @@ async fn supervisor(addr: String, timeout: Duration, retry: usize) -> Result<()>
         }
         peer = peer => match peer {
             None => Err("canceled")?,
-            Some(peer) => peer??,
+            Some(peer) => peer?,
         }
     };
     eprintln!("[{}] connected", peer);
+    // Dumps the dummy tasks of the peer.
+    dump();
     peer.cancel().await;
     Ok(())
 }
@@ -208,34 +171,3 @@ fn block_on<F: Future>(future: F) -> F::Output {
         }
     }
 }
-
-type TaskId = String;
-type Task = async_task::Task<TaskId>;
-type JoinHandle<R> = async_task::JoinHandle<R, TaskId>;
-
-fn spawn<F, R>(future: F, tag: TaskId) -> JoinHandle<F::Output>
-where
-    F: Future<Output = R> + Send + 'static,
-    R: Send + 'static,
-{
-    let schedule = |task| QUEUE.send(task).unwrap();
-    let (task, handle) = async_task::spawn(future, schedule, tag);
-    task.schedule();
-    handle
-}
-
-static QUEUE: Lazy<Sender<Task>> = Lazy::new(|| {
-    let (tx, rx) = unbounded::<Task>();
-    for _ in 0..num_cpus::get().max(1) {
-        let rx = rx.clone();
-        thread::spawn(move || {
-            rx.iter().for_each(|task| {
-                // ignore the task panic.
-                let _ = catch_unwind(|| {
-                    task.run();
-                });
-            });
-        });
-    }
-    tx
-});
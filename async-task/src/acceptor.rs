@@ -0,0 +1,445 @@
+//! TCP [`Acceptor`] with the connection limit
+//!
+//! The acceptor spawns a handler task per connection onto the default
+//! [`executor`], and keeps accepting across the idle periods until the
+//! shutdown future completes.  The concurrent connections are capped by
+//! the semaphore, and the in-flight handlers are drained on shutdown.
+//!
+//! # Examples
+//!
+//! ```
+//! use async_std::{channel, net::TcpStream, prelude::*};
+//! use async_task_book::acceptor::Acceptor;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! async_std::task::block_on(async {
+//!     let acceptor = Acceptor::bind("127.0.0.1:0").await?.max_connections(10);
+//!     let addr = acceptor.local_addr()?;
+//!     let (shutdown, signal) = channel::bounded::<()>(1);
+//!     let server = async_std::task::spawn(acceptor.serve(
+//!         |mut stream, _peer| async move {
+//!             let mut buf = [0; 5];
+//!             if stream.read_exact(&mut buf).await.is_ok() {
+//!                 let _ = stream.write_all(&buf).await;
+//!             }
+//!         },
+//!         async move {
+//!             let _ = signal.recv().await;
+//!         },
+//!     ));
+//!     let mut client = TcpStream::connect(addr).await?;
+//!     client.write_all(b"hello").await?;
+//!     let mut buf = [0; 5];
+//!     client.read_exact(&mut buf).await?;
+//!     assert_eq!(b"hello", &buf);
+//!
+//!     drop(shutdown);
+//!     assert_eq!(1, server.await?);
+//!     Ok(())
+//! })
+//! # }
+//! ```
+//! [`acceptor`]: struct.Acceptor.html
+//! [`executor`]: ../executor/index.html
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use futures::future::{self, Either, FutureExt};
+
+use crate::executor::spawn;
+
+/// Default maximum number of the concurrent connections.
+const MAX_CONNECTIONS: usize = 1024;
+
+/// Pause after the accept error, not to spin on e.g. `EMFILE`.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// TCP acceptor, which spawns a handler task per connection.
+pub struct Acceptor {
+    listener: TcpListener,
+    max: usize,
+}
+
+impl Acceptor {
+    /// `new` creates an acceptor of `listener`.
+    pub fn new(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            max: MAX_CONNECTIONS,
+        }
+    }
+    /// `bind` creates an acceptor listening on `addr`.
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        TcpListener::bind(addr).await.map(Self::new)
+    }
+    /// `max_connections` sets the maximum number of the concurrent
+    /// connections, at least one.
+    ///
+    /// The acceptor stops accepting while the limit is reached.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max = max.max(1);
+        self
+    }
+    /// `local_addr` returns the address the acceptor listens on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// `serve` accepts the connections and spawns `handler` for each of
+    /// them until `shutdown` completes.
+    ///
+    /// It stops accepting on shutdown, waits for the in-flight handlers
+    /// to complete, and returns the number of the accepted connections.
+    /// The accept errors, e.g. running out of the file descriptors, are
+    /// logged and don't stop the acceptor.
+    pub async fn serve<H, F, S>(self, handler: H, shutdown: S) -> io::Result<usize>
+    where
+        H: Fn(TcpStream, SocketAddr) -> F,
+        F: Future<Output = ()> + Send + 'static,
+        S: Future<Output = ()>,
+    {
+        let semaphore = Semaphore::new(self.max);
+        let shutdown = shutdown.fuse();
+        futures::pin_mut!(shutdown);
+        let mut accepted = 0;
+        loop {
+            let accept = async {
+                let permit = semaphore.acquire().await;
+                self.listener
+                    .accept()
+                    .await
+                    .map(|(stream, peer)| (stream, peer, permit))
+            };
+            futures::pin_mut!(accept);
+            let (stream, peer, permit) = match future::select(shutdown.as_mut(), accept).await {
+                Either::Left(_) => break,
+                Either::Right((Ok(conn), _)) => conn,
+                Either::Right((Err(err), _)) => {
+                    eprintln!("[acceptor] {}", err);
+                    async_std::task::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            accepted += 1;
+            let handler = handler(stream, peer);
+            // Detaches the task, which holds the permit until it's done.
+            spawn(format!("conn {}", peer), async move {
+                let _permit = permit;
+                handler.await;
+            });
+        }
+        // Drains the in-flight handlers by taking all the permits back.
+        drop(self.listener);
+        for _ in 0..self.max {
+            semaphore.acquire().await.forget();
+        }
+        Ok(accepted)
+    }
+}
+
+/// Counting semaphore to limit the concurrent connections.
+#[derive(Clone)]
+struct Semaphore(Arc<Mutex<State>>);
+
+struct State {
+    permits: usize,
+    /// Wakers of the pending `Acquire`s, in the arrival order.
+    waiters: BTreeMap<u64, Waker>,
+    next_id: u64,
+}
+
+impl State {
+    /// `wake_one` wakes up the first waiter, in case of the free permit.
+    fn wake_one(&mut self) {
+        if self.permits == 0 {
+            return;
+        }
+        if let Some(id) = self.waiters.keys().next().copied() {
+            // The woken `Acquire` takes the permit, or passes the wake up
+            // on to the next one on drop.
+            self.waiters.remove(&id).unwrap().wake();
+        }
+    }
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self(Arc::new(Mutex::new(State {
+            permits,
+            waiters: BTreeMap::new(),
+            next_id: 0,
+        })))
+    }
+    fn acquire(&self) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            id: None,
+        }
+    }
+    fn release(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.permits += 1;
+        state.wake_one();
+    }
+}
+
+struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    /// Waiter ID, once it's pending.
+    id: Option<u64>,
+}
+
+impl Future for Acquire<'_> {
+    type Output = Permit;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let mut state = semaphore.0.lock().unwrap();
+        if state.permits > 0 {
+            state.permits -= 1;
+            if let Some(id) = self.id.take() {
+                state.waiters.remove(&id);
+            }
+            return Poll::Ready(Permit(Some(semaphore.clone())));
+        }
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                self.id = Some(id);
+                id
+            }
+        };
+        // Keeps a single waker per `Acquire`, across the spurious wake ups.
+        match state.waiters.get(&id) {
+            Some(waker) if waker.will_wake(cx.waker()) => (),
+            _ => {
+                state.waiters.insert(id, cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = self.semaphore.0.lock().unwrap();
+            if state.waiters.remove(&id).is_none() {
+                // Woken up but gone, so wakes up the next one instead.
+                state.wake_one();
+            }
+        }
+    }
+}
+
+/// Permit of the semaphore, released on drop.
+struct Permit(Option<Semaphore>);
+
+impl Permit {
+    /// `forget` keeps the permit acquired.
+    fn forget(mut self) {
+        self.0.take();
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(semaphore) = self.0.take() {
+            semaphore.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Acceptor, Semaphore};
+    use async_std::{channel, net::TcpStream, prelude::*, task};
+    use futures::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    #[test]
+    fn semaphore() {
+        let semaphore = Semaphore::new(2);
+        task::block_on(async {
+            let first = semaphore.acquire().await;
+            let _second = semaphore.acquire().await;
+            assert!(semaphore.acquire().now_or_never().is_none());
+            drop(first);
+            assert!(semaphore.acquire().now_or_never().is_some());
+        });
+    }
+    #[test]
+    fn semaphore_waiters() {
+        use futures::task::{waker, ArcWake};
+        use std::pin::Pin;
+        use std::task::Context;
+        struct Counter(AtomicUsize);
+        impl ArcWake for Counter {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        fn poll(acquire: &mut Option<super::Acquire<'_>>, counter: &Arc<Counter>) -> bool {
+            let waker = waker(counter.clone());
+            let acquire = acquire.as_mut().unwrap();
+            Pin::new(acquire)
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+        }
+        let semaphore = Semaphore::new(2);
+        let first = task::block_on(semaphore.acquire());
+        let second = task::block_on(semaphore.acquire());
+        let counters: Vec<_> = (0..3)
+            .map(|_| Arc::new(Counter(AtomicUsize::new(0))))
+            .collect();
+        let woken = || -> Vec<_> {
+            counters
+                .iter()
+                .map(|c| c.0.load(Ordering::SeqCst))
+                .collect()
+        };
+        let mut waiters: Vec<_> = (0..3).map(|_| Some(semaphore.acquire())).collect();
+        // Spurious polls don't pile up the wakers.
+        for _ in 0..10 {
+            for (waiter, counter) in waiters.iter_mut().zip(&counters) {
+                assert!(!poll(waiter, counter));
+            }
+        }
+        assert_eq!(3, semaphore.0.lock().unwrap().waiters.len());
+        // A single release wakes up a single waiter.
+        drop(first);
+        assert_eq!(vec![1, 0, 0], woken());
+        // The woken one is gone, so the next one is woken up instead.
+        waiters[0] = None;
+        assert_eq!(vec![1, 1, 0], woken());
+        assert!(poll(&mut waiters[1], &counters[1]));
+        drop(second);
+        assert_eq!(vec![1, 1, 1], woken());
+        assert!(poll(&mut waiters[2], &counters[2]));
+    }
+    #[test]
+    fn serve() {
+        struct Test {
+            name: &'static str,
+            max: usize,
+            clients: usize,
+            idle: Duration,
+        }
+        let tests = [
+            Test {
+                name: "single connection",
+                max: 1,
+                clients: 1,
+                idle: Duration::from_millis(0),
+            },
+            Test {
+                name: "capped connections",
+                max: 2,
+                clients: 6,
+                idle: Duration::from_millis(0),
+            },
+            Test {
+                name: "across the idle periods",
+                max: 4,
+                clients: 3,
+                idle: Duration::from_millis(200),
+            },
+        ];
+        for t in &tests {
+            let active = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+            let done = Arc::new(AtomicUsize::new(0));
+            let got = task::block_on(async {
+                let acceptor = Acceptor::bind("127.0.0.1:0")
+                    .await
+                    .unwrap()
+                    .max_connections(t.max);
+                let addr = acceptor.local_addr().unwrap();
+                let (shutdown, signal) = channel::bounded::<()>(1);
+                let handler = {
+                    let (active, peak, done) = (active.clone(), peak.clone(), done.clone());
+                    move |mut stream: TcpStream, _| {
+                        let (active, peak, done) = (active.clone(), peak.clone(), done.clone());
+                        async move {
+                            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            // Keeps the connection for a while.
+                            task::sleep(Duration::from_millis(50)).await;
+                            let _ = stream.write_all(b"bye").await;
+                            active.fetch_sub(1, Ordering::SeqCst);
+                            done.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                };
+                let server = task::spawn(acceptor.serve(handler, async move {
+                    let _ = signal.recv().await;
+                }));
+                let mut clients = Vec::new();
+                for _ in 0..t.clients {
+                    task::sleep(t.idle).await;
+                    clients.push(task::spawn(async move {
+                        let mut stream = TcpStream::connect(addr).await.unwrap();
+                        let mut buf = Vec::new();
+                        stream.read_to_end(&mut buf).await.unwrap();
+                        buf
+                    }));
+                }
+                for client in clients {
+                    assert_eq!(b"bye".to_vec(), client.await, "{}", t.name);
+                }
+                drop(shutdown);
+                server.await.unwrap()
+            });
+            assert_eq!(t.clients, got, "{}", t.name);
+            assert_eq!(t.clients, done.load(Ordering::SeqCst), "{}", t.name);
+            assert!(peak.load(Ordering::SeqCst) <= t.max, "{}", t.name);
+        }
+    }
+    #[test]
+    fn drain_on_shutdown() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let got = task::block_on(async {
+            let acceptor = Acceptor::bind("127.0.0.1:0").await.unwrap();
+            let addr = acceptor.local_addr().unwrap();
+            let (shutdown, signal) = channel::bounded::<()>(1);
+            let (accepted, connected) = channel::unbounded::<()>();
+            let handler = {
+                let done = done.clone();
+                move |_, _| {
+                    let (done, accepted) = (done.clone(), accepted.clone());
+                    async move {
+                        let _ = accepted.send(()).await;
+                        task::sleep(Duration::from_millis(200)).await;
+                        done.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            };
+            let server = task::spawn(acceptor.serve(handler, async move {
+                let _ = signal.recv().await;
+            }));
+            let mut clients = Vec::new();
+            for _ in 0..3 {
+                clients.push(TcpStream::connect(addr).await.unwrap());
+                connected.recv().await.unwrap();
+            }
+            // Shuts it down while the handlers are still in flight.
+            drop(shutdown);
+            let got = server.await.unwrap();
+            // Not accepting anymore.
+            assert!(TcpStream::connect(addr).await.is_err());
+            got
+        });
+        assert_eq!(3, got);
+        assert_eq!(3, done.load(Ordering::SeqCst));
+    }
+}
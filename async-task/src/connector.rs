@@ -0,0 +1,490 @@
+//! TCP [`Connector`] with the retry policy and Happy Eyeballs
+//!
+//! The connector resolves the address and tries all the resolved
+//! addresses, alternating the address families.  It starts the next
+//! attempt once the previous one fails or doesn't complete within the
+//! attempt delay, without cancelling the previous one, and returns the
+//! first connected stream.  The whole round is retried with the
+//! [`Backoff`] delay in between.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use async_std::net::TcpListener;
+//! use async_task_book::connector::{Backoff, Connector};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! async_std::task::block_on(async {
+//!     let listener = TcpListener::bind("127.0.0.1:0").await?;
+//!     let addr = listener.local_addr()?;
+//!     let connector = Connector::new()
+//!         .timeout(Duration::from_secs(1))
+//!         .retry(3)
+//!         .backoff(Backoff::Exponential {
+//!             base: Duration::from_millis(100),
+//!             max: Duration::from_secs(1),
+//!         })
+//!         .jitter(0.1);
+//!     let stream = connector.connect(addr).await?;
+//!     assert_eq!(addr, stream.peer_addr()?);
+//!     Ok(())
+//! })
+//! # }
+//! ```
+//! [`connector`]: struct.Connector.html
+//! [`backoff`]: enum.Backoff.html
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::SocketAddr,
+    time::Duration,
+};
+
+use async_std::{
+    future::timeout,
+    net::{TcpStream, ToSocketAddrs},
+    task::sleep,
+};
+use futures::{
+    future::{self, AbortHandle, Aborted, Either},
+    stream::{FuturesUnordered, StreamExt},
+};
+
+/// Default timeout of each connection attempt.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default number of the rounds.
+const RETRY: usize = 3;
+
+/// Default delay before starting the next attempt, recommended by
+/// [RFC 8305].
+///
+/// [rfc 8305]: https://tools.ietf.org/html/rfc8305#section-5
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Backoff policy between the connection rounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backoff {
+    /// The same delay for every retry.
+    Fixed(Duration),
+    /// The delay grows by the given delay for every retry.
+    Linear(Duration),
+    /// The delay doubles for every retry from `base`, up to `max`.
+    Exponential { base: Duration, max: Duration },
+}
+
+impl Backoff {
+    /// `delay` returns the delay before the `retry`th retry, starting
+    /// from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let retry = retry.max(1);
+        match *self {
+            Self::Fixed(delay) => delay,
+            Self::Linear(delay) => delay.saturating_mul(retry),
+            Self::Exponential { base, max } => 2u32
+                .checked_pow(retry - 1)
+                .and_then(|factor| base.checked_mul(factor))
+                .map_or(max, |delay| delay.min(max)),
+        }
+    }
+}
+
+/// TCP connector with the retry policy and Happy Eyeballs.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    timeout: Duration,
+    retry: usize,
+    backoff: Backoff,
+    jitter: f64,
+    attempt_delay: Duration,
+}
+
+impl Default for Connector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Connector {
+    /// `new` creates a connector with the default configuration.
+    pub fn new() -> Self {
+        Self {
+            timeout: TIMEOUT,
+            retry: RETRY,
+            backoff: Backoff::Exponential {
+                base: Duration::from_millis(100),
+                max: Duration::from_secs(5),
+            },
+            jitter: 0.0,
+            attempt_delay: ATTEMPT_DELAY,
+        }
+    }
+    /// `timeout` sets the timeout of each connection attempt.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// `retry` sets the number of the rounds across all the resolved
+    /// addresses, at least one.
+    pub fn retry(mut self, retry: usize) -> Self {
+        self.retry = retry.max(1);
+        self
+    }
+    /// `backoff` sets the backoff policy between the rounds.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+    /// `jitter` sets the ratio of the random jitter added to or
+    /// subtracted from the backoff delay, between `0.0` and `1.0`.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+    /// `attempt_delay` sets the delay before starting the attempt to the
+    /// next address while the previous attempt is in progress.
+    pub fn attempt_delay(mut self, delay: Duration) -> Self {
+        self.attempt_delay = delay;
+        self
+    }
+    /// `connect` connects to `addr`, and returns the error of the last
+    /// attempt in case all of them fail.
+    pub async fn connect(&self, addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let mut err = None;
+        for round in 0..self.retry {
+            if round > 0 {
+                sleep(self.delay(round as u32)).await;
+            }
+            let attempt = match addr.to_socket_addrs().await {
+                Err(e) => Err(e),
+                Ok(addrs) => self.happy_eyeballs(addrs.collect()).await,
+            };
+            match attempt {
+                Ok(stream) => return Ok(stream),
+                Err(e) => err = Some(e),
+            }
+        }
+        Err(err.unwrap())
+    }
+    /// `connect_abortable` returns the future to connect to `addr`, and
+    /// the handle to abort it.
+    ///
+    /// The aborted future resolves to the `io::ErrorKind::Interrupted`
+    /// error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use async_task_book::connector::Connector;
+    ///
+    /// let (connect, handle) = Connector::new().connect_abortable("127.0.0.1:1");
+    /// handle.abort();
+    /// let err = async_std::task::block_on(connect).unwrap_err();
+    /// assert_eq!(std::io::ErrorKind::Interrupted, err.kind());
+    /// ```
+    pub fn connect_abortable<A>(
+        &self,
+        addr: A,
+    ) -> (impl Future<Output = io::Result<TcpStream>>, AbortHandle)
+    where
+        A: ToSocketAddrs,
+    {
+        let this = self.clone();
+        let (connect, handle) = future::abortable(async move { this.connect(addr).await });
+        let connect = async move {
+            match connect.await {
+                Ok(res) => res,
+                Err(Aborted) => Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "connect aborted",
+                )),
+            }
+        };
+        (connect, handle)
+    }
+    /// `happy_eyeballs` tries all the addresses, starting the next attempt
+    /// once the previous one fails or the attempt delay passes.
+    async fn happy_eyeballs(&self, addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+        let mut addrs = interleave(addrs).into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut err = None;
+        loop {
+            if attempts.is_empty() {
+                match addrs.next() {
+                    Some(addr) => attempts.push(self.attempt(addr)),
+                    None => break,
+                }
+            }
+            let delay = sleep(self.attempt_delay);
+            futures::pin_mut!(delay);
+            match future::select(attempts.next(), delay).await {
+                Either::Left((Some(Ok(stream)), _)) => return Ok(stream),
+                Either::Left((Some(Err(e)), _)) => {
+                    err = Some(e);
+                    // Starts the next one right away.
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(self.attempt(addr));
+                    }
+                }
+                Either::Left((None, _)) => (),
+                Either::Right(_) => {
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(self.attempt(addr));
+                    }
+                }
+            }
+        }
+        Err(err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to connect")
+        }))
+    }
+    async fn attempt(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        match timeout(self.timeout, TcpStream::connect(addr)).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("connect to {} timed out", addr),
+            )),
+        }
+    }
+    /// `delay` returns the backoff delay with the jitter.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.backoff.delay(retry);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        // Random number between 0.0 and 1.0, from the hasher with the
+        // fresh keys.  It's a deliberately cheap source of the jitter, not
+        // a proper RNG, as the keys are seeded once per thread and only
+        // incremented for each `RandomState`.
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        // Scales it to between -1.0 and 1.0.
+        delay.mul_f64(1.0 + self.jitter * (random * 2.0 - 1.0))
+    }
+}
+
+/// `interleave` reorders `addrs` to alternate the address families,
+/// starting from the family of the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut first, mut second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut out = Vec::with_capacity(first.len() + second.len());
+    first.reverse();
+    second.reverse();
+    loop {
+        match (first.pop(), second.pop()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interleave, Backoff, Connector};
+    use async_std::task::block_on;
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::{Duration, Instant};
+    #[test]
+    fn backoff_delay() {
+        struct Test {
+            name: &'static str,
+            backoff: Backoff,
+            want: Vec<u64>,
+        }
+        let tests = [
+            Test {
+                name: "fixed",
+                backoff: Backoff::Fixed(Duration::from_millis(100)),
+                want: vec![100, 100, 100, 100],
+            },
+            Test {
+                name: "linear",
+                backoff: Backoff::Linear(Duration::from_millis(100)),
+                want: vec![100, 200, 300, 400],
+            },
+            Test {
+                name: "exponential",
+                backoff: Backoff::Exponential {
+                    base: Duration::from_millis(100),
+                    max: Duration::from_millis(500),
+                },
+                want: vec![100, 200, 400, 500],
+            },
+        ];
+        for t in &tests {
+            let got: Vec<_> = (1..=4)
+                .map(|retry| t.backoff.delay(retry).as_millis() as u64)
+                .collect();
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+        let exponential = Backoff::Exponential {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        };
+        assert_eq!(Duration::from_secs(60), exponential.delay(1_000));
+    }
+    #[test]
+    fn jitter() {
+        let connector = Connector::new()
+            .backoff(Backoff::Fixed(Duration::from_millis(100)))
+            .jitter(0.5);
+        for _ in 0..100 {
+            let delay = connector.delay(1);
+            assert!(delay >= Duration::from_millis(50), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(150), "{:?}", delay);
+        }
+    }
+    #[test]
+    fn interleave_families() {
+        let v4 = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let v6 = |port| SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port));
+        struct Test {
+            name: &'static str,
+            addrs: Vec<SocketAddr>,
+            want: Vec<SocketAddr>,
+        }
+        let tests = [
+            Test {
+                name: "empty",
+                addrs: vec![],
+                want: vec![],
+            },
+            Test {
+                name: "single family",
+                addrs: vec![v4(1), v4(2)],
+                want: vec![v4(1), v4(2)],
+            },
+            Test {
+                name: "v6 first",
+                addrs: vec![v6(1), v6(2), v6(3), v4(4)],
+                want: vec![v6(1), v4(4), v6(2), v6(3)],
+            },
+            Test {
+                name: "v4 first",
+                addrs: vec![v4(1), v4(2), v6(3), v6(4)],
+                want: vec![v4(1), v6(3), v4(2), v6(4)],
+            },
+        ];
+        for t in &tests {
+            assert_eq!(t.want, interleave(t.addrs.clone()), "{}", t.name);
+        }
+    }
+    /// `refusing` returns the address nobody listens on.
+    fn refusing() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+    /// `stalling` returns the listener which never completes the new
+    /// connection, as its accept queue is full.
+    fn stalling() -> (socket2::Socket, Vec<std::net::TcpStream>) {
+        use socket2::{Domain, Socket, Type};
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        socket
+            .bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into())
+            .unwrap();
+        socket.listen(0).unwrap();
+        let addr = socket.local_addr().unwrap().as_socket().unwrap();
+        // Fills up the accept queue.
+        let mut fillers = Vec::new();
+        loop {
+            let timeout = Duration::from_millis(100);
+            match std::net::TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => fillers.push(stream),
+                Err(_) => return (socket, fillers),
+            }
+        }
+    }
+    #[test]
+    fn connect() {
+        let (stall, _fillers) = stalling();
+        let stalling = stall.local_addr().unwrap().as_socket().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listening = listener.local_addr().unwrap();
+        struct Test {
+            name: &'static str,
+            addrs: Vec<SocketAddr>,
+            retry: usize,
+            ok: bool,
+            min: Duration,
+        }
+        let tests = [
+            Test {
+                name: "listening",
+                addrs: vec![listening],
+                retry: 1,
+                ok: true,
+                min: Duration::from_millis(0),
+            },
+            Test {
+                name: "refusing with the backoff",
+                addrs: vec![refusing()],
+                retry: 3,
+                ok: false,
+                // 100ms + 200ms backoff.
+                min: Duration::from_millis(300),
+            },
+            Test {
+                name: "refusing then listening",
+                addrs: vec![refusing(), listening],
+                retry: 1,
+                ok: true,
+                min: Duration::from_millis(0),
+            },
+            Test {
+                name: "stalling then listening after the attempt delay",
+                addrs: vec![stalling, listening],
+                retry: 1,
+                ok: true,
+                min: Duration::from_millis(250),
+            },
+            Test {
+                name: "stalling timed out",
+                addrs: vec![stalling],
+                retry: 2,
+                ok: false,
+                // 500ms timeout twice with 100ms backoff.
+                min: Duration::from_millis(1_100),
+            },
+        ];
+        for t in &tests {
+            let connector = Connector::new()
+                .timeout(Duration::from_millis(500))
+                .retry(t.retry)
+                .backoff(Backoff::Exponential {
+                    base: Duration::from_millis(100),
+                    max: Duration::from_secs(1),
+                });
+            let start = Instant::now();
+            let got = block_on(connector.connect(&t.addrs[..]));
+            let elapsed = start.elapsed();
+            assert_eq!(t.ok, got.is_ok(), "{}: {:?}", t.name, got);
+            if let Ok(stream) = got {
+                assert_eq!(listening, stream.peer_addr().unwrap(), "{}", t.name);
+            }
+            // Only the lower bound, as the loaded machine can be slow.
+            assert!(elapsed >= t.min, "{}: {:?}", t.name, elapsed);
+        }
+    }
+    #[test]
+    fn abort() {
+        let (stall, _fillers) = stalling();
+        let stalling = stall.local_addr().unwrap().as_socket().unwrap();
+        let connector = Connector::new().timeout(Duration::from_secs(10));
+        let (connect, handle) = connector.connect_abortable(stalling);
+        let connect = async_std::task::spawn(connect);
+        std::thread::sleep(Duration::from_millis(100));
+        handle.abort();
+        let start = Instant::now();
+        let err = block_on(connect).unwrap_err();
+        assert_eq!(std::io::ErrorKind::Interrupted, err.kind());
+        // Well before the 10s timeout.
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
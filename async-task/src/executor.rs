@@ -0,0 +1,420 @@
+//! [Executor] with the registry of the live tasks
+//!
+//! Each task is spawned with a tag, and the executor tracks its spawn
+//! location, [`State`], poll count and the cumulative poll time until the
+//! task is completed or cancelled.  [`dump`] prints them all, which helps
+//! to find the stuck tasks.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::future::pending;
+//!
+//! use async_task_book::executor::Executor;
+//!
+//! let executor = Executor::new(1);
+//! let handles: Vec<_> = (0..3)
+//!     .map(|i| executor.spawn(format!("dummy{}", i), pending::<()>()))
+//!     .collect();
+//! let done = executor.spawn("done", async { 1 + 2 });
+//! assert_eq!(Some(3), futures::executor::block_on(done));
+//!
+//! // Only the pending dummies are alive.
+//! let tasks = executor.tasks();
+//! assert_eq!(3, tasks.len());
+//! assert!(tasks.iter().all(|task| task.tag().starts_with("dummy")));
+//! executor.dump();
+//! # drop(handles);
+//! ```
+//! [executor]: struct.Executor.html
+//! [`state`]: enum.State.html
+//! [`dump`]: struct.Executor.html#method.dump
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    panic::{catch_unwind, Location},
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{unbounded, Sender};
+use once_cell::sync::Lazy;
+
+type Task = async_task::Task<Arc<Entry>>;
+
+/// `JoinHandle` of the task spawned onto the [`Executor`].
+///
+/// It resolves to `None` in case the task is cancelled or panicked.
+///
+/// [`executor`]: struct.Executor.html
+pub type JoinHandle<R> = async_task::JoinHandle<R, Arc<Entry>>;
+
+/// The default executor of [`spawn`], with a worker thread per CPU.
+///
+/// [`spawn`]: fn.spawn.html
+static EXECUTOR: Lazy<Executor> = Lazy::new(|| Executor::new(num_cpus::get().max(1)));
+
+/// `spawn` spawns `future` tagged with `tag` onto the default executor.
+#[track_caller]
+pub fn spawn<F, R>(tag: impl Into<String>, future: F) -> JoinHandle<R>
+where
+    F: Future<Output = R> + Send + 'static,
+    R: Send + 'static,
+{
+    EXECUTOR.spawn(tag, future)
+}
+
+/// `tasks` returns the live tasks of the default executor.
+pub fn tasks() -> Vec<TaskInfo> {
+    EXECUTOR.tasks()
+}
+
+/// `dump` prints the live tasks of the default executor to stderr.
+pub fn dump() {
+    EXECUTOR.dump()
+}
+
+/// Multi-threaded executor, which keeps track of its live tasks.
+///
+/// The worker threads exit once the executor and all of its tasks are
+/// dropped.
+pub struct Executor {
+    queue: Sender<Task>,
+    registry: Arc<Registry>,
+}
+
+impl Executor {
+    /// `new` creates an executor with `threads` worker threads.
+    pub fn new(threads: usize) -> Self {
+        let (queue, rx) = unbounded::<Task>();
+        for _ in 0..threads.max(1) {
+            let rx = rx.clone();
+            thread::spawn(move || rx.iter().for_each(run));
+        }
+        Self {
+            queue,
+            registry: Arc::new(Registry::default()),
+        }
+    }
+    /// `spawn` spawns `future` tagged with `tag`.
+    ///
+    /// The caller location is recorded as the spawn location of the task.
+    #[track_caller]
+    pub fn spawn<F, R>(&self, tag: impl Into<String>, future: F) -> JoinHandle<R>
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let entry = self.registry.insert(tag.into(), Location::caller());
+        let future = Tracked {
+            future,
+            entry: entry.clone(),
+            registry: self.registry.clone(),
+        };
+        let queue = self.queue.clone();
+        let schedule = move |task: Task| {
+            task.tag().set_state(State::Scheduled);
+            // The task is dropped once the worker threads are gone.
+            let _ = queue.send(task);
+        };
+        let (task, handle) = async_task::spawn(future, schedule, entry);
+        task.schedule();
+        handle
+    }
+    /// `tasks` returns the live tasks, ordered by the spawn order.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.registry.snapshot()
+    }
+    /// `dump` prints the live tasks to stderr.
+    pub fn dump(&self) {
+        eprint!("{}", Dump(self.tasks()));
+    }
+}
+
+/// `run` runs the task and records its state, poll count and poll time.
+fn run(task: Task) {
+    let entry = task.tag().clone();
+    entry.set_state(State::Running);
+    let start = Instant::now();
+    // Ignores the task panic, as the `JoinHandle` resolves to `None`.
+    let _ = catch_unwind(|| task.run());
+    let elapsed = start.elapsed().as_nanos() as u64;
+    entry.polls.fetch_add(1, Ordering::Relaxed);
+    entry.poll_nanos.fetch_add(elapsed, Ordering::Relaxed);
+    // Keeps it scheduled in case the task is woken up while running.
+    let _ = entry.state.compare_exchange(
+        State::Running as u8,
+        State::Idle as u8,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+}
+
+/// State of the task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Waiting in the run queue.
+    Scheduled = 0,
+    /// Being polled by the worker thread.
+    Running = 1,
+    /// Waiting to be woken up.
+    Idle = 2,
+}
+
+impl State {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => Self::Scheduled,
+            1 => Self::Running,
+            _ => Self::Idle,
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            Self::Scheduled => "scheduled",
+            Self::Running => "running",
+            Self::Idle => "idle",
+        };
+        f.pad(state)
+    }
+}
+
+/// Registry of the live tasks, keyed by the task ID.
+#[derive(Default)]
+struct Registry {
+    next: AtomicU64,
+    tasks: Mutex<BTreeMap<u64, Arc<Entry>>>,
+}
+
+impl Registry {
+    fn insert(&self, tag: String, location: &'static Location<'static>) -> Arc<Entry> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::new(Entry {
+            id,
+            tag,
+            location,
+            spawned: Instant::now(),
+            state: AtomicU8::new(State::Scheduled as u8),
+            polls: AtomicU64::new(0),
+            poll_nanos: AtomicU64::new(0),
+        });
+        self.tasks.lock().unwrap().insert(id, entry.clone());
+        entry
+    }
+    fn remove(&self, id: u64) {
+        self.tasks.lock().unwrap().remove(&id);
+    }
+    fn snapshot(&self) -> Vec<TaskInfo> {
+        let now = Instant::now();
+        self.tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| TaskInfo {
+                id: entry.id,
+                tag: entry.tag.clone(),
+                location: entry.location,
+                state: State::from_u8(entry.state.load(Ordering::Acquire)),
+                polls: entry.polls.load(Ordering::Relaxed),
+                poll_time: Duration::from_nanos(entry.poll_nanos.load(Ordering::Relaxed)),
+                age: now - entry.spawned,
+            })
+            .collect()
+    }
+}
+
+/// Registry entry of the task, which is also the `async_task` tag.
+pub struct Entry {
+    id: u64,
+    tag: String,
+    location: &'static Location<'static>,
+    spawned: Instant,
+    state: AtomicU8,
+    polls: AtomicU64,
+    poll_nanos: AtomicU64,
+}
+
+impl Entry {
+    /// `tag` returns the tag given to the task.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+    fn set_state(&self, state: State) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+}
+
+/// `Tracked` future removes the task from the registry once it's dropped,
+/// i.e. the task is completed, cancelled or panicked.
+struct Tracked<F> {
+    future: F,
+    entry: Arc<Entry>,
+    registry: Arc<Registry>,
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the inner future is never moved out of the pinned wrapper.
+        unsafe { self.map_unchecked_mut(|this| &mut this.future) }.poll(cx)
+    }
+}
+
+impl<F> Drop for Tracked<F> {
+    fn drop(&mut self) {
+        self.registry.remove(self.entry.id);
+    }
+}
+
+/// Snapshot of the live task returned by [`Executor::tasks`].
+///
+/// [`executor::tasks`]: struct.Executor.html#method.tasks
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    id: u64,
+    tag: String,
+    location: &'static Location<'static>,
+    state: State,
+    polls: u64,
+    poll_time: Duration,
+    age: Duration,
+}
+
+impl TaskInfo {
+    /// `id` returns the task ID, unique within the executor.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    /// `tag` returns the tag given to the task.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+    /// `location` returns the location the task is spawned at.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+    /// `state` returns the state of the task.
+    pub fn state(&self) -> State {
+        self.state
+    }
+    /// `polls` returns the number of the polls.
+    pub fn polls(&self) -> u64 {
+        self.polls
+    }
+    /// `poll_time` returns the cumulative time spent on the polls.
+    pub fn poll_time(&self) -> Duration {
+        self.poll_time
+    }
+    /// `age` returns the time since the task is spawned.
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+}
+
+impl fmt::Display for TaskInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>6} {:<9} {:>8} {:>12?} {:>12?} {} ({})",
+            self.id, self.state, self.polls, self.poll_time, self.age, self.tag, self.location,
+        )
+    }
+}
+
+/// Task dump printed by [`Executor::dump`].
+///
+/// [`executor::dump`]: struct.Executor.html#method.dump
+struct Dump(Vec<TaskInfo>);
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} live task(s)", self.0.len())?;
+        writeln!(
+            f,
+            "{:>6} {:<9} {:>8} {:>12} {:>12} TAG (LOCATION)",
+            "ID", "STATE", "POLLS", "POLL TIME", "AGE"
+        )?;
+        for task in &self.0 {
+            writeln!(f, "{}", task)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dump, Executor, State};
+    use futures::{executor::block_on, future::pending};
+    #[test]
+    fn registry() {
+        struct Test {
+            name: &'static str,
+            pending: usize,
+            completed: usize,
+        }
+        let tests = [
+            Test {
+                name: "no task",
+                pending: 0,
+                completed: 0,
+            },
+            Test {
+                name: "completed tasks only",
+                pending: 0,
+                completed: 10,
+            },
+            Test {
+                name: "pending and completed tasks",
+                pending: 100,
+                completed: 10,
+            },
+        ];
+        for t in &tests {
+            let executor = Executor::new(2);
+            let pending: Vec<_> = (0..t.pending)
+                .map(|i| executor.spawn(format!("pending{}", i), pending::<()>()))
+                .collect();
+            let completed: Vec<_> = (0..t.completed)
+                .map(|i| executor.spawn(format!("completed{}", i), async move { i }))
+                .collect();
+            for (i, handle) in completed.into_iter().enumerate() {
+                assert_eq!(Some(i), block_on(handle), "{}", t.name);
+            }
+            // Waits for the pending tasks to be polled once.
+            while executor.tasks().len() > t.pending
+                || executor.tasks().iter().any(|task| task.polls() == 0)
+            {
+                std::thread::yield_now();
+            }
+
+            let tasks = executor.tasks();
+            assert_eq!(t.pending, tasks.len(), "{}", t.name);
+            for (i, task) in tasks.iter().enumerate() {
+                assert_eq!(format!("pending{}", i), task.tag(), "{}", t.name);
+                assert_eq!(State::Idle, task.state(), "{}", t.name);
+                assert_eq!(1, task.polls(), "{}", t.name);
+                assert_eq!(file!(), task.location().file(), "{}", t.name);
+            }
+            let dump = Dump(tasks).to_string();
+            assert!(
+                dump.starts_with(&format!("{} live task(s)", t.pending)),
+                "{}",
+                t.name
+            );
+
+            // Cancelling the tasks removes them from the registry.
+            pending.into_iter().for_each(|handle| handle.cancel());
+            while !executor.tasks().is_empty() {
+                std::thread::yield_now();
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
 //! [async-task] play ground
 //!
 //! [async-task]: https://lib.rs/crates/async-task
+pub mod acceptor;
+pub mod connector;
+pub mod executor;
 mod task;
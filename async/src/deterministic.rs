@@ -0,0 +1,335 @@
+//! Deterministic single-threaded [`DeterministicExecutor`]
+//!
+//! The executor runs all the tasks on the calling thread and picks the
+//! next task to poll with the seeded pseudo random number generator, so
+//! that the same seed always results in the same scheduling order.
+//! The timers inside the executor run on the virtual time, which jumps
+//! to the next timer deadline once all the tasks are blocked on timers.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use async_book::{timer, DeterministicExecutor};
+//!
+//! let executor = DeterministicExecutor::new(1);
+//! let elapsed = executor.block_on(async {
+//!     let start = timer::now();
+//!     // Returns instantly with the virtual time.
+//!     timer::sleep(Duration::from_secs(3_600)).await;
+//!     timer::now() - start
+//! });
+//! assert_eq!(Duration::from_secs(3_600), elapsed);
+//! ```
+//! [`deterministicexecutor`]: struct.DeterministicExecutor.html
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::Context,
+    time::Instant,
+};
+
+use futures::{
+    future::{FutureExt, LocalBoxFuture},
+    task::{waker_ref, ArcWake},
+};
+
+use super::pool::{join, JoinHandle};
+use super::task::{self, Locals};
+use super::timer::Driver;
+
+/// Deterministic single-threaded executor with the virtual time.
+///
+/// The `DeterministicExecutor` is cheap to clone, so that the tasks can
+/// spawn other tasks on the same executor.
+#[derive(Clone)]
+pub struct DeterministicExecutor {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    /// Pending tasks, indexed by the task ID.
+    tasks: RefCell<HashMap<usize, Task>>,
+
+    /// IDs of the tasks ready to be polled.
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+
+    next_id: Cell<usize>,
+
+    /// xorshift64* state of the scheduler.
+    rng: Cell<u64>,
+
+    /// Timer driver on the virtual time.
+    driver: Arc<Driver>,
+}
+
+impl DeterministicExecutor {
+    /// `new` creates a new executor with the scheduler `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                tasks: RefCell::new(HashMap::new()),
+                ready: Arc::new(Mutex::new(BTreeSet::new())),
+                next_id: Cell::new(0),
+                // xorshift doesn't work with the zero state.
+                rng: Cell::new(seed ^ 0x9e37_79b9_7f4a_7c15),
+                driver: Driver::new_virtual(),
+            }),
+        }
+    }
+    /// `spawn` spawns a future onto the executor and returns the
+    /// [`JoinHandle`] to await its output.
+    ///
+    /// The future doesn't need to be `Send`, as it never leaves the
+    /// current thread.
+    ///
+    /// [`joinhandle`]: struct.JoinHandle.html
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (future, handle) = join(future);
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        let task = Task {
+            info: task::Task::new(None),
+            locals: Locals::default(),
+            future: future.boxed_local(),
+        };
+        self.inner.tasks.borrow_mut().insert(id, task);
+        self.inner.ready.lock().unwrap().insert(id);
+        handle
+    }
+    /// `now` returns the current virtual time of the executor.
+    pub fn now(&self) -> Instant {
+        self.inner.driver.now()
+    }
+    /// `run_until_stalled` polls the tasks until none of them is ready
+    /// to run, without advancing the virtual time.
+    pub fn run_until_stalled(&self) {
+        Driver::enter(&self.inner.driver, || while self.poll_one() {});
+    }
+    /// `run` runs the tasks until all of them are completed, advancing the
+    /// virtual time whenever all of them are blocked on timers.
+    ///
+    /// It returns early in case the remaining tasks are blocked on
+    /// something other than timers.
+    pub fn run(&self) {
+        loop {
+            self.run_until_stalled();
+            if self.inner.tasks.borrow().is_empty() || !self.inner.driver.advance() {
+                return;
+            }
+        }
+    }
+    /// `block_on` spawns `future` and runs the executor until it completes.
+    ///
+    /// # Panics
+    ///
+    /// It panics if `future` is blocked on something other than timers.
+    pub fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let output = Rc::new(RefCell::new(None));
+        let slot = output.clone();
+        self.spawn(async move { *slot.borrow_mut() = Some(future.await) });
+        loop {
+            self.run_until_stalled();
+            if let Some(output) = output.borrow_mut().take() {
+                return output;
+            }
+            assert!(self.inner.driver.advance(), "future stalled");
+        }
+    }
+    /// `poll_one` polls one of the ready tasks and returns `false` if
+    /// there is no ready task.
+    fn poll_one(&self) -> bool {
+        let id = {
+            let mut ready = self.inner.ready.lock().unwrap();
+            if ready.is_empty() {
+                return false;
+            }
+            let i = (self.next_random() % ready.len() as u64) as usize;
+            let id = *ready.iter().nth(i).unwrap();
+            ready.remove(&id);
+            id
+        };
+        // Take it out so that the task can spawn other tasks.
+        let mut task = match self.inner.tasks.borrow_mut().remove(&id) {
+            None => return true,
+            Some(task) => task,
+        };
+        let waker = Arc::new(TaskWaker {
+            id,
+            ready: self.inner.ready.clone(),
+        });
+        let waker = waker_ref(&waker);
+        let context = &mut Context::from_waker(&waker);
+        let future = &mut task.future;
+        if task::enter(&task.info, &task.locals, || future.as_mut().poll(context)).is_pending() {
+            self.inner.tasks.borrow_mut().insert(id, task);
+        }
+        true
+    }
+    fn next_random(&self) -> u64 {
+        let mut x = self.inner.rng.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.inner.rng.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+struct Task {
+    info: task::Task,
+    locals: Locals,
+    future: LocalBoxFuture<'static, ()>,
+}
+
+/// Waker to put the task back to the ready set.
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.ready.lock().unwrap().insert(arc_self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeterministicExecutor;
+    use crate::timer::{self, interval, sleep, timeout};
+    use futures::future::{join_all, poll_fn, FutureExt};
+    use futures::stream::StreamExt;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::task::Poll;
+    use std::time::Duration;
+
+    /// `trace` runs the tasks which yield a few times and returns the
+    /// order of the polls.
+    fn trace(seed: u64) -> Vec<usize> {
+        let executor = DeterministicExecutor::new(seed);
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        for id in 0..8 {
+            let trace = trace.clone();
+            let mut yields = 3;
+            executor.spawn(poll_fn(move |cx| {
+                trace.borrow_mut().push(id);
+                if yields == 0 {
+                    return Poll::Ready(());
+                }
+                yields -= 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }));
+        }
+        executor.run();
+        let trace = trace.borrow().clone();
+        trace
+    }
+    #[test]
+    fn seeded_scheduler() {
+        struct Test {
+            name: &'static str,
+            seeds: (u64, u64),
+            same: bool,
+        }
+        let tests = [
+            Test {
+                name: "same zero seed",
+                seeds: (0, 0),
+                same: true,
+            },
+            Test {
+                name: "same non-zero seed",
+                seeds: (42, 42),
+                same: true,
+            },
+            Test {
+                name: "different seeds",
+                seeds: (1, 2),
+                same: false,
+            },
+        ];
+        for t in &tests {
+            let (a, b) = (trace(t.seeds.0), trace(t.seeds.1));
+            assert_eq!(32, a.len(), "{}", t.name);
+            assert_eq!(t.same, a == b, "{}", t.name);
+        }
+    }
+    #[test]
+    fn run_until_stalled() {
+        let executor = DeterministicExecutor::new(0);
+        let start = executor.now();
+        let done = executor.spawn(async { 1 });
+        let timer = executor.spawn(async { sleep(Duration::from_secs(1)).await });
+        executor.run_until_stalled();
+        assert_eq!(start, executor.now());
+        assert_eq!(Some(1), done.now_or_never().map(Result::unwrap));
+        executor.run();
+        assert_eq!(start + Duration::from_secs(1), executor.now());
+        assert_eq!(Some(()), timer.now_or_never().map(Result::unwrap));
+    }
+    #[test]
+    fn virtual_time() {
+        let executor = DeterministicExecutor::new(0);
+        let start = executor.now();
+        let sleeps: Vec<_> = [30u64, 10, 20]
+            .iter()
+            .map(|secs| {
+                executor.spawn(async move {
+                    sleep(Duration::from_secs(*secs)).await;
+                    timer::now()
+                })
+            })
+            .collect();
+        let woken: Vec<_> = executor
+            .block_on(join_all(sleeps))
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let want: Vec<_> = [30u64, 10, 20]
+            .iter()
+            .map(|secs| start + Duration::from_secs(*secs))
+            .collect();
+        assert_eq!(want, woken);
+        assert_eq!(start + Duration::from_secs(30), executor.now());
+    }
+    #[test]
+    fn virtual_interval_and_timeout() {
+        let executor = DeterministicExecutor::new(0);
+        let start = executor.now();
+        let ticks = executor.block_on(async {
+            interval(Duration::from_secs(60))
+                .take(3)
+                .collect::<Vec<_>>()
+                .await
+        });
+        assert_eq!(
+            vec![
+                start + Duration::from_secs(60),
+                start + Duration::from_secs(120),
+                start + Duration::from_secs(180)
+            ],
+            ticks
+        );
+        let got = executor.block_on(async {
+            let slow = sleep(Duration::from_secs(3_600));
+            timeout(Duration::from_secs(1), slow).await
+        });
+        assert!(got.is_err());
+        assert_eq!(start + Duration::from_secs(181), executor.now());
+    }
+}
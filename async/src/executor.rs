@@ -28,87 +28,137 @@
 //! // Run the executor until the task queue is empty.
 //! executor.run();
 //! ```
-use std::{
-    future::Future,
-    sync::mpsc::{self, Receiver, SyncSender},
-    sync::{Arc, Mutex},
-    task::{Context, Poll},
-};
+use std::{future::Future, sync::Arc};
 
-use futures::{
-    future::{BoxFuture, FutureExt},
-    task::{waker_ref, ArcWake},
-};
+use super::pool::{JoinHandle, Pool, ThreadPoolExecutor};
 
 /// Create a executor and spawner, so that you can spawn a task and places
 /// it on a executor.
+///
+/// The executor runs the tasks on the [`ThreadPoolExecutor`] with one
+/// worker thread per CPU.
+///
+/// [`threadpoolexecutor`]: ../pool/struct.ThreadPoolExecutor.html
 pub fn new_executor_and_spawner() -> (Executor, Spawner) {
-    // Maximum number of tasks to allow queuing in the channel at once.
-    // This is just to make `sync_channel` happy, and wouldn't be a real executor.
-    const MAX_QUEUED_TASKS: usize = 10_000;
-    let (tx, rx) = mpsc::sync_channel(MAX_QUEUED_TASKS);
-    (Executor { queue: rx }, Spawner { queue: tx })
+    let executor = ThreadPoolExecutor::new(num_cpus::get());
+    let spawner = Spawner::new(executor.pool().clone());
+    (Executor { executor }, spawner)
 }
 
-/// Task executor that receives tasks off of a channel
-/// and runs them.
+/// Task executor that runs the spawned tasks on the worker threads.
 pub struct Executor {
-    /// Run queue endpoint to wait for the next task to execute.
-    queue: Receiver<Arc<Task>>,
+    executor: ThreadPoolExecutor,
 }
 
 impl Executor {
+    /// `run` blocks until all the [`Spawner`]s are dropped and all the
+    /// tasks are completed.
+    ///
+    /// [`spawner`]: struct.Spawner.html
     pub fn run(&self) {
-        while let Ok(task) = self.queue.recv() {
-            let mut future_slot = task.future.lock().unwrap();
-            if let Some(mut future) = future_slot.take() {
-                let waker = waker_ref(&task);
-                let context = &mut Context::from_waker(&*waker);
-                if let Poll::Pending = future.as_mut().poll(context) {
-                    *future_slot = Some(future);
-                }
-            }
-        }
+        self.executor.pool().wait_idle();
+    }
+    /// `run_until_stalled` blocks until none of the tasks is ready to run,
+    /// e.g. all the tasks are completed or waiting for the wake up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use async_book::{new_executor_and_spawner, TimerFuture};
+    ///
+    /// let (executor, spawner) = new_executor_and_spawner();
+    /// let handle = spawner.spawn(async { 1 + 2 });
+    /// spawner.spawn(TimerFuture::new(Duration::from_secs(60)));
+    ///
+    /// // It returns even though the timer task is still alive.
+    /// executor.run_until_stalled();
+    /// assert_eq!(3, futures::executor::block_on(handle).unwrap());
+    /// ```
+    pub fn run_until_stalled(&self) {
+        self.executor.pool().wait_stalled();
     }
 }
 
-/// `Spawner` spawns new futures onto the task channel.
-#[derive(Clone)]
+/// `Spawner` spawns new futures onto the executor.
 pub struct Spawner {
-    queue: SyncSender<Arc<Task>>,
+    pool: Arc<Pool>,
 }
 
 impl Spawner {
-    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
-        let future = future.boxed();
-        let task = Arc::new(Task {
-            future: Mutex::new(Some(future)),
-            queue: self.queue.clone(),
-        });
-        self.queue.send(task).expect("too many task queued");
+    fn new(pool: Arc<Pool>) -> Self {
+        pool.add_spawner();
+        Self { pool }
+    }
+    /// `spawn` spawns a future onto the executor and returns the
+    /// [`JoinHandle`] to await its output.
+    ///
+    /// [`joinhandle`]: ../pool/struct.JoinHandle.html
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T> {
+        self.pool.spawn(future, None)
+    }
+    /// `builder` returns the [`Builder`] to configure the task before
+    /// spawning it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use async_book::{new_executor_and_spawner, task};
+    ///
+    /// let (executor, spawner) = new_executor_and_spawner();
+    /// spawner.builder().name("worker").spawn(async {
+    ///     assert_eq!(Some("worker"), task::current().name());
+    /// });
+    /// drop(spawner);
+    /// executor.run();
+    /// ```
+    /// [`builder`]: struct.Builder.html
+    pub fn builder(&self) -> Builder<'_> {
+        Builder {
+            spawner: self,
+            name: None,
+        }
     }
 }
 
-/// A future that can reschedule itself to be polled by [`Executor`].
+/// `Builder` to configure the task, returned by [`Spawner::builder`].
 ///
-/// [`executor`]: struct.Executor.html
-struct Task {
-    /// In-progress future that should be pushed to completion.
+/// [`spawner::builder`]: struct.Spawner.html#method.builder
+pub struct Builder<'a> {
+    spawner: &'a Spawner,
+    name: Option<String>,
+}
+
+impl Builder<'_> {
+    /// `name` names the task, which is reported on panic and is available
+    /// through [`task::current`].
     ///
-    /// The `Mutex` is not necessary for correctness, since we only have
-    /// one thread executing tasks at once.  However, Rust isn't smart enough
-    /// to know that `future` is only mutated from one thread, so we need to
-    /// use the `Mutex` to prove thread-safety.  A production executor would
-    /// not need this, and could use `UnsafeCell` instead.
-    future: Mutex<Option<BoxFuture<'static, ()>>>,
+    /// [`task::current`]: ../task/fn.current.html
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    /// `spawn` spawns a future onto the executor with the configuration.
+    pub fn spawn<T: Send + 'static>(
+        self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T> {
+        self.spawner.pool.spawn(future, self.name)
+    }
+}
 
-    /// Run queue tx to re-submit to the [`Executor`].
-    queue: SyncSender<Arc<Task>>,
+impl Clone for Spawner {
+    fn clone(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
 }
 
-impl ArcWake for Task {
-    fn wake_by_ref(arc_self: &Arc<Self>) {
-        let cloned = arc_self.clone();
-        arc_self.queue.send(cloned).expect("too many tasks queued");
+impl Drop for Spawner {
+    fn drop(&mut self) {
+        self.pool.remove_spawner();
     }
 }
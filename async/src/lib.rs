@@ -1,11 +1,16 @@
 //! [Async Book] examples
 //!
 //! [async book]: https://rust-lang.github.io/async-book/
+mod deterministic;
 mod executor;
 mod future;
-mod timer;
+mod pool;
+pub mod task;
+pub mod timer;
 mod unpin;
 
-pub use executor::new_executor_and_spawner;
+pub use deterministic::DeterministicExecutor;
+pub use executor::{new_executor_and_spawner, Builder, Executor, Spawner};
+pub use pool::{JoinError, JoinHandle, ThreadPoolExecutor};
 pub use timer::TimerFuture;
 pub use unpin::execute_unpin_future;
@@ -0,0 +1,592 @@
+//! Multi-threaded work-stealing [`ThreadPoolExecutor`]
+//!
+//! Each worker thread has its own run queue and steals tasks from the
+//! global injector queue, or from the other worker's run queues, once
+//! its own run queue runs dry.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::executor::block_on;
+//!
+//! use async_book::ThreadPoolExecutor;
+//!
+//! let pool = ThreadPoolExecutor::new(4);
+//! let handles: Vec<_> = (0..10u64)
+//!     .map(|i| pool.spawn(async move { i * 2 }))
+//!     .collect();
+//! let sum: u64 = handles.into_iter().map(|h| block_on(h).unwrap()).sum();
+//! assert_eq!(90, sum);
+//! ```
+//! [`threadpoolexecutor`]: struct.ThreadPoolExecutor.html
+use std::{
+    any::Any,
+    cell::RefCell,
+    error::Error,
+    fmt,
+    future::Future,
+    iter,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll},
+    thread,
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use futures::{
+    channel::oneshot,
+    future::{self, AbortHandle, Aborted, BoxFuture, FutureExt},
+    task::{waker_ref, ArcWake},
+};
+
+use super::task::{self, Locals};
+
+/// Pool counter, to tell which pool the worker thread belongs to.
+static POOLS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The run queue of the current worker thread, with its pool ID.
+    static WORKER: RefCell<Option<(usize, Worker<Arc<Task>>)>> = const { RefCell::new(None) };
+}
+
+/// Multi-threaded work-stealing executor.
+///
+/// Dropping the `ThreadPoolExecutor` stops and joins all the worker
+/// threads, and drops the tasks still in the run queues.
+pub struct ThreadPoolExecutor {
+    pool: Arc<Pool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl ThreadPoolExecutor {
+    /// `new` creates a new executor with `threads` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// It panics if `threads` is zero.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "thread pool needs at least one thread");
+        let workers: Vec<_> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let pool = Arc::new(Pool {
+            id: POOLS.fetch_add(1, Ordering::Relaxed),
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            tasks: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            spawners: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            work: Condvar::new(),
+            idle: Condvar::new(),
+        });
+        let threads = workers
+            .into_iter()
+            .enumerate()
+            .map(|(i, worker)| {
+                let pool = pool.clone();
+                thread::Builder::new()
+                    .name(format!("async-book-worker-{}", i))
+                    .spawn(move || pool.run(worker))
+                    .expect("cannot spawn a worker thread")
+            })
+            .collect();
+        Self { pool, threads }
+    }
+    /// `threads` returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.threads.len()
+    }
+    /// `spawn` spawns a future onto the executor and returns the
+    /// [`JoinHandle`] to await its output.
+    ///
+    /// Dropping the `JoinHandle` detaches the task.
+    ///
+    /// [`joinhandle`]: struct.JoinHandle.html
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.pool.spawn(future, None)
+    }
+    pub(crate) fn pool(&self) -> &Arc<Pool> {
+        &self.pool
+    }
+}
+
+impl Drop for ThreadPoolExecutor {
+    fn drop(&mut self) {
+        self.pool.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _guard = self.pool.lock.lock().unwrap();
+            self.pool.work.notify_all();
+        }
+        // The last reference to the executor could be dropped by one
+        // of its own tasks.
+        let current = thread::current().id();
+        for thread in self.threads.drain(..) {
+            if thread.thread().id() != current {
+                let _ = thread.join();
+            }
+        }
+        // Drop the tasks left in the injector queue.
+        while !self.pool.injector.steal().is_empty() {}
+    }
+}
+
+/// `JoinHandle` to await the output of the spawned task.
+///
+/// It resolves to [`JoinError`] in case the task panicked, or it's
+/// cancelled or dropped before its completion, e.g. the executor is
+/// dropped.
+///
+/// # Examples
+///
+/// ```
+/// use futures::executor::block_on;
+///
+/// use async_book::ThreadPoolExecutor;
+///
+/// let pool = ThreadPoolExecutor::new(1);
+/// let panicked = pool.spawn(async { panic!("oops") });
+/// let cancelled = pool.spawn(futures::future::pending::<()>());
+/// cancelled.cancel();
+///
+/// assert!(block_on(panicked).unwrap_err().is_panic());
+/// assert!(block_on(cancelled).unwrap_err().is_cancelled());
+/// // The executor keeps running the other tasks.
+/// assert_eq!(3, block_on(pool.spawn(async { 1 + 2 })).unwrap());
+/// ```
+/// [`joinerror`]: struct.JoinError.html
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<Result<T, JoinError>>,
+    abort: AbortHandle,
+}
+
+impl<T> JoinHandle<T> {
+    /// `cancel` cancels the task.
+    ///
+    /// The task drops its future on the next poll, which runs the
+    /// future's destructors, and the `JoinHandle` resolves to the
+    /// cancelled [`JoinError`].  It does nothing in case the task has
+    /// already been completed.
+    ///
+    /// [`joinerror`]: struct.JoinError.html
+    pub fn cancel(&self) {
+        self.abort.abort();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(output)) => Poll::Ready(output),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::Cancelled)),
+        }
+    }
+}
+
+/// `join` wraps `future` to catch its panic and to make it cancellable,
+/// and returns the wrapped future to run as a task and its [`JoinHandle`].
+///
+/// [`joinhandle`]: struct.JoinHandle.html
+pub(crate) fn join<F: Future>(future: F) -> (impl Future<Output = ()>, JoinHandle<F::Output>) {
+    let (tx, rx) = oneshot::channel();
+    let (future, abort) = future::abortable(AssertUnwindSafe(future).catch_unwind());
+    let future = async move {
+        let output = match future.await {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(payload)) => {
+                eprintln!("{} panicked", task::current());
+                Err(JoinError::Panic(payload))
+            }
+            Err(Aborted) => Err(JoinError::Cancelled),
+        };
+        // The receiver may have been dropped to detach the task.
+        let _ = tx.send(output);
+    };
+    (future, JoinHandle { rx, abort })
+}
+
+/// `JoinError` returned by the [`JoinHandle`].
+///
+/// [`joinhandle`]: struct.JoinHandle.html
+pub enum JoinError {
+    /// The task was cancelled or dropped before its completion.
+    Cancelled,
+    /// The task panicked, with the panic payload.
+    Panic(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    /// `is_cancelled` returns `true` if the task was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Self::Cancelled)
+    }
+    /// `is_panic` returns `true` if the task panicked.
+    pub fn is_panic(&self) -> bool {
+        matches!(self, Self::Panic(_))
+    }
+    /// `into_panic` returns the panic payload, so that the caller can
+    /// resume the panic with `std::panic::resume_unwind`.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the task didn't panic.
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            Self::Panic(payload) => payload,
+            Self::Cancelled => panic!("`JoinError` is not a panic"),
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "JoinError::Cancelled"),
+            Self::Panic(_) => write!(f, "JoinError::Panic(..)"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "task was cancelled"),
+            Self::Panic(payload) => match payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            {
+                Some(msg) => write!(f, "task panicked: {}", msg),
+                None => write!(f, "task panicked"),
+            },
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+/// State shared among the executor, the worker threads and the tasks.
+pub(crate) struct Pool {
+    /// Pool ID, to find the worker thread's own run queue.
+    id: usize,
+
+    /// Global run queue for the tasks scheduled outside of the workers.
+    injector: Injector<Arc<Task>>,
+
+    /// Worker's run queue endpoints to steal tasks from.
+    stealers: Vec<Stealer<Arc<Task>>>,
+
+    /// Number of live tasks.
+    tasks: AtomicUsize,
+
+    /// Number of tasks either in the run queues or being polled.
+    active: AtomicUsize,
+
+    /// Number of live [`Spawner`]s.
+    ///
+    /// [`spawner`]: ../executor/struct.Spawner.html
+    spawners: AtomicUsize,
+
+    /// Set when the executor is dropped.
+    shutdown: AtomicBool,
+
+    /// Lock for the `work` and `idle` condition variables.
+    lock: Mutex<()>,
+
+    /// Signaled when the new task is scheduled.
+    work: Condvar,
+
+    /// Signaled when the last task or the last `Spawner` is dropped, or
+    /// the last active task is polled.
+    idle: Condvar,
+}
+
+impl Pool {
+    pub(crate) fn spawn<F>(
+        self: &Arc<Self>,
+        future: F,
+        name: Option<String>,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (future, handle) = join(future);
+        self.tasks.fetch_add(1, Ordering::SeqCst);
+        let task = Arc::new(Task {
+            info: task::Task::new(name),
+            locals: Mutex::new(Locals::default()),
+            future: Mutex::new(Some(future.boxed())),
+            scheduled: AtomicBool::new(true),
+            pool: self.clone(),
+        });
+        self.schedule(task);
+        handle
+    }
+    pub(crate) fn add_spawner(&self) {
+        self.spawners.fetch_add(1, Ordering::SeqCst);
+    }
+    pub(crate) fn remove_spawner(&self) {
+        if self.spawners.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let _guard = self.lock.lock().unwrap();
+            self.idle.notify_all();
+        }
+    }
+    /// `wait_idle` blocks until all the tasks and `Spawner`s are dropped.
+    pub(crate) fn wait_idle(&self) {
+        let mut guard = self.lock.lock().unwrap();
+        while self.tasks.load(Ordering::SeqCst) != 0 || self.spawners.load(Ordering::SeqCst) != 0 {
+            guard = self.idle.wait(guard).unwrap();
+        }
+    }
+    /// `wait_stalled` blocks until none of the tasks is ready to run.
+    pub(crate) fn wait_stalled(&self) {
+        let mut guard = self.lock.lock().unwrap();
+        while self.active.load(Ordering::SeqCst) != 0 {
+            guard = self.idle.wait(guard).unwrap();
+        }
+    }
+    fn schedule(&self, task: Arc<Task>) {
+        if self.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        // Push it to the current worker's run queue, if any.
+        let mut task = Some(task);
+        let _ = WORKER.try_with(|worker| {
+            if let Ok(Some((id, local))) = worker.try_borrow().as_deref() {
+                if *id == self.id {
+                    local.push(task.take().unwrap());
+                }
+            }
+        });
+        if let Some(task) = task {
+            self.injector.push(task);
+        }
+        let _guard = self.lock.lock().unwrap();
+        self.work.notify_one();
+    }
+    fn run(self: Arc<Self>, worker: Worker<Arc<Task>>) {
+        WORKER.with(|local| *local.borrow_mut() = Some((self.id, worker)));
+        while let Some(task) = self.next() {
+            task.run();
+            if self.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                let _guard = self.lock.lock().unwrap();
+                self.idle.notify_all();
+            }
+        }
+        // Drop the tasks left in the worker's run queue.
+        let worker = WORKER.with(|local| local.borrow_mut().take());
+        drop(worker);
+    }
+    /// `next` returns the next task to run, or `None` on shutdown.
+    fn next(&self) -> Option<Arc<Task>> {
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(task) = self.find() {
+                return Some(task);
+            }
+            let guard = self.lock.lock().unwrap();
+            if !self.shutdown.load(Ordering::SeqCst) && self.is_empty() {
+                drop(self.work.wait(guard).unwrap());
+            }
+        }
+    }
+    fn find(&self) -> Option<Arc<Task>> {
+        WORKER.with(|worker| {
+            let worker = worker.borrow();
+            let (_, local) = worker.as_ref()?;
+            local.pop().or_else(|| {
+                iter::repeat_with(|| {
+                    self.injector
+                        .steal_batch_and_pop(local)
+                        .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+                })
+                .find(|s| !s.is_retry())
+                .and_then(Steal::success)
+            })
+        })
+    }
+    fn is_empty(&self) -> bool {
+        self.injector.is_empty() && self.stealers.iter().all(Stealer::is_empty)
+    }
+    fn release(&self) {
+        if self.tasks.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let _guard = self.lock.lock().unwrap();
+            self.idle.notify_all();
+        }
+    }
+}
+
+/// A future that can reschedule itself to be polled by the worker thread.
+struct Task {
+    /// Task ID and the name.
+    info: task::Task,
+
+    /// Task-local values, accessed only by the worker polling the task.
+    locals: Mutex<Locals>,
+
+    /// In-progress future that should be pushed to completion.
+    future: Mutex<Option<BoxFuture<'static, ()>>>,
+
+    /// Set while the task is in the run queue, to avoid queuing it twice.
+    scheduled: AtomicBool,
+
+    /// Pool to re-submit the task to.
+    pool: Arc<Pool>,
+}
+
+impl Task {
+    fn run(self: Arc<Self>) {
+        self.scheduled.store(false, Ordering::SeqCst);
+        let mut future_slot = self.future.lock().unwrap();
+        if let Some(mut future) = future_slot.take() {
+            let waker = waker_ref(&self);
+            let context = &mut Context::from_waker(&waker);
+            let locals = self.locals.lock().unwrap();
+            // The future never panics, as `join` catches the panic.
+            if task::enter(&self.info, &locals, || future.as_mut().poll(context)).is_pending() {
+                *future_slot = Some(future);
+            }
+        }
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if !arc_self.scheduled.swap(true, Ordering::SeqCst) {
+            arc_self.pool.schedule(arc_self.clone());
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.pool.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadPoolExecutor;
+    use futures::executor::block_on;
+    use futures::future::join_all;
+    #[test]
+    fn spawn_and_join() {
+        struct Test {
+            name: &'static str,
+            threads: usize,
+            tasks: u64,
+        }
+        let tests = [
+            Test {
+                name: "single thread with single task",
+                threads: 1,
+                tasks: 1,
+            },
+            Test {
+                name: "single thread with 100 tasks",
+                threads: 1,
+                tasks: 100,
+            },
+            Test {
+                name: "four threads with 100 tasks",
+                threads: 4,
+                tasks: 100,
+            },
+            Test {
+                name: "four threads with 20,000 tasks",
+                threads: 4,
+                tasks: 20_000,
+            },
+        ];
+        for t in &tests {
+            let pool = ThreadPoolExecutor::new(t.threads);
+            assert_eq!(t.threads, pool.threads(), "{}", t.name);
+            let handles: Vec<_> = (0..t.tasks).map(|i| pool.spawn(async move { i })).collect();
+            let got: u64 = block_on(join_all(handles))
+                .into_iter()
+                .map(Result::unwrap)
+                .sum();
+            assert_eq!(t.tasks * (t.tasks - 1) / 2, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn spawn_inside_task() {
+        let pool = std::sync::Arc::new(ThreadPoolExecutor::new(4));
+        let inner = pool.clone();
+        let handle = pool.spawn(async move {
+            let handles: Vec<_> = (0..1_000u64)
+                .map(|i| inner.spawn(async move { i }))
+                .collect();
+            join_all(handles)
+                .await
+                .into_iter()
+                .map(Result::unwrap)
+                .sum::<u64>()
+        });
+        assert_eq!(499_500, block_on(handle).unwrap());
+    }
+    #[test]
+    fn panic_and_cancel() {
+        struct Test {
+            name: &'static str,
+            threads: usize,
+        }
+        let tests = [
+            Test {
+                name: "single thread",
+                threads: 1,
+            },
+            Test {
+                name: "four threads",
+                threads: 4,
+            },
+        ];
+        for t in &tests {
+            let pool = ThreadPoolExecutor::new(t.threads);
+            let name = t.name;
+            let panicked = pool.spawn(async move { panic!("{}", name) });
+            let err = block_on(panicked).unwrap_err();
+            assert!(err.is_panic(), "{}", t.name);
+            assert_eq!(format!("task panicked: {}", t.name), err.to_string());
+
+            // Cancelling the pending task drops its future.
+            let (tx, rx) = futures::channel::oneshot::channel::<()>();
+            let cancelled = pool.spawn(async move {
+                let _tx = tx;
+                futures::future::pending::<()>().await
+            });
+            cancelled.cancel();
+            assert!(block_on(rx).is_err(), "{}: future not dropped", t.name);
+            assert!(
+                block_on(cancelled).unwrap_err().is_cancelled(),
+                "{}",
+                t.name
+            );
+
+            // Cancelling the completed task does nothing.
+            let completed = pool.spawn(async { 1 });
+            pool.pool().wait_stalled();
+            completed.cancel();
+            assert_eq!(1, block_on(completed).unwrap(), "{}", t.name);
+
+            // The executor keeps running the other tasks.
+            let handles: Vec<_> = (0..100u64).map(|i| pool.spawn(async move { i })).collect();
+            let got: u64 = block_on(join_all(handles))
+                .into_iter()
+                .map(Result::unwrap)
+                .sum();
+            assert_eq!(4_950, got, "{}", t.name);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Task identity and [task-local storage]
+//!
+//! # Examples
+//!
+//! ```
+//! use std::cell::Cell;
+//!
+//! use async_book::{new_executor_and_spawner, task, task_local};
+//!
+//! task_local! {
+//!     static REQUEST_ID: Cell<u64> = Cell::new(0);
+//! }
+//!
+//! let (executor, spawner) = new_executor_and_spawner();
+//! let handle = spawner.builder().name("request").spawn(async {
+//!     REQUEST_ID.with(|id| id.set(42));
+//!     let task = task::current();
+//!     (task.name().map(String::from), REQUEST_ID.with(Cell::get))
+//! });
+//! drop(spawner);
+//! executor.run();
+//!
+//! let (name, id) = futures::executor::block_on(handle).unwrap();
+//! assert_eq!(Some("request"), name.as_deref());
+//! assert_eq!(42, id);
+//! ```
+//! [task-local storage]: macro.task_local.html
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    error::Error,
+    fmt, ptr,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    sync::Arc,
+};
+
+/// Declares the task-local values of type [`LocalKey`].
+///
+/// Each task has its own copy of the value, initialized on the first
+/// access from the task.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::RefCell;
+///
+/// use async_book::task_local;
+///
+/// task_local! {
+///     static PEER: RefCell<String> = RefCell::new(String::new());
+///     static HITS: std::cell::Cell<u32> = std::cell::Cell::new(0);
+/// }
+/// ```
+/// [`localkey`]: task/struct.LocalKey.html
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task::LocalKey<$t> = {
+            fn init() -> $t {
+                $init
+            }
+            $crate::task::LocalKey::new(init)
+        };
+    };
+}
+
+/// Task ID counter.
+static TASKS: AtomicU64 = AtomicU64::new(1);
+
+/// Task-local key counter.
+static KEYS: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    /// The task being polled on the current thread.
+    static CURRENT: Cell<(*const Task, *const Locals)> = const { Cell::new((ptr::null(), ptr::null())) };
+}
+
+/// `current` returns the handle of the task being polled.
+///
+/// # Panics
+///
+/// It panics if it's called outside of the task.
+pub fn current() -> Task {
+    try_current().expect("`task::current()` called outside of a task")
+}
+
+/// `try_current` returns the handle of the task being polled, or `None`
+/// if it's called outside of the task.
+pub fn try_current() -> Option<Task> {
+    let (task, _) = CURRENT.try_with(Cell::get).ok()?;
+    // Safety: the pointer is valid while `enter` runs the task.
+    unsafe { task.as_ref() }.cloned()
+}
+
+/// A handle of the task, with its ID and the name.
+#[derive(Clone, Debug)]
+pub struct Task {
+    id: TaskId,
+    name: Option<Arc<str>>,
+}
+
+impl Task {
+    pub(crate) fn new(name: Option<String>) -> Self {
+        Self {
+            id: TaskId(TASKS.fetch_add(1, Ordering::Relaxed)),
+            name: name.map(Arc::from),
+        }
+    }
+    /// `id` returns the unique ID of the task.
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+    /// `name` returns the name of the task given by [`Builder::name`].
+    ///
+    /// [`builder::name`]: ../struct.Builder.html#method.name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "'{}' ({})", name, self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+/// A unique task ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "task#{}", self.0)
+    }
+}
+
+/// Task-local values of a task, keyed by the [`LocalKey`] ID.
+///
+/// [`localkey`]: struct.LocalKey.html
+#[derive(Default)]
+pub(crate) struct Locals {
+    values: RefCell<HashMap<usize, Box<dyn Any + Send>>>,
+}
+
+/// `enter` makes `task` the current task while running `f`.
+pub(crate) fn enter<R>(task: &Task, locals: &Locals, f: impl FnOnce() -> R) -> R {
+    struct Reset((*const Task, *const Locals));
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
+    }
+    let prev = CURRENT.with(|current| current.replace((task, locals)));
+    let _reset = Reset(prev);
+    f()
+}
+
+/// A key for the task-local value, declared by [`task_local!`].
+///
+/// [`task_local!`]: ../macro.task_local.html
+pub struct LocalKey<T: Send + 'static> {
+    init: fn() -> T,
+    key: AtomicUsize,
+}
+
+impl<T: Send + 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            init,
+            key: AtomicUsize::new(0),
+        }
+    }
+    /// `with` calls `f` with the reference to the task-local value.
+    ///
+    /// # Panics
+    ///
+    /// It panics if it's called outside of the task.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.try_with(f)
+            .expect("task-local value accessed outside of a task")
+    }
+    /// `try_with` calls `f` with the reference to the task-local value,
+    /// or returns [`AccessError`] if it's called outside of the task.
+    ///
+    /// [`accesserror`]: struct.AccessError.html
+    pub fn try_with<F, R>(&'static self, f: F) -> Result<R, AccessError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let (_, locals) = CURRENT.try_with(Cell::get).map_err(|_| AccessError)?;
+        // Safety: the pointer is valid while `enter` runs the task.
+        let locals = unsafe { locals.as_ref() }.ok_or(AccessError)?;
+        let key = self.key();
+        if !locals.values.borrow().contains_key(&key) {
+            // Initialize it outside of the borrow, as `init` may access
+            // the other task-local values.
+            let value = Box::new((self.init)());
+            locals.values.borrow_mut().entry(key).or_insert(value);
+        }
+        let value: *const T = locals.values.borrow()[&key].downcast_ref::<T>().unwrap();
+        // Safety: the value is boxed and never removed while the task is
+        // alive, so it stays in place while the other values are added
+        // by the nested `with` calls.
+        Ok(f(unsafe { &*value }))
+    }
+    fn key(&self) -> usize {
+        match self.key.load(Ordering::Acquire) {
+            0 => {
+                let key = KEYS.fetch_add(1, Ordering::Relaxed);
+                match self
+                    .key
+                    .compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire)
+                {
+                    Ok(_) => key,
+                    Err(key) => key,
+                }
+            }
+            key => key,
+        }
+    }
+}
+
+/// `AccessError` returned by [`LocalKey::try_with`] outside of the task.
+///
+/// [`localkey::try_with`]: struct.LocalKey.html#method.try_with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "task-local value accessed outside of a task")
+    }
+}
+
+impl Error for AccessError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{enter, try_current, Locals, Task};
+    use std::cell::Cell;
+    task_local! {
+        static A: Cell<u32> = Cell::new(1);
+        static B: Cell<u32> = Cell::new(2)
+    }
+    #[test]
+    fn task_local_per_task() {
+        struct Test {
+            name: &'static str,
+            task: Option<&'static str>,
+            set: u32,
+        }
+        let tests = [
+            Test {
+                name: "unnamed task",
+                task: None,
+                set: 10,
+            },
+            Test {
+                name: "named task",
+                task: Some("named"),
+                set: 20,
+            },
+        ];
+        for t in &tests {
+            let task = Task::new(t.task.map(String::from));
+            let locals = Locals::default();
+            enter(&task, &locals, || {
+                assert_eq!(t.task, try_current().unwrap().name(), "{}", t.name);
+                assert_eq!(1, A.with(Cell::get), "{}", t.name);
+                A.with(|a| {
+                    a.set(t.set);
+                    // nested access to the other key.
+                    B.with(|b| b.set(a.get() + 1));
+                });
+                assert_eq!(t.set, A.with(Cell::get), "{}", t.name);
+                assert_eq!(t.set + 1, B.with(Cell::get), "{}", t.name);
+            });
+            assert!(try_current().is_none(), "{}", t.name);
+            assert!(A.try_with(Cell::get).is_err(), "{}", t.name);
+        }
+    }
+}
@@ -0,0 +1,525 @@
+//! Let's [build a timer]!
+//!
+//! All the timers are driven by a single timer driver thread, which keeps
+//! them in the hierarchical timer [`Wheel`] and wakes up the tasks once
+//! their deadline has elapsed.  The timers created inside the
+//! [`DeterministicExecutor`] run on its virtual time instead.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use futures::executor::block_on;
+//! use futures::stream::StreamExt;
+//!
+//! use async_book::timer::{interval, sleep, timeout};
+//!
+//! block_on(async {
+//!     sleep(Duration::from_millis(1)).await;
+//!
+//!     let ticks: Vec<_> = interval(Duration::from_millis(1)).take(3).collect().await;
+//!     assert_eq!(3, ticks.len());
+//!
+//!     let slow = sleep(Duration::from_secs(60));
+//!     assert!(timeout(Duration::from_millis(1), slow).await.is_err());
+//! });
+//! ```
+//! [build a timer]: https://rust-lang.github.io/async-book/02_execution/03_wakeups.html
+//! [`wheel`]: wheel/struct.Wheel.html
+//! [`deterministicexecutor`]: ../struct.DeterministicExecutor.html
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures::stream::Stream;
+use once_cell::sync::Lazy;
+
+mod wheel;
+
+use wheel::Wheel;
+
+thread_local! {
+    /// The timer driver of the current executor, if any.
+    static CURRENT: RefCell<Option<Arc<Driver>>> = const { RefCell::new(None) };
+}
+
+/// `now` returns the current time of the timers, which is the virtual
+/// time inside the [`DeterministicExecutor`].
+///
+/// [`deterministicexecutor`]: ../struct.DeterministicExecutor.html
+pub fn now() -> Instant {
+    Driver::current().now()
+}
+
+/// `sleep` returns a [`TimerFuture`] which completes after `duration`.
+///
+/// [`timerfuture`]: struct.TimerFuture.html
+pub fn sleep(duration: Duration) -> TimerFuture {
+    TimerFuture::new(duration)
+}
+
+/// `sleep_until` returns a [`TimerFuture`] which completes at `deadline`.
+///
+/// [`timerfuture`]: struct.TimerFuture.html
+pub fn sleep_until(deadline: Instant) -> TimerFuture {
+    TimerFuture::at(deadline)
+}
+
+/// `interval` returns an [`Interval`] stream which yields every `period`.
+///
+/// # Panics
+///
+/// It panics if `period` is zero.
+///
+/// [`interval`]: struct.Interval.html
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::from_secs(0), "zero interval period");
+    Interval {
+        period,
+        timer: TimerFuture::new(period),
+    }
+}
+
+/// `timeout` returns a [`Timeout`] future which completes with the output
+/// of `future`, or with [`Elapsed`] error in case `future` doesn't complete
+/// within `duration`.
+///
+/// [`timeout`]: struct.Timeout.html
+/// [`elapsed`]: struct.Elapsed.html
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        timer: TimerFuture::new(duration),
+    }
+}
+
+/// Future based timer example
+///
+/// The deadline is fixed when the timer is created, on the timer driver of
+/// the current executor, e.g. the timer created by the task of the
+/// [`DeterministicExecutor`] runs on its virtual time.  Dropping the
+/// `TimerFuture` cancels the timer.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use futures::executor::block_on;
+///
+/// use async_book::TimerFuture;
+///
+/// // wake up after 1ms.
+/// let f = TimerFuture::new(Duration::from_millis(1));
+/// block_on(f);
+/// ```
+/// [`deterministicexecutor`]: ../struct.DeterministicExecutor.html
+pub struct TimerFuture {
+    deadline: Instant,
+    driver: Arc<Driver>,
+    id: u64,
+}
+
+impl TimerFuture {
+    pub fn new(duration: Duration) -> Self {
+        let driver = Driver::current();
+        let deadline = driver.now() + duration;
+        Self::register(driver, deadline)
+    }
+    pub fn at(deadline: Instant) -> Self {
+        Self::register(Driver::current(), deadline)
+    }
+    fn register(driver: Arc<Driver>, deadline: Instant) -> Self {
+        let id = driver.register(deadline);
+        Self {
+            deadline,
+            driver,
+            id,
+        }
+    }
+}
+
+impl Future for TimerFuture {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.driver.lock();
+        match state.timers.get_mut(&self.id) {
+            Some(Timer {
+                fired: false,
+                waker,
+            }) => {
+                // Setup the waker so that the timer driver thread can
+                // notify the executor to call `poll()` again.
+                match waker {
+                    Some(waker) if waker.will_wake(cx.waker()) => (),
+                    _ => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+            _ => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        let mut state = self.driver.lock();
+        state.timers.remove(&self.id);
+        state.wheel.remove(self.id);
+    }
+}
+
+/// `Interval` stream which yields the `Instant` of each tick.
+///
+/// Ticks missed by the slow consumer are not made up for, e.g. the next
+/// tick is a `period` after the late one.
+pub struct Interval {
+    period: Duration,
+    timer: TimerFuture,
+}
+
+impl Interval {
+    /// `tick` waits for the next tick.
+    pub async fn tick(&mut self) -> Instant {
+        futures::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+            .await
+            .expect("interval never ends")
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if Pin::new(&mut self.timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let tick = self.timer.deadline;
+        let now = self.timer.driver.now();
+        let next = if tick + self.period > now {
+            tick + self.period
+        } else {
+            now + self.period
+        };
+        self.timer = TimerFuture::at(next);
+        Poll::Ready(Some(tick))
+    }
+}
+
+/// `Timeout` future returned by [`timeout`].
+///
+/// [`timeout`]: fn.timeout.html
+pub struct Timeout<F> {
+    future: F,
+    timer: TimerFuture,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `future` is structurally pinned and never moved out
+        // of the `Timeout`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.timer).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// `Elapsed` error returned by the [`Timeout`] future.
+///
+/// [`timeout`]: struct.Timeout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+/// Timer `Driver`, shared by the timer driver thread and the timers.
+pub(crate) struct Driver {
+    state: Mutex<State>,
+    cvar: Condvar,
+}
+
+struct State {
+    /// Base time of the timer wheel.
+    origin: Instant,
+    clock: Clock,
+    wheel: Wheel,
+    timers: HashMap<u64, Timer>,
+    next_id: u64,
+}
+
+struct Timer {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+enum Clock {
+    /// Wall clock time, driven by the timer driver thread.
+    Real,
+    /// Virtual time since `origin`, driven by [`Driver::advance`].
+    ///
+    /// [`driver::advance`]: struct.Driver.html#method.advance
+    Virtual(Duration),
+}
+
+impl Driver {
+    /// `global` returns the process wide timer driver, which spawns the
+    /// timer driver thread on the first call.
+    fn global() -> Arc<Self> {
+        static DRIVER: Lazy<Arc<Driver>> = Lazy::new(|| {
+            let driver = Arc::new(Driver::new(Clock::Real));
+            let cloned = driver.clone();
+            thread::Builder::new()
+                .name(String::from("async-book-timer"))
+                .spawn(move || cloned.run())
+                .expect("cannot spawn the timer thread");
+            driver
+        });
+        DRIVER.clone()
+    }
+    /// `new_virtual` returns the timer driver on the virtual time, which
+    /// only advances by [`advance`].
+    ///
+    /// [`advance`]: #method.advance
+    pub(crate) fn new_virtual() -> Arc<Self> {
+        Arc::new(Self::new(Clock::Virtual(Duration::from_secs(0))))
+    }
+    /// `current` returns the timer driver of the current executor, or the
+    /// global timer driver.
+    fn current() -> Arc<Self> {
+        CURRENT
+            .try_with(|current| current.borrow().clone())
+            .ok()
+            .flatten()
+            .unwrap_or_else(Self::global)
+    }
+    /// `enter` makes `driver` the timer driver of the current thread while
+    /// running `f`.
+    pub(crate) fn enter<R>(driver: &Arc<Self>, f: impl FnOnce() -> R) -> R {
+        struct Reset(Option<Arc<Driver>>);
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                CURRENT.with(|current| *current.borrow_mut() = prev);
+            }
+        }
+        let prev = CURRENT.with(|current| current.borrow_mut().replace(driver.clone()));
+        let _reset = Reset(prev);
+        f()
+    }
+    /// `now` returns the current time of the driver.
+    pub(crate) fn now(&self) -> Instant {
+        let state = self.lock();
+        match state.clock {
+            Clock::Real => Instant::now(),
+            Clock::Virtual(elapsed) => state.origin + elapsed,
+        }
+    }
+    /// `advance` advances the virtual time to the next timer deadline and
+    /// wakes up the expired timers.
+    ///
+    /// It returns `false` in case there is no timer to advance the time to.
+    pub(crate) fn advance(&self) -> bool {
+        let mut state = self.lock();
+        loop {
+            let when = match state.wheel.next_expiration() {
+                None => return false,
+                Some(when) => when,
+            };
+            let elapsed = Duration::from_millis(when);
+            if let Clock::Virtual(now) = &mut state.clock {
+                *now = (*now).max(elapsed);
+            }
+            if let Some(wakers) = state.fire(when) {
+                drop(state);
+                wakers.into_iter().for_each(Waker::wake);
+                return true;
+            }
+        }
+    }
+    fn new(clock: Clock) -> Self {
+        Self {
+            state: Mutex::new(State {
+                origin: Instant::now(),
+                clock,
+                wheel: Wheel::new(),
+                timers: HashMap::new(),
+                next_id: 0,
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+    fn register(&self, deadline: Instant) -> u64 {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        let when = state.millis(deadline);
+        let fired = !state.wheel.insert(id, when);
+        state.timers.insert(id, Timer { fired, waker: None });
+        if !fired {
+            // Let the driver thread recalculate the next wake up time.
+            self.cvar.notify_one();
+        }
+        id
+    }
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            let now = Instant::now().saturating_duration_since(state.origin);
+            if let Some(wakers) = state.fire(now.as_millis() as u64) {
+                drop(state);
+                wakers.into_iter().for_each(Waker::wake);
+                state = self.lock();
+                continue;
+            }
+            state = match state.wheel.next_expiration() {
+                None => self.cvar.wait(state).unwrap(),
+                Some(when) => {
+                    let timeout = Duration::from_millis(when).saturating_sub(now);
+                    self.cvar.wait_timeout(state, timeout).unwrap().0
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    /// `millis` returns the milliseconds from `origin`, rounded up.
+    fn millis(&self, deadline: Instant) -> u64 {
+        let duration = deadline.saturating_duration_since(self.origin);
+        let millis = duration.as_millis() as u64;
+        if duration > Duration::from_millis(millis) {
+            millis + 1
+        } else {
+            millis
+        }
+    }
+    /// `fire` advances the wheel to `now` and returns the wakers of the
+    /// expired timers, or `None` in case no timer has expired.
+    fn fire(&mut self, now: u64) -> Option<Vec<Waker>> {
+        let expired = self.wheel.poll(now);
+        if expired.is_empty() {
+            return None;
+        }
+        let mut wakers = Vec::with_capacity(expired.len());
+        for id in expired {
+            if let Some(timer) = self.timers.get_mut(&id) {
+                timer.fired = true;
+                wakers.extend(timer.waker.take());
+            }
+        }
+        Some(wakers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interval, sleep, sleep_until, timeout, Driver, TimerFuture};
+    use futures::executor::block_on;
+    use futures::future::FutureExt;
+    use futures::stream::StreamExt;
+    use std::time::{Duration, Instant};
+    #[test]
+    fn sleep_and_sleep_until() {
+        struct Test {
+            name: &'static str,
+            millis: u64,
+        }
+        let tests = [
+            Test {
+                name: "zero sleep",
+                millis: 0,
+            },
+            Test {
+                name: "1ms sleep",
+                millis: 1,
+            },
+            Test {
+                name: "70ms sleep on the level 1 wheel",
+                millis: 70,
+            },
+        ];
+        for t in &tests {
+            let duration = Duration::from_millis(t.millis);
+            let start = Instant::now();
+            block_on(sleep(duration));
+            assert!(start.elapsed() >= duration, "{}: sleep", t.name);
+            let deadline = Instant::now() + duration;
+            block_on(sleep_until(deadline));
+            assert!(Instant::now() >= deadline, "{}: sleep_until", t.name);
+        }
+    }
+    #[test]
+    fn interval_ticks() {
+        let period = Duration::from_millis(5);
+        let start = Instant::now();
+        let ticks: Vec<_> = block_on(interval(period).take(3).collect());
+        for (i, tick) in ticks.iter().enumerate() {
+            assert!(*tick >= start + period * (i as u32 + 1));
+        }
+        assert!(start.elapsed() >= period * 3);
+    }
+    #[test]
+    fn timeout_elapsed_and_completed() {
+        let slow = sleep(Duration::from_secs(60));
+        assert!(block_on(timeout(Duration::from_millis(1), slow)).is_err());
+        let fast = async { 1 };
+        assert_eq!(Ok(1), block_on(timeout(Duration::from_secs(60), fast)));
+    }
+    #[test]
+    fn deadline_on_creation() {
+        use crate::DeterministicExecutor;
+        let executor = DeterministicExecutor::new(0);
+        let start = executor.now();
+        let (slept, timed_out) = executor.block_on(async {
+            let slept = sleep(Duration::from_secs(10));
+            let timed_out = timeout(Duration::from_secs(10), async {
+                sleep(Duration::from_secs(30)).await;
+            });
+            sleep(Duration::from_secs(5)).await;
+            slept.await;
+            let slept = super::now();
+            assert!(timed_out.await.is_err());
+            (slept, super::now())
+        });
+        // Not counted from the first poll at 5s.
+        assert_eq!(start + Duration::from_secs(10), slept);
+        assert_eq!(start + Duration::from_secs(10), timed_out);
+    }
+    #[test]
+    fn drop_cancels_timer() {
+        let mut timers: Vec<_> = (0..10_000)
+            .map(|_| TimerFuture::new(Duration::from_secs(3_600)))
+            .collect();
+        for timer in &mut timers {
+            assert_eq!(None, timer.now_or_never());
+        }
+        let ids: Vec<_> = timers.iter().map(|timer| timer.id).collect();
+        drop(timers);
+        let driver = Driver::global();
+        let state = driver.lock();
+        assert!(ids.iter().all(|id| !state.timers.contains_key(id)));
+    }
+}
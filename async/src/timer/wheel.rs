@@ -0,0 +1,254 @@
+//! Hierarchical timer [`Wheel`]
+//!
+//! The wheel has `LEVELS` levels of `SLOTS` slots each.  A slot on the
+//! level `n` covers `SLOTS^n` milliseconds, e.g. the level 0 slot covers
+//! a millisecond and the level 1 slot covers 64 milliseconds.  A timer
+//! is placed on the lowest level which covers its deadline and cascades
+//! down to the lower levels as the wheel advances.
+//!
+//! [`wheel`]: struct.Wheel.html
+use std::collections::HashMap;
+
+/// Number of slots per level.
+const SLOTS: usize = 64;
+
+/// Number of bits to index a slot in a level.
+const SLOT_BITS: usize = 6;
+
+/// Number of levels, which covers about two years in milliseconds.
+const LEVELS: usize = 6;
+
+/// Maximum duration the wheel can hold, in milliseconds.
+const MAX: u64 = 1 << (SLOT_BITS * LEVELS);
+
+/// Hierarchical timer `Wheel` in milliseconds.
+pub(crate) struct Wheel {
+    /// Milliseconds the wheel has advanced to.
+    elapsed: u64,
+
+    /// Timer wheel levels, from the finest to the coarsest.
+    levels: Vec<Level>,
+
+    /// Level and the slot index of the timers in the wheel.
+    index: HashMap<u64, (usize, usize)>,
+}
+
+struct Level {
+    /// Bitmap of the non-empty slots.
+    occupied: u64,
+
+    /// Timer IDs and their deadlines.
+    slots: Vec<HashMap<u64, u64>>,
+}
+
+impl Wheel {
+    pub(crate) fn new() -> Self {
+        let levels = (0..LEVELS)
+            .map(|_| Level {
+                occupied: 0,
+                slots: (0..SLOTS).map(|_| HashMap::new()).collect(),
+            })
+            .collect();
+        Self {
+            elapsed: 0,
+            levels,
+            index: HashMap::new(),
+        }
+    }
+    /// `elapsed` returns the milliseconds the wheel has advanced to.
+    #[cfg(test)]
+    pub(crate) fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+    /// `insert` inserts the timer `id` which expires at `when`.
+    ///
+    /// It returns `false` without inserting the timer if `when` has
+    /// already been elapsed.
+    pub(crate) fn insert(&mut self, id: u64, when: u64) -> bool {
+        if when <= self.elapsed {
+            return false;
+        }
+        let level = Self::level_for(self.elapsed, when);
+        let slot = Self::slot_for(self.elapsed, when, level);
+        let level_ref = &mut self.levels[level];
+        level_ref.slots[slot].insert(id, when);
+        level_ref.occupied |= 1 << slot;
+        self.index.insert(id, (level, slot));
+        true
+    }
+    /// `remove` removes the timer `id` from the wheel, if any.
+    pub(crate) fn remove(&mut self, id: u64) {
+        if let Some((level, slot)) = self.index.remove(&id) {
+            let level = &mut self.levels[level];
+            level.slots[slot].remove(&id);
+            if level.slots[slot].is_empty() {
+                level.occupied &= !(1 << slot);
+            }
+        }
+    }
+    /// `next_expiration` returns the earliest time, in milliseconds,
+    /// the wheel needs to be polled.
+    pub(crate) fn next_expiration(&self) -> Option<u64> {
+        self.next_slot().map(|(_, _, deadline)| deadline)
+    }
+    /// `poll` advances the wheel to `now` and returns the expired timers.
+    pub(crate) fn poll(&mut self, now: u64) -> Vec<u64> {
+        let mut expired = Vec::new();
+        while let Some((level, slot, deadline)) = self.next_slot() {
+            if deadline > now {
+                break;
+            }
+            self.elapsed = self.elapsed.max(deadline);
+            let level = &mut self.levels[level];
+            level.occupied &= !(1 << slot);
+            let timers = std::mem::take(&mut level.slots[slot]);
+            for (id, when) in timers {
+                self.index.remove(&id);
+                // Cascade it down to the lower level.
+                if !self.insert(id, when) {
+                    expired.push(id);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+        expired
+    }
+    /// `next_slot` returns the level, the slot index and the deadline of
+    /// the next slot to be processed.
+    fn next_slot(&self) -> Option<(usize, usize, u64)> {
+        let mut next: Option<(usize, usize, u64)> = None;
+        for (i, level) in self.levels.iter().enumerate() {
+            if level.occupied == 0 {
+                continue;
+            }
+            let slot_range = 1u64 << (SLOT_BITS * i);
+            let level_range = slot_range << SLOT_BITS;
+            let now_slot = (self.elapsed / slot_range) as usize % SLOTS;
+            let zeros = level
+                .occupied
+                .rotate_right(now_slot as u32)
+                .trailing_zeros() as usize;
+            let slot = (zeros + now_slot) % SLOTS;
+            let level_start = self.elapsed & !(level_range - 1);
+            let mut deadline = level_start + slot as u64 * slot_range;
+            if deadline <= self.elapsed && (i != 0 || slot != now_slot) {
+                // The slot is in the next round of the level.
+                deadline += level_range;
+            }
+            if !matches!(next, Some((_, _, d)) if d <= deadline) {
+                next = Some((i, slot, deadline));
+            }
+        }
+        next
+    }
+    fn level_for(elapsed: u64, when: u64) -> usize {
+        let when = when.min(elapsed + MAX - 1);
+        let masked = (elapsed ^ when) | (SLOTS as u64 - 1);
+        let significant = 63 - masked.leading_zeros() as usize;
+        (significant / SLOT_BITS).min(LEVELS - 1)
+    }
+    fn slot_for(elapsed: u64, when: u64, level: usize) -> usize {
+        let when = when.min(elapsed + MAX - 1);
+        (when >> (SLOT_BITS * level)) as usize % SLOTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Wheel;
+    #[test]
+    fn poll() {
+        struct Test {
+            name: &'static str,
+            timers: Vec<u64>,
+            polls: Vec<(u64, Vec<u64>)>,
+        }
+        let tests = [
+            Test {
+                name: "no timer",
+                timers: vec![],
+                polls: vec![(1, vec![]), (1_000, vec![])],
+            },
+            Test {
+                name: "single level 0 timer",
+                timers: vec![10],
+                polls: vec![(9, vec![]), (10, vec![10]), (11, vec![])],
+            },
+            Test {
+                name: "single level 1 timer",
+                timers: vec![100],
+                polls: vec![(64, vec![]), (99, vec![]), (100, vec![100])],
+            },
+            Test {
+                name: "single level 3 timer",
+                timers: vec![300_000],
+                polls: vec![(299_999, vec![]), (300_000, vec![300_000])],
+            },
+            Test {
+                name: "timers on multiple levels at once",
+                timers: vec![1, 63, 64, 4_095, 4_096, 1_000_000],
+                polls: vec![(2_000_000, vec![1, 63, 64, 4_095, 4_096, 1_000_000])],
+            },
+            Test {
+                name: "timers on multiple levels one by one",
+                timers: vec![1, 63, 64, 4_095, 4_096, 1_000_000],
+                polls: vec![
+                    (1, vec![1]),
+                    (63, vec![63]),
+                    (64, vec![64]),
+                    (4_095, vec![4_095]),
+                    (4_096, vec![4_096]),
+                    (999_999, vec![]),
+                    (1_000_000, vec![1_000_000]),
+                ],
+            },
+            Test {
+                name: "timer beyond the wheel",
+                timers: vec![1 << 40],
+                polls: vec![(1 << 36, vec![]), (1 << 40, vec![1 << 40])],
+            },
+        ];
+        for t in &tests {
+            let mut wheel = Wheel::new();
+            for when in &t.timers {
+                assert!(wheel.insert(*when, *when), "{}", t.name);
+            }
+            for (now, want) in &t.polls {
+                let mut got = wheel.poll(*now);
+                got.sort();
+                assert_eq!(want, &got, "{}: poll({})", t.name, now);
+                assert_eq!(*now, wheel.elapsed(), "{}", t.name);
+            }
+        }
+    }
+    #[test]
+    fn next_expiration() {
+        let mut wheel = Wheel::new();
+        assert_eq!(None, wheel.next_expiration());
+        wheel.insert(1, 1_000);
+        assert!(wheel.next_expiration().unwrap() <= 1_000);
+        wheel.insert(2, 10);
+        assert_eq!(Some(10), wheel.next_expiration());
+        assert_eq!(vec![2], wheel.poll(10));
+        assert_eq!(vec![1], wheel.poll(1_000));
+        assert_eq!(None, wheel.next_expiration());
+    }
+    #[test]
+    fn insert_elapsed_and_remove() {
+        let mut wheel = Wheel::new();
+        wheel.poll(100);
+        assert!(!wheel.insert(1, 100), "elapsed timer");
+        assert!(wheel.insert(2, 200));
+        assert!(wheel.insert(3, 300));
+        assert_eq!(2, wheel.len());
+        wheel.remove(2);
+        wheel.remove(2);
+        assert_eq!(1, wheel.len());
+        assert_eq!(vec![3], wheel.poll(1_000));
+        assert_eq!(0, wheel.len());
+    }
+}
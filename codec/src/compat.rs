@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-2.0
+//! [`Compat`] adapter of the Tokio I/O types
+//!
+//! Tokio 0.2 has its own `AsyncRead` and `AsyncWrite`, which don't
+//! implement the `futures` ones the [`Framed`] is built on.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::{SinkExt, StreamExt};
+//! use tokio::net::{TcpListener, TcpStream};
+//! use codec_book::{Compat, Framed, LinesCodec};
+//!
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(async {
+//!     let mut l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+//!     let addr = l.local_addr().unwrap();
+//!     let client = TcpStream::connect(addr).await.unwrap();
+//!     let mut client = Framed::new(Compat::new(client), LinesCodec::new());
+//!     let (server, _) = l.accept().await.unwrap();
+//!     let mut server = Framed::new(Compat::new(server), LinesCodec::new());
+//!
+//!     client.send("hello").await.unwrap();
+//!     assert_eq!("hello", server.next().await.unwrap().unwrap());
+//! });
+//! ```
+//! [`compat`]: struct.Compat.html
+//! [`framed`]: ../struct.Framed.html
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+/// Adapter of the Tokio `AsyncRead` and `AsyncWrite`.
+#[derive(Debug)]
+pub struct Compat<T>(T);
+
+impl<T> Compat<T> {
+    pub fn new(io: T) -> Self {
+        Self(io)
+    }
+    pub fn get_ref(&self) -> &T {
+        &self.0
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> AsyncRead for Compat<T>
+where
+    T: tokio::io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Compat<T>
+where
+    T: tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: GPL-2.0
+//! [`Framed`] type
+//!
+//! [`framed`]: struct.Framed.html
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{ready, Sink, Stream};
+
+use super::{Decoder, Encoder, Error};
+
+/// Read size of the underlying reader.
+const READ: usize = 8 * 1024;
+
+/// Write buffer size to flush the frames at.
+const BACKPRESSURE: usize = 8 * 1024;
+
+/// `Stream` and `Sink` of the frames over the byte I/O.
+///
+/// The stream yields the recoverable [`Error`]s in between the frames, and
+/// ends after the I/O error.
+///
+/// [`error`]: enum.Error.html
+#[derive(Debug)]
+pub struct Framed<T, C> {
+    io: T,
+    codec: C,
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+    eof: bool,
+    done: bool,
+}
+
+impl<T, C> Framed<T, C> {
+    /// `new` creates the framed `io` with `codec`.
+    pub fn new(io: T, codec: C) -> Self {
+        Self {
+            io,
+            codec,
+            rbuf: Vec::new(),
+            wbuf: Vec::new(),
+            eof: false,
+            done: false,
+        }
+    }
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+    /// `into_inner` returns the underlying I/O, dropping the buffered
+    /// bytes.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T, C> Stream for Framed<T, C>
+where
+    T: AsyncRead + Unpin,
+    C: Decoder + Unpin,
+{
+    type Item = Result<C::Item, Error>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.done {
+            let frame = if this.eof {
+                this.codec.decode_eof(&mut this.rbuf)
+            } else {
+                this.codec.decode(&mut this.rbuf)
+            };
+            match frame {
+                Ok(Some(frame)) => return Poll::Ready(Some(Ok(frame))),
+                Ok(None) if this.eof => this.done = true,
+                Ok(None) => (),
+                Err(err) => {
+                    this.done = !err.is_recoverable();
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+            if this.eof {
+                continue;
+            }
+            let len = this.rbuf.len();
+            this.rbuf.resize(len + READ, 0);
+            let n = match Pin::new(&mut this.io).poll_read(cx, &mut this.rbuf[len..]) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(err)) => {
+                    this.rbuf.truncate(len);
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+                Poll::Pending => {
+                    this.rbuf.truncate(len);
+                    return Poll::Pending;
+                }
+            };
+            this.rbuf.truncate(len + n);
+            this.eof = n == 0;
+        }
+        Poll::Ready(None)
+    }
+}
+
+impl<T, C, I> Sink<I> for Framed<T, C>
+where
+    T: AsyncWrite + Unpin,
+    C: Encoder<I> + Unpin,
+{
+    type Error = Error;
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.wbuf.len() >= BACKPRESSURE {
+            self.poll_flush(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Error> {
+        let this = self.get_mut();
+        this.codec.encode(item, &mut this.wbuf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        while !this.wbuf.is_empty() {
+            let n = ready!(Pin::new(&mut this.io).poll_write(cx, &this.wbuf))?;
+            if n == 0 {
+                let err = std::io::ErrorKind::WriteZero;
+                return Poll::Ready(Err(std::io::Error::from(err).into()));
+            }
+            this.wbuf.drain(..n);
+        }
+        ready!(Pin::new(&mut this.io).poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        ready!(Pin::new(&mut self.io).poll_close(cx))?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Framed;
+    use crate::{Error, LengthDelimitedCodec, LinesCodec};
+    use futures::io::{self, AsyncRead, Cursor};
+    use futures::{executor, SinkExt, StreamExt};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Reader, which returns the chunks one at a time, and then the error.
+    struct Chunks {
+        chunks: Vec<&'static [u8]>,
+        err: Option<io::ErrorKind>,
+    }
+
+    impl AsyncRead for Chunks {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            if self.chunks.is_empty() {
+                return Poll::Ready(self.err.map_or(Ok(0), |kind| Err(kind.into())));
+            }
+            // Makes the reader pending once before each chunk.
+            if self.chunks[0].is_empty() {
+                self.chunks.remove(0);
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Poll::Ready(Ok(chunk.len()))
+        }
+    }
+
+    #[test]
+    fn lines() {
+        struct Test {
+            name: &'static str,
+            chunks: Vec<&'static [u8]>,
+            err: Option<io::ErrorKind>,
+            want: Vec<Result<&'static str, String>>,
+        }
+        let tests = [
+            Test {
+                name: "lines over pending chunks",
+                chunks: vec![b"", b"hel", b"", b"lo\nworld", b"", b"\n"],
+                err: None,
+                want: vec![Ok("hello"), Ok("world")],
+            },
+            Test {
+                name: "recovers from the too long line",
+                chunks: vec![b"hello world\n", b"hello\n"],
+                err: None,
+                want: vec![Err("line is longer than 8 bytes".into()), Ok("hello")],
+            },
+            Test {
+                name: "ends after the I/O error",
+                chunks: vec![b"hello\nwor"],
+                err: Some(io::ErrorKind::ConnectionReset),
+                want: vec![Ok("hello"), Err("connection reset".into())],
+            },
+        ];
+        for t in &tests {
+            let reader = Chunks {
+                chunks: t.chunks.clone(),
+                err: t.err,
+            };
+            let framed = Framed::new(reader, LinesCodec::with_max_length(8));
+            let got: Vec<_> = executor::block_on(framed.collect());
+            let got: Vec<_> = got.into_iter().map(|r| r.map_err(|e| e.to_string())).collect();
+            let want: Vec<_> = t.want.iter().map(|r| r.clone().map(String::from)).collect();
+            assert_eq!(want, got, "{}", t.name);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        executor::block_on(async {
+            let frames: Vec<&[u8]> = vec![b"hello", b"", b"world"];
+            let mut framed = Framed::new(Cursor::new(Vec::new()), LengthDelimitedCodec::new());
+            for frame in &frames {
+                framed.send(*frame).await.unwrap();
+            }
+            // Rejects the frame without touching the buffer.
+            *framed.codec_mut() = LengthDelimitedCodec::with_max_length(4);
+            match framed.send(&b"hello"[..]).await {
+                Err(err @ Error::FrameTooLarge { .. }) => assert!(err.is_recoverable()),
+                other => panic!("unexpected {:?}", other),
+            }
+            let mut io = framed.into_inner();
+            io.set_position(0);
+            let framed = Framed::new(io, LengthDelimitedCodec::new());
+            let got: Vec<_> = framed.map(Result::unwrap).collect().await;
+            assert_eq!(frames, got);
+        });
+    }
+}
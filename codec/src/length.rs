@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: GPL-2.0
+//! [`LengthDelimitedCodec`] type
+//!
+//! [`lengthdelimitedcodec`]: struct.LengthDelimitedCodec.html
+use std::{convert::TryFrom, mem};
+
+use super::{Decoder, Encoder, Error};
+
+/// Length of the frame header.
+const HEADER: usize = mem::size_of::<u32>();
+
+/// Default max frame length, 8MiB.
+const MAX: usize = 8 * 1024 * 1024;
+
+/// Codec of the frames prefixed by the big-endian `u32` length.
+///
+/// The frame longer than the max length is skipped with the
+/// [`FrameTooLarge`] error.
+///
+/// [`frametoolarge`]: enum.Error.html#variant.FrameTooLarge
+#[derive(Clone, Debug)]
+pub struct LengthDelimitedCodec {
+    max: usize,
+    /// Remaining bytes of the skipped frame.
+    skip: usize,
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> Self {
+        Self::with_max_length(MAX)
+    }
+}
+
+impl LengthDelimitedCodec {
+    /// `new` creates the codec with the 8MiB max frame length.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// `with_max_length` creates the codec with the `max` bytes frame
+    /// length, excluding the header.
+    pub fn with_max_length(max: usize) -> Self {
+        Self { max, skip: 0 }
+    }
+    /// `max_length` returns the max frame length.
+    pub fn max_length(&self) -> usize {
+        self.max
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        if self.skip > 0 {
+            let n = self.skip.min(buf.len());
+            buf.drain(..n);
+            self.skip -= n;
+            if self.skip > 0 {
+                return Ok(None);
+            }
+        }
+        if buf.len() < HEADER {
+            return Ok(None);
+        }
+        let mut header = [0; HEADER];
+        header.copy_from_slice(&buf[..HEADER]);
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.max {
+            buf.drain(..HEADER);
+            let n = len.min(buf.len());
+            buf.drain(..n);
+            self.skip = len - n;
+            return Err(Error::FrameTooLarge { len, max: self.max });
+        }
+        if buf.len() < HEADER + len {
+            buf.reserve(HEADER + len - buf.len());
+            return Ok(None);
+        }
+        let frame = buf[HEADER..HEADER + len].to_vec();
+        buf.drain(..HEADER + len);
+        Ok(Some(frame))
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LengthDelimitedCodec {
+    fn encode(&mut self, frame: T, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let frame = frame.as_ref();
+        let len = frame.len();
+        let header = match u32::try_from(len) {
+            Ok(header) if len <= self.max => header,
+            _ => return Err(Error::FrameTooLarge { len, max: self.max }),
+        };
+        buf.reserve(HEADER + len);
+        buf.extend_from_slice(&header.to_be_bytes());
+        buf.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LengthDelimitedCodec;
+    use crate::{Decoder, Encoder};
+    #[test]
+    fn decode() {
+        struct Test {
+            name: &'static str,
+            max: usize,
+            chunks: &'static [&'static [u8]],
+            want: Vec<Result<&'static [u8], &'static str>>,
+        }
+        let tests = [
+            Test {
+                name: "no input",
+                max: 8,
+                chunks: &[],
+                want: vec![],
+            },
+            Test {
+                name: "empty frame",
+                max: 8,
+                chunks: &[b"\0\0\0\0"],
+                want: vec![Ok(b"")],
+            },
+            Test {
+                name: "frames in a chunk",
+                max: 8,
+                chunks: &[b"\0\0\0\x05hello\0\0\0\x05world"],
+                want: vec![Ok(b"hello"), Ok(b"world")],
+            },
+            Test {
+                name: "frame over chunks",
+                max: 8,
+                chunks: &[b"\0\0", b"\0\x05he", b"llo"],
+                want: vec![Ok(b"hello")],
+            },
+            Test {
+                name: "too large frame in a chunk",
+                max: 5,
+                chunks: &[b"\0\0\0\x0bhello world\0\0\0\x05hello"],
+                want: vec![Err("11 bytes frame is longer than 5 bytes"), Ok(b"hello")],
+            },
+            Test {
+                name: "too large frame over chunks",
+                max: 5,
+                chunks: &[b"\0\0\0\x0bhello", b" wor", b"ld\0\0\0\x05hello"],
+                want: vec![Err("11 bytes frame is longer than 5 bytes"), Ok(b"hello")],
+            },
+            Test {
+                name: "partial frame",
+                max: 8,
+                chunks: &[b"\0\0\0\x05hello\0\0\0\x05wor"],
+                want: vec![Ok(b"hello"), Err("bytes remaining on stream")],
+            },
+        ];
+        for t in &tests {
+            let mut codec = LengthDelimitedCodec::with_max_length(t.max);
+            let mut buf = Vec::new();
+            let mut got = Vec::new();
+            for chunk in t.chunks {
+                buf.extend_from_slice(chunk);
+                loop {
+                    match codec.decode(&mut buf) {
+                        Ok(Some(frame)) => got.push(Ok(frame)),
+                        Ok(None) => break,
+                        Err(err) => got.push(Err(err.to_string())),
+                    }
+                }
+            }
+            match codec.decode_eof(&mut buf) {
+                Ok(Some(frame)) => got.push(Ok(frame)),
+                Ok(None) => (),
+                Err(err) => got.push(Err(err.to_string())),
+            }
+            let want: Vec<_> = t
+                .want
+                .iter()
+                .map(|r| r.map(<[u8]>::to_vec).map_err(String::from))
+                .collect();
+            assert_eq!(want, got, "{}", t.name);
+            assert!(buf.is_empty(), "{}", t.name);
+        }
+    }
+    #[test]
+    fn encode() {
+        let mut codec = LengthDelimitedCodec::with_max_length(5);
+        let mut buf = Vec::new();
+        codec.encode("hello", &mut buf).unwrap();
+        codec.encode(vec![], &mut buf).unwrap();
+        let err = codec.encode("hello world", &mut buf).unwrap_err();
+        assert!(err.is_recoverable());
+        assert_eq!(b"\0\0\0\x05hello\0\0\0\0", &buf[..]);
+    }
+}
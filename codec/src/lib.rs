@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Framing layer shared by the [async-std] and the [tokio] examples
+//!
+//! The [`Decoder`] and the [`Encoder`] turn the bytes into the frames and
+//! back, and the [`Framed`] adapter drives them over any [`AsyncRead`] and
+//! [`AsyncWrite`] as the `Stream` and the `Sink` of the frames.  The
+//! [`Error`]s other than the I/O errors are recoverable, e.g. the stream
+//! keeps yielding the frames after the too long line.
+//!
+//! The Tokio I/O types are adapted with [`Compat`] under the `tokio`
+//! feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::{executor, io::Cursor, SinkExt, StreamExt};
+//! use codec_book::{Error, Framed, LinesCodec};
+//!
+//! executor::block_on(async {
+//!     let input = Cursor::new(b"hello\nway too long line\nworld\n".to_vec());
+//!     let mut lines = Framed::new(input, LinesCodec::with_max_length(8));
+//!     assert_eq!("hello", lines.next().await.unwrap().unwrap());
+//!     match lines.next().await.unwrap() {
+//!         Err(Error::LineTooLong { max: 8 }) => (),
+//!         other => panic!("unexpected {:?}", other),
+//!     }
+//!     assert_eq!("world", lines.next().await.unwrap().unwrap());
+//!     assert!(lines.next().await.is_none());
+//!
+//!     let mut output = Framed::new(Cursor::new(Vec::new()), LinesCodec::new());
+//!     output.send("hello").await.unwrap();
+//!     assert_eq!(b"hello\n", &output.into_inner().into_inner()[..]);
+//! });
+//! ```
+//! [async-std]: https://book.async.rs
+//! [tokio]: https://github.com/tokio-rs/book/blob/master/SUMMARY.md
+//! [`decoder`]: trait.Decoder.html
+//! [`encoder`]: trait.Encoder.html
+//! [`framed`]: struct.Framed.html
+//! [`error`]: enum.Error.html
+//! [`compat`]: compat/struct.Compat.html
+//! [`asyncread`]: https://docs.rs/futures/0.3/futures/io/trait.AsyncRead.html
+//! [`asyncwrite`]: https://docs.rs/futures/0.3/futures/io/trait.AsyncWrite.html
+use std::{error, fmt, io, string::FromUtf8Error};
+
+#[cfg(feature = "tokio")]
+pub mod compat;
+mod framed;
+mod length;
+mod lines;
+
+#[cfg(feature = "tokio")]
+pub use compat::Compat;
+pub use framed::Framed;
+pub use length::LengthDelimitedCodec;
+pub use lines::LinesCodec;
+
+/// Decoder of the frames out of the read buffer.
+pub trait Decoder {
+    type Item;
+    /// `decode` takes the next frame out of `buf`, or returns `None` in
+    /// case `buf` doesn't have the whole frame yet.
+    ///
+    /// The frame bytes, including the broken ones, should be removed from
+    /// `buf` so that the next call picks up from the next frame.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Self::Item>, Error>;
+    /// `decode_eof` is called once the reader reaches EOF.
+    ///
+    /// It returns the `UnexpectedEof` error in case the partial frame
+    /// remains in `buf`.
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<Self::Item>, Error> {
+        match self.decode(buf)? {
+            Some(frame) => Ok(Some(frame)),
+            None if buf.is_empty() => Ok(None),
+            None => {
+                buf.clear();
+                let msg = "bytes remaining on stream";
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg).into())
+            }
+        }
+    }
+}
+
+/// Encoder of the `Item` frames into the write buffer.
+pub trait Encoder<Item> {
+    /// `encode` appends the `item` frame to `buf`.
+    ///
+    /// `buf` should be left untouched in case of the error.
+    fn encode(&mut self, item: Item, buf: &mut Vec<u8>) -> Result<(), Error>;
+}
+
+/// Error of the framing layer.
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error, which ends the stream.
+    Io(io::Error),
+    /// The line is longer than `max` bytes.
+    LineTooLong { max: usize },
+    /// The frame of `len` bytes is longer than `max` bytes.
+    FrameTooLarge { len: usize, max: usize },
+    /// The line is not UTF-8.
+    Utf8(FromUtf8Error),
+}
+
+impl Error {
+    /// `is_recoverable` returns `true` in case the stream yields the next
+    /// frame after the error.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, Self::Io(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => err.fmt(f),
+            Self::LineTooLong { max } => write!(f, "line is longer than {} bytes", max),
+            Self::FrameTooLarge { len, max } => {
+                write!(f, "{} bytes frame is longer than {} bytes", len, max)
+            }
+            Self::Utf8(err) => write!(f, "invalid line: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Utf8(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: GPL-2.0
+//! [`LinesCodec`] type
+//!
+//! [`linescodec`]: struct.LinesCodec.html
+use super::{Decoder, Encoder, Error};
+
+/// Codec of the newline terminated UTF-8 lines.
+///
+/// The decoded line doesn't have the trailing `\n` nor `\r\n`.  The line
+/// longer than the max length is discarded up to the next newline with
+/// the [`LineTooLong`] error.
+///
+/// [`linetoolong`]: enum.Error.html#variant.LineTooLong
+#[derive(Clone, Debug)]
+pub struct LinesCodec {
+    max: usize,
+    /// Index of `buf` to look for the newline from.
+    next: usize,
+    /// Discards the bytes up to the next newline.
+    discarding: bool,
+}
+
+impl Default for LinesCodec {
+    fn default() -> Self {
+        Self::with_max_length(usize::MAX)
+    }
+}
+
+impl LinesCodec {
+    /// `new` creates the codec without the max length.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// `with_max_length` creates the codec with the `max` bytes line
+    /// length, excluding the newline.
+    pub fn with_max_length(max: usize) -> Self {
+        Self {
+            max,
+            next: 0,
+            discarding: false,
+        }
+    }
+    /// `max_length` returns the max line length.
+    pub fn max_length(&self) -> usize {
+        self.max
+    }
+    fn line(&self, mut line: Vec<u8>) -> Result<String, Error> {
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.len() > self.max {
+            return Err(Error::LineTooLong { max: self.max });
+        }
+        String::from_utf8(line).map_err(Error::Utf8)
+    }
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<String>, Error> {
+        loop {
+            let newline = buf[self.next..].iter().position(|b| *b == b'\n');
+            match newline {
+                Some(offset) => {
+                    let end = self.next + offset;
+                    let mut line: Vec<_> = buf.drain(..=end).collect();
+                    self.next = 0;
+                    if self.discarding {
+                        self.discarding = false;
+                        continue;
+                    }
+                    line.pop();
+                    return self.line(line).map(Some);
+                }
+                None if self.discarding => {
+                    buf.clear();
+                    return Ok(None);
+                }
+                // Allows the trailing `\r` of the max length line.
+                None if buf.len() > self.max.saturating_add(1) => {
+                    buf.clear();
+                    self.next = 0;
+                    self.discarding = true;
+                    return Err(Error::LineTooLong { max: self.max });
+                }
+                None => {
+                    self.next = buf.len();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<String>, Error> {
+        if let Some(line) = self.decode(buf)? {
+            return Ok(Some(line));
+        }
+        self.next = 0;
+        if self.discarding || buf.is_empty() {
+            self.discarding = false;
+            buf.clear();
+            return Ok(None);
+        }
+        // The last line without the newline.
+        let line = buf.split_off(0);
+        self.line(line).map(Some)
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for LinesCodec {
+    fn encode(&mut self, line: T, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let line = line.as_ref();
+        buf.reserve(line.len() + 1);
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinesCodec;
+    use crate::{Decoder, Encoder, Error};
+    #[test]
+    fn decode() {
+        struct Test {
+            name: &'static str,
+            max: usize,
+            chunks: &'static [&'static [u8]],
+            want: Vec<Result<&'static str, &'static str>>,
+        }
+        let tests = [
+            Test {
+                name: "no input",
+                max: 8,
+                chunks: &[],
+                want: vec![],
+            },
+            Test {
+                name: "single line",
+                max: 8,
+                chunks: &[b"hello\n"],
+                want: vec![Ok("hello")],
+            },
+            Test {
+                name: "lines in a chunk",
+                max: 8,
+                chunks: &[b"hello\r\nworld\n\n"],
+                want: vec![Ok("hello"), Ok("world"), Ok("")],
+            },
+            Test {
+                name: "line over chunks",
+                max: 8,
+                chunks: &[b"he", b"llo", b"\nworld"],
+                want: vec![Ok("hello"), Ok("world")],
+            },
+            Test {
+                name: "max length line",
+                max: 5,
+                chunks: &[b"hello\r", b"\nworld\n"],
+                want: vec![Ok("hello"), Ok("world")],
+            },
+            Test {
+                name: "too long line in a chunk",
+                max: 5,
+                chunks: &[b"hello world\nhello\n"],
+                want: vec![Err("line is longer than 5 bytes"), Ok("hello")],
+            },
+            Test {
+                name: "too long line over chunks",
+                max: 5,
+                chunks: &[b"hello ", b"wor", b"ld\nhel", b"lo\n"],
+                want: vec![Err("line is longer than 5 bytes"), Ok("hello")],
+            },
+            Test {
+                name: "too long last line",
+                max: 5,
+                chunks: &[b"hello\nhello world"],
+                want: vec![Ok("hello"), Err("line is longer than 5 bytes")],
+            },
+            Test {
+                name: "invalid UTF-8 line",
+                max: 8,
+                chunks: &[b"\xffhello\nworld\n"],
+                want: vec![
+                    Err("invalid line: invalid utf-8 sequence of 1 bytes from index 0"),
+                    Ok("world"),
+                ],
+            },
+        ];
+        for t in &tests {
+            let mut codec = LinesCodec::with_max_length(t.max);
+            let mut buf = Vec::new();
+            let mut got = Vec::new();
+            for chunk in t.chunks {
+                buf.extend_from_slice(chunk);
+                loop {
+                    match codec.decode(&mut buf) {
+                        Ok(Some(line)) => got.push(Ok(line)),
+                        Ok(None) => break,
+                        Err(err) => got.push(Err(err.to_string())),
+                    }
+                }
+            }
+            loop {
+                match codec.decode_eof(&mut buf) {
+                    Ok(Some(line)) => got.push(Ok(line)),
+                    Ok(None) => break,
+                    Err(err) => got.push(Err(err.to_string())),
+                }
+            }
+            let want: Vec<_> = t
+                .want
+                .iter()
+                .map(|r| r.map(String::from).map_err(String::from))
+                .collect();
+            assert_eq!(want, got, "{}", t.name);
+            assert!(buf.is_empty(), "{}", t.name);
+        }
+    }
+    #[test]
+    fn encode() {
+        let mut codec = LinesCodec::with_max_length(5);
+        let mut buf = Vec::new();
+        codec.encode("hello", &mut buf).unwrap();
+        codec.encode(String::from(""), &mut buf).unwrap();
+        assert_eq!(b"hello\n\n", &buf[..]);
+        match codec.decode(&mut b"hello world\n".to_vec()) {
+            Err(err @ Error::LineTooLong { .. }) => assert!(err.is_recoverable()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
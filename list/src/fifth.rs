@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Singly linked [`List`] queue with the raw pointers
+//!
+//! The [fifth list] of the book.  It pushes at the back through the raw
+//! tail pointer, and pops at the front, both in O(1).  All the links are
+//! the raw pointers from `Box::into_raw` so that the tail pointer doesn't
+//! alias with the `Box` owning the same node.  Run the tests with [Miri]
+//! to check the unsafe code:
+//!
+//! ```sh
+//! $ cargo +nightly miri test -p list-book fifth
+//! ```
+//!
+//! # Examples
+//!
+//! ```
+//! use list_book::fifth::List;
+//!
+//! let mut list = List::new();
+//! list.push(1);
+//! list.push(2);
+//! *list.peek_mut().unwrap() *= 10;
+//! assert_eq!(Some(10), list.pop());
+//! list.push(3);
+//! assert_eq!(vec![&2, &3], list.iter().collect::<Vec<_>>());
+//! ```
+//! [fifth list]: https://rust-unofficial.github.io/too-many-lists/fifth.html
+//! [miri]: https://github.com/rust-lang/miri
+//! [`list`]: struct.List.html
+use std::{fmt, marker::PhantomData, ptr};
+
+/// Singly linked queue.
+pub struct List<T> {
+    head: Link<T>,
+    tail: Link<T>,
+    len: usize,
+    /// Owns the nodes.
+    _marker: PhantomData<Box<Node<T>>>,
+}
+
+type Link<T> = *mut Node<T>;
+
+struct Node<T> {
+    elem: T,
+    next: Link<T>,
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+            len: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> List<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+    /// `push` pushes `elem` at the back.
+    pub fn push(&mut self, elem: T) {
+        let new_tail = Box::into_raw(Box::new(Node {
+            elem,
+            next: ptr::null_mut(),
+        }));
+        if self.tail.is_null() {
+            self.head = new_tail;
+        } else {
+            // SAFETY: `tail` points to the live node owned by the list.
+            unsafe { (*self.tail).next = new_tail };
+        }
+        self.tail = new_tail;
+        self.len += 1;
+    }
+    /// `pop` pops the element at the front.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.head.is_null() {
+            return None;
+        }
+        // SAFETY: `head` came from `Box::into_raw` and is unlinked here.
+        let head = unsafe { Box::from_raw(self.head) };
+        self.head = head.next;
+        if self.head.is_null() {
+            self.tail = ptr::null_mut();
+        }
+        self.len -= 1;
+        Some(head.elem)
+    }
+    pub fn peek(&self) -> Option<&T> {
+        // SAFETY: `head` is null or points to the live node.
+        unsafe { self.head.as_ref().map(|node| &node.elem) }
+    }
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        // SAFETY: `head` is null or points to the live node, borrowed
+        // mutably through `self`.
+        unsafe { self.head.as_mut().map(|node| &mut node.elem) }
+    }
+    /// `iter` returns the iterator from the front.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: self.head,
+            _marker: PhantomData,
+        }
+    }
+    /// `iter_mut` returns the mutable iterator from the front.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            next: self.head,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct IntoIter<T>(List<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for List<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+pub struct Iter<'a, T> {
+    next: Link<T>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: the nodes live while the list is borrowed for `'a`.
+        unsafe {
+            self.next.as_ref().map(|node| {
+                self.next = node.next;
+                &node.elem
+            })
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+pub struct IterMut<'a, T> {
+    next: Link<T>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: the nodes live while the list is borrowed mutably for
+        // `'a`, and each node is yielded once.
+        unsafe {
+            self.next.as_mut().map(|node| {
+                self.next = node.next;
+                &mut node.elem
+            })
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut List<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+    use std::collections::VecDeque;
+    #[test]
+    fn push_pop_and_peek() {
+        #[derive(Clone, Copy, Debug)]
+        enum Op {
+            Push(i32),
+            Pop,
+            PeekMut(i32),
+        }
+        struct Test {
+            name: &'static str,
+            ops: Vec<Op>,
+        }
+        use Op::*;
+        let tests = [
+            Test {
+                name: "pop from the empty list",
+                ops: vec![Pop, PeekMut(1), Pop],
+            },
+            Test {
+                name: "first in, first out",
+                ops: vec![Push(1), Push(2), Push(3), Pop, Pop, Pop, Pop],
+            },
+            Test {
+                name: "push after emptied",
+                ops: vec![Push(1), Pop, Pop, Push(2), Push(3), Pop, Push(4), Pop],
+            },
+            Test {
+                name: "peek_mut in between",
+                ops: vec![Push(1), PeekMut(10), Push(2), Pop, PeekMut(20), Pop, Pop],
+            },
+        ];
+        for t in &tests {
+            let mut list = List::new();
+            let mut want = VecDeque::new();
+            for op in &t.ops {
+                let msg = format!("{}: {:?}", t.name, op);
+                match *op {
+                    Push(v) => {
+                        list.push(v);
+                        want.push_back(v);
+                    }
+                    Pop => assert_eq!(want.pop_front(), list.pop(), "{}", msg),
+                    PeekMut(v) => {
+                        if let Some(elem) = list.peek_mut() {
+                            *elem = v;
+                        }
+                        if let Some(elem) = want.front_mut() {
+                            *elem = v;
+                        }
+                    }
+                }
+                assert_eq!(want.len(), list.len(), "{}", msg);
+                assert_eq!(want.is_empty(), list.is_empty(), "{}", msg);
+                assert_eq!(want.front(), list.peek(), "{}", msg);
+                assert!(want.iter().eq(list.iter()), "{}", msg);
+            }
+        }
+    }
+    #[test]
+    fn iterators() {
+        let mut list = List::new();
+        (1..=3).for_each(|v| list.push(v));
+        assert_eq!(vec![&1, &2, &3], list.iter().collect::<Vec<_>>());
+        for elem in &mut list {
+            *elem *= 10;
+        }
+        // Mixes the raw pointer accesses with the references.
+        let first = list.peek_mut().unwrap();
+        *first += 1;
+        list.push(40);
+        assert_eq!("[11, 20, 30, 40]", format!("{:?}", list));
+        let mut iter = list.into_iter();
+        assert_eq!((4, Some(4)), iter.size_hint());
+        assert_eq!(Some(11), iter.next());
+        assert_eq!(vec![20, 30, 40], iter.collect::<Vec<_>>());
+    }
+    #[test]
+    fn drop_elements() {
+        use std::rc::Rc;
+        let elem = Rc::new(());
+        let mut list = List::new();
+        (0..10).for_each(|_| list.push(elem.clone()));
+        list.pop();
+        assert_eq!(10, Rc::strong_count(&elem));
+        drop(list);
+        assert_eq!(1, Rc::strong_count(&elem));
+    }
+}
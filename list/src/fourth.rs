@@ -0,0 +1,518 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Doubly linked [`List`] deque with `Rc<RefCell>` links
+//!
+//! The [fourth list] of the book.  It pushes and pops at both ends in
+//! O(1), and the peeks return the `Ref` and `RefMut` guards instead of the
+//! references, as the nodes live in the `RefCell`s.  The [`CursorMut`]
+//! walks the list to insert and remove the elements in the middle.
+//!
+//! # Examples
+//!
+//! ```
+//! use list_book::fourth::List;
+//!
+//! let mut list = List::new();
+//! list.push_back(2);
+//! list.push_back(4);
+//! list.push_front(1);
+//! *list.peek_back_mut().unwrap() += 1;
+//! assert_eq!(5, *list.peek_back().unwrap());
+//!
+//! let mut cursor = list.cursor_front_mut();
+//! cursor.move_next();
+//! cursor.insert_after(3);
+//! assert_eq!(Some(2), cursor.remove_current());
+//! drop(cursor);
+//! assert_eq!(vec![1, 3, 5], list.into_iter().collect::<Vec<_>>());
+//! ```
+//! [fourth list]: https://rust-unofficial.github.io/too-many-lists/fourth.html
+//! [`list`]: struct.List.html
+//! [`cursormut`]: struct.CursorMut.html
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fmt,
+    rc::Rc,
+};
+
+/// Doubly linked deque.
+pub struct List<T> {
+    head: Link<T>,
+    tail: Link<T>,
+    len: usize,
+}
+
+type Link<T> = Option<Rc<RefCell<Node<T>>>>;
+
+struct Node<T> {
+    elem: T,
+    next: Link<T>,
+    prev: Link<T>,
+}
+
+impl<T> Node<T> {
+    fn new(elem: T) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            elem,
+            next: None,
+            prev: None,
+        }))
+    }
+    /// `into_elem` unwraps the node, which should be unlinked already.
+    fn into_elem(node: Rc<RefCell<Self>>) -> T {
+        match Rc::try_unwrap(node) {
+            Ok(node) => node.into_inner().elem,
+            Err(_) => unreachable!("node is still linked"),
+        }
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+        }
+    }
+}
+
+impl<T> List<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+    pub fn push_front(&mut self, elem: T) {
+        let new_head = Node::new(elem);
+        match self.head.take() {
+            Some(old_head) => {
+                old_head.borrow_mut().prev = Some(new_head.clone());
+                new_head.borrow_mut().next = Some(old_head);
+            }
+            None => self.tail = Some(new_head.clone()),
+        }
+        self.head = Some(new_head);
+        self.len += 1;
+    }
+    pub fn push_back(&mut self, elem: T) {
+        let new_tail = Node::new(elem);
+        match self.tail.take() {
+            Some(old_tail) => {
+                old_tail.borrow_mut().next = Some(new_tail.clone());
+                new_tail.borrow_mut().prev = Some(old_tail);
+            }
+            None => self.head = Some(new_tail.clone()),
+        }
+        self.tail = Some(new_tail);
+        self.len += 1;
+    }
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.head.take().map(|old_head| {
+            match old_head.borrow_mut().next.take() {
+                Some(new_head) => {
+                    new_head.borrow_mut().prev.take();
+                    self.head = Some(new_head);
+                }
+                None => {
+                    self.tail.take();
+                }
+            }
+            self.len -= 1;
+            Node::into_elem(old_head)
+        })
+    }
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.tail.take().map(|old_tail| {
+            match old_tail.borrow_mut().prev.take() {
+                Some(new_tail) => {
+                    new_tail.borrow_mut().next.take();
+                    self.tail = Some(new_tail);
+                }
+                None => {
+                    self.head.take();
+                }
+            }
+            self.len -= 1;
+            Node::into_elem(old_tail)
+        })
+    }
+    pub fn peek_front(&self) -> Option<Ref<'_, T>> {
+        self.head
+            .as_ref()
+            .map(|node| Ref::map(node.borrow(), |node| &node.elem))
+    }
+    pub fn peek_back(&self) -> Option<Ref<'_, T>> {
+        self.tail
+            .as_ref()
+            .map(|node| Ref::map(node.borrow(), |node| &node.elem))
+    }
+    pub fn peek_front_mut(&mut self) -> Option<RefMut<'_, T>> {
+        self.head
+            .as_ref()
+            .map(|node| RefMut::map(node.borrow_mut(), |node| &mut node.elem))
+    }
+    pub fn peek_back_mut(&mut self) -> Option<RefMut<'_, T>> {
+        self.tail
+            .as_ref()
+            .map(|node| RefMut::map(node.borrow_mut(), |node| &mut node.elem))
+    }
+    /// `cursor_front_mut` returns the cursor at the front element.
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        let cur = self.head.clone();
+        CursorMut {
+            index: cur.as_ref().map(|_| 0),
+            list: self,
+            cur,
+        }
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        // Breaks the `Rc` cycles between the nodes.
+        while self.pop_front().is_some() {}
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut list = f.debug_list();
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            list.entry(&node.borrow().elem);
+            cur = node.borrow().next.clone();
+        }
+        list.finish()
+    }
+}
+
+pub struct IntoIter<T>(List<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for List<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+/// Cursor to walk the [`List`] and to insert and remove the elements.
+///
+/// The cursor points to an element, or to the "ghost" position past the
+/// back and before the front, as `std::collections::LinkedList`'s does.
+///
+/// [`list`]: struct.List.html
+pub struct CursorMut<'a, T> {
+    list: &'a mut List<T>,
+    cur: Link<T>,
+    index: Option<usize>,
+}
+
+/// Keeps the list borrowed while the cursor holds the current node, so
+/// that the list doesn't pop it from under the cursor.
+impl<T> Drop for CursorMut<'_, T> {
+    fn drop(&mut self) {}
+}
+
+impl<'a, T> CursorMut<'a, T> {
+    /// `index` returns the index of the current element, or `None` at the
+    /// ghost position.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+    pub fn current(&mut self) -> Option<RefMut<'_, T>> {
+        self.cur
+            .as_ref()
+            .map(|node| RefMut::map(node.borrow_mut(), |node| &mut node.elem))
+    }
+    /// `move_next` moves to the next element, from the back to the ghost
+    /// position and from the ghost position to the front.
+    pub fn move_next(&mut self) {
+        self.cur = match self.cur.take() {
+            Some(node) => node.borrow().next.clone(),
+            None => self.list.head.clone(),
+        };
+        self.index = match (&self.cur, self.index) {
+            (None, _) => None,
+            (Some(_), Some(index)) => Some(index + 1),
+            (Some(_), None) => Some(0),
+        };
+    }
+    /// `move_prev` moves to the previous element, from the front to the
+    /// ghost position and from the ghost position to the back.
+    pub fn move_prev(&mut self) {
+        self.cur = match self.cur.take() {
+            Some(node) => node.borrow().prev.clone(),
+            None => self.list.tail.clone(),
+        };
+        self.index = match (&self.cur, self.index) {
+            (None, _) => None,
+            (Some(_), Some(index)) => Some(index - 1),
+            (Some(_), None) => Some(self.list.len - 1),
+        };
+    }
+    /// `insert_before` inserts `elem` before the current element, or at the
+    /// back at the ghost position.
+    pub fn insert_before(&mut self, elem: T) {
+        let cur = match &self.cur {
+            Some(cur) => cur,
+            None => return self.list.push_back(elem),
+        };
+        let prev = cur.borrow().prev.clone();
+        match prev {
+            Some(prev) => {
+                let node = Node::new(elem);
+                node.borrow_mut().prev = Some(prev.clone());
+                node.borrow_mut().next = Some(cur.clone());
+                prev.borrow_mut().next = Some(node.clone());
+                cur.borrow_mut().prev = Some(node);
+                self.list.len += 1;
+            }
+            None => self.list.push_front(elem),
+        }
+        self.index = self.index.map(|index| index + 1);
+    }
+    /// `insert_after` inserts `elem` after the current element, or at the
+    /// front at the ghost position.
+    pub fn insert_after(&mut self, elem: T) {
+        let cur = match &self.cur {
+            Some(cur) => cur,
+            None => return self.list.push_front(elem),
+        };
+        let next = cur.borrow().next.clone();
+        match next {
+            Some(next) => {
+                let node = Node::new(elem);
+                node.borrow_mut().prev = Some(cur.clone());
+                node.borrow_mut().next = Some(next.clone());
+                next.borrow_mut().prev = Some(node.clone());
+                cur.borrow_mut().next = Some(node);
+                self.list.len += 1;
+            }
+            None => self.list.push_back(elem),
+        }
+    }
+    /// `remove_current` removes the current element, and moves to the next
+    /// one.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let node = self.cur.take()?;
+        let prev = node.borrow_mut().prev.take();
+        let next = node.borrow_mut().next.take();
+        match &prev {
+            Some(prev) => prev.borrow_mut().next = next.clone(),
+            None => self.list.head = next.clone(),
+        }
+        match &next {
+            Some(next) => next.borrow_mut().prev = prev,
+            None => self.list.tail = prev,
+        }
+        if next.is_none() {
+            self.index = None;
+        }
+        self.cur = next;
+        self.list.len -= 1;
+        Some(Node::into_elem(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+    use std::collections::VecDeque;
+    #[test]
+    fn push_pop_and_peek() {
+        #[derive(Clone, Copy, Debug)]
+        enum Op {
+            PushFront(i32),
+            PushBack(i32),
+            PopFront,
+            PopBack,
+        }
+        struct Test {
+            name: &'static str,
+            ops: Vec<Op>,
+        }
+        use Op::*;
+        let tests = [
+            Test {
+                name: "pops from the empty list",
+                ops: vec![PopFront, PopBack],
+            },
+            Test {
+                name: "push and pop at the front",
+                ops: vec![PushFront(1), PushFront(2), PopFront, PopFront, PopFront],
+            },
+            Test {
+                name: "push and pop at the back",
+                ops: vec![PushBack(1), PushBack(2), PopBack, PopBack, PopBack],
+            },
+            Test {
+                name: "push at the front and pop at the back",
+                ops: vec![PushFront(1), PushFront(2), PopBack, PopBack, PopBack],
+            },
+            Test {
+                name: "mixed",
+                ops: vec![
+                    PushBack(1),
+                    PushFront(2),
+                    PushBack(3),
+                    PopFront,
+                    PushFront(4),
+                    PopBack,
+                    PopBack,
+                    PushBack(5),
+                    PopFront,
+                    PopFront,
+                    PopBack,
+                ],
+            },
+        ];
+        for t in &tests {
+            let mut list = List::new();
+            let mut want = VecDeque::new();
+            for op in &t.ops {
+                let msg = format!("{}: {:?}", t.name, op);
+                match *op {
+                    PushFront(v) => {
+                        list.push_front(v);
+                        want.push_front(v);
+                    }
+                    PushBack(v) => {
+                        list.push_back(v);
+                        want.push_back(v);
+                    }
+                    PopFront => assert_eq!(want.pop_front(), list.pop_front(), "{}", msg),
+                    PopBack => assert_eq!(want.pop_back(), list.pop_back(), "{}", msg),
+                }
+                assert_eq!(want.len(), list.len(), "{}", msg);
+                assert_eq!(want.front(), list.peek_front().as_deref(), "{}", msg);
+                assert_eq!(want.back(), list.peek_back().as_deref(), "{}", msg);
+            }
+        }
+    }
+    #[test]
+    fn peek_mut() {
+        let mut list = List::new();
+        assert!(list.peek_front_mut().is_none());
+        assert!(list.peek_back_mut().is_none());
+        list.push_back(1);
+        list.push_back(2);
+        *list.peek_front_mut().unwrap() *= 10;
+        *list.peek_back_mut().unwrap() *= 10;
+        assert_eq!("[10, 20]", format!("{:?}", list));
+    }
+    #[test]
+    fn into_iter() {
+        let list: List<_> = {
+            let mut list = List::new();
+            (1..=4).for_each(|v| list.push_back(v));
+            list
+        };
+        let mut iter = list.into_iter();
+        assert_eq!((4, Some(4)), iter.size_hint());
+        assert_eq!(Some(1), iter.next());
+        assert_eq!(Some(4), iter.next_back());
+        assert_eq!(vec![2, 3], iter.collect::<Vec<_>>());
+    }
+    #[test]
+    fn cursor() {
+        let mut list = List::new();
+        (1..=3).for_each(|v| list.push_back(v));
+        let mut cursor = list.cursor_front_mut();
+        assert_eq!(Some(0), cursor.index());
+        assert_eq!(1, *cursor.current().unwrap());
+        // Walks through the ghost position.
+        cursor.move_prev();
+        assert_eq!(None, cursor.index());
+        assert!(cursor.current().is_none());
+        cursor.move_prev();
+        assert_eq!(Some(2), cursor.index());
+        assert_eq!(3, *cursor.current().unwrap());
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(Some(0), cursor.index());
+        // Pops the current element only after the cursor is dropped.
+        assert_eq!(1, *cursor.current().unwrap());
+
+        // Inserts in the middle and at both ends.
+        cursor.move_next();
+        cursor.insert_before(10);
+        cursor.insert_after(20);
+        assert_eq!(Some(2), cursor.index());
+        *cursor.current().unwrap() *= 100;
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.insert_before(0);
+        assert_eq!(Some(1), cursor.index());
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.insert_before(30);
+        cursor.insert_after(-1);
+        assert_eq!(None, cursor.index());
+        drop(cursor);
+        assert_eq!("[-1, 0, 1, 10, 200, 20, 3, 30]", format!("{:?}", list));
+
+        // Removes from the middle and at both ends.
+        let mut cursor = list.cursor_front_mut();
+        assert_eq!(Some(-1), cursor.remove_current());
+        assert_eq!(Some(0), cursor.index());
+        cursor.move_next();
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(Some(200), cursor.remove_current());
+        assert_eq!(Some(20), cursor.remove_current());
+        assert_eq!(Some(3), cursor.index());
+        cursor.move_next();
+        assert_eq!(Some(30), cursor.remove_current());
+        assert_eq!(None, cursor.index());
+        assert_eq!(None, cursor.remove_current());
+        drop(cursor);
+        assert_eq!(4, list.len());
+        assert_eq!(3, *list.peek_back().unwrap());
+        assert_eq!(
+            vec![3, 10, 1, 0],
+            list.into_iter().rev().collect::<Vec<_>>()
+        );
+    }
+    #[test]
+    fn cursor_remove_all() {
+        let mut list = List::new();
+        (0..5).for_each(|v| list.push_front(v));
+        let mut cursor = list.cursor_front_mut();
+        let got: Vec<_> = std::iter::from_fn(|| cursor.remove_current()).collect();
+        assert_eq!(vec![4, 3, 2, 1, 0], got);
+        drop(cursor);
+        assert!(list.is_empty());
+        assert!(list.peek_front().is_none());
+        assert!(list.peek_back().is_none());
+    }
+    #[test]
+    fn long_list() {
+        // Drops without the stack overflow.
+        let mut list = List::new();
+        (0..100_000).for_each(|v| list.push_back(v));
+        assert_eq!(100_000, list.len());
+    }
+}
@@ -1,3 +1,9 @@
 // SPDX-License-Identifier: GPL-2.0
+//! [Too Many Lists] book
+//!
+//! [too many lists]: https://rust-unofficial.github.io/too-many-lists/
+pub mod fifth;
 mod first;
-mod second;
+pub mod fourth;
+pub mod second;
+pub mod third;
@@ -1,8 +1,34 @@
 // SPDX-License-Identifier: GPL-2.0
-pub struct IntoIter<T>(List<T>);
+//! [`List`] stack with the `Option<Box>` links
+//!
+//! The [second list] of the book with the iterators.  It's the last in,
+//! first out stack, and the iterators walk it from the top.
+//!
+//! # Examples
+//!
+//! ```
+//! use list_book::second::List;
+//!
+//! let mut list: List<_> = vec![1, 2, 3].into_iter().collect();
+//! list.push(4);
+//! assert_eq!(4, list.len());
+//! assert_eq!(Some(&4), list.peek());
+//! for elem in list.iter_mut() {
+//!     *elem *= 10;
+//! }
+//! assert_eq!(vec![&40, &30, &20, &10], list.iter().collect::<Vec<_>>());
+//! list.reverse();
+//! assert_eq!(Some(10), list.pop());
+//! assert_eq!("[20, 30, 40]", format!("{:?}", list));
+//! ```
+//! [second list]: https://rust-unofficial.github.io/too-many-lists/second.html
+//! [`list`]: struct.List.html
+use std::{fmt, iter::FromIterator, mem};
 
+/// Singly linked stack.
 pub struct List<T> {
     head: Link<T>,
+    len: usize,
 }
 
 type Link<T> = Option<Box<Node<T>>>;
@@ -12,37 +38,59 @@ struct Node<T> {
     next: Link<T>,
 }
 
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        Self { head: None, len: 0 }
+    }
+}
+
 impl<T> List<T> {
-    #[allow(dead_code)]
     pub fn new() -> Self {
-        List { head: None }
+        Self::default()
+    }
+    /// `len` returns the number of the elements in O(1).
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
     }
-    #[allow(dead_code)]
     pub fn push(&mut self, elem: T) {
         let new_node = Box::new(Node {
             elem,
             next: self.head.take(),
         });
         self.head = Some(new_node);
+        self.len += 1;
     }
-    #[allow(dead_code)]
     pub fn pop(&mut self) -> Option<T> {
         self.head.take().map(|node| {
             self.head = node.next;
+            self.len -= 1;
             node.elem
         })
     }
-    #[allow(dead_code)]
     pub fn peek(&self) -> Option<&T> {
         self.head.as_ref().map(|node| &node.elem)
     }
-    #[allow(dead_code)]
     pub fn peek_mut(&mut self) -> Option<&mut T> {
         self.head.as_mut().map(|node| &mut node.elem)
     }
-    #[allow(dead_code)]
-    pub fn into_iter(self) -> IntoIter<T> {
-        IntoIter(self)
+    /// `reverse` reverses the list in place, without allocation.
+    pub fn reverse(&mut self) {
+        let mut cur_link = self.head.take();
+        while let Some(mut boxed_node) = cur_link {
+            cur_link = mem::replace(&mut boxed_node.next, self.head.take());
+            self.head = Some(boxed_node);
+        }
+    }
+    /// `iter` returns the iterator from the top of the stack.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter(self.head.as_deref())
+    }
+    /// `iter_mut` returns the mutable iterator from the top of the stack.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut(self.head.as_deref_mut())
     }
 }
 
@@ -55,11 +103,115 @@ impl<T> Drop for List<T> {
     }
 }
 
+impl<T: Clone> Clone for List<T> {
+    fn clone(&self) -> Self {
+        // Appends to the tail to keep the order without the recursion.
+        let mut list = Self::new();
+        let mut tail = &mut list.head;
+        for elem in self.iter() {
+            let node = tail.get_or_insert(Box::new(Node {
+                elem: elem.clone(),
+                next: None,
+            }));
+            tail = &mut node.next;
+        }
+        list.len = self.len;
+        list
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for List<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for List<T> {}
+
+/// Pushes the elements in the iterator order, e.g. the last one is on the
+/// top, as `Vec` does.
+impl<T> FromIterator<T> for List<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = Self::new();
+        list.extend(iter);
+        list
+    }
+}
+
+impl<T> Extend<T> for List<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for elem in iter {
+            self.push(elem);
+        }
+    }
+}
+
+pub struct IntoIter<T>(List<T>);
+
 impl<T> Iterator for IntoIter<T> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
         self.0.pop()
     }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for List<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+pub struct Iter<'a, T>(Option<&'a Node<T>>);
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.map(|node| {
+            self.0 = node.next.as_deref();
+            &node.elem
+        })
+    }
+}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+pub struct IterMut<'a, T>(Option<&'a mut Node<T>>);
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.take().map(|node| {
+            self.0 = node.next.as_deref_mut();
+            &mut node.elem
+        })
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut List<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
 }
 
 #[cfg(test)]
@@ -406,4 +558,152 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn iter_and_iter_mut() {
+        struct Test {
+            name: &'static str,
+            data: Vec<i32>,
+            want: Vec<i32>,
+        }
+        let tests = [
+            Test {
+                name: "empty list",
+                data: vec![],
+                want: vec![],
+            },
+            Test {
+                name: "single element",
+                data: vec![1],
+                want: vec![1],
+            },
+            Test {
+                name: "three elements from the top",
+                data: vec![1, 2, 3],
+                want: vec![3, 2, 1],
+            },
+        ];
+        for t in &tests {
+            let mut list: List<_> = t.data.iter().copied().collect();
+            assert_eq!(t.data.len(), list.len(), "{}", t.name);
+            let got: Vec<_> = list.iter().copied().collect();
+            assert_eq!(t.want, got, "{}", t.name);
+            for elem in &mut list {
+                *elem *= 10;
+            }
+            let got: Vec<_> = (&list).into_iter().map(|elem| elem / 10).collect();
+            assert_eq!(t.want, got, "{}", t.name);
+            // Borrowing iterators don't consume the list.
+            assert_eq!(t.data.len(), list.len(), "{}", t.name);
+        }
+    }
+    #[test]
+    fn reverse() {
+        struct Test {
+            name: &'static str,
+            data: Vec<i32>,
+            want: Vec<i32>,
+        }
+        let tests = [
+            Test {
+                name: "empty list",
+                data: vec![],
+                want: vec![],
+            },
+            Test {
+                name: "single element",
+                data: vec![1],
+                want: vec![1],
+            },
+            Test {
+                name: "three elements",
+                data: vec![1, 2, 3],
+                want: vec![1, 2, 3],
+            },
+        ];
+        for t in &tests {
+            let mut list: List<_> = t.data.iter().copied().collect();
+            list.reverse();
+            let got: Vec<_> = list.into_iter().collect();
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn traits() {
+        let list: List<_> = vec!["a", "b"].into_iter().collect();
+        let mut cloned = list.clone();
+        assert_eq!(list, cloned);
+        assert_eq!("[\"b\", \"a\"]", format!("{:?}", cloned));
+        cloned.extend(vec!["c"]);
+        assert_ne!(list, cloned);
+        assert_eq!(Some("c"), cloned.pop());
+        assert_eq!(list, cloned);
+        assert_eq!(List::<i32>::new(), List::default());
+        // Drops the long list without the stack overflow.
+        let long: List<_> = (0..1_000_000).collect();
+        assert_eq!(long, long.clone());
+    }
+    /// Checks the list against `Vec` used as a stack with the random
+    /// operations.
+    #[test]
+    fn vec_stack_property() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        #[derive(Debug)]
+        enum Op {
+            Push(u8),
+            Pop,
+            PeekMut(u8),
+            Reverse,
+            Extend(Vec<u8>),
+            Clone,
+        }
+        for seed in 0..100 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut list = List::new();
+            let mut stack = Vec::new();
+            for _ in 0..rng.gen_range(0..200) {
+                let op = match rng.gen_range(0..10) {
+                    0..=3 => Op::Push(rng.gen()),
+                    4..=6 => Op::Pop,
+                    7 => Op::PeekMut(rng.gen()),
+                    8 => Op::Reverse,
+                    _ if rng.gen() => Op::Clone,
+                    _ => Op::Extend((0..rng.gen_range(0..5)).map(|_| rng.gen()).collect()),
+                };
+                let msg = format!("seed={} op={:?}", seed, op);
+                match op {
+                    Op::Push(v) => {
+                        list.push(v);
+                        stack.push(v);
+                    }
+                    Op::Pop => assert_eq!(stack.pop(), list.pop(), "{}", msg),
+                    Op::PeekMut(v) => {
+                        if let Some(elem) = list.peek_mut() {
+                            *elem = v;
+                        }
+                        if let Some(elem) = stack.last_mut() {
+                            *elem = v;
+                        }
+                    }
+                    Op::Reverse => {
+                        list.reverse();
+                        stack.reverse();
+                    }
+                    Op::Extend(values) => {
+                        list.extend(values.iter().copied());
+                        stack.extend(values);
+                    }
+                    Op::Clone => list = list.clone(),
+                }
+                assert_eq!(stack.len(), list.len(), "{}", msg);
+                assert_eq!(stack.is_empty(), list.is_empty(), "{}", msg);
+                assert_eq!(stack.last(), list.peek(), "{}", msg);
+                assert!(stack.iter().rev().eq(list.iter()), "{}", msg);
+            }
+            let want: List<_> = stack.iter().copied().collect();
+            assert_eq!(want, list, "seed={}", seed);
+            let got: Vec<_> = list.into_iter().collect();
+            stack.reverse();
+            assert_eq!(stack, got, "seed={}", seed);
+        }
+    }
 }
@@ -0,0 +1,297 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Persistent [`List`] with the shared tails
+//!
+//! The [third list] of the book.  The list is immutable, and [`prepend`]
+//! and [`tail`] return the new list sharing the rest of the nodes, which
+//! makes [`clone`] O(1).  The nodes are shared by `Rc`, and by `Arc` in
+//! the [`sync`] variant to share the lists across the threads.
+//!
+//! # Examples
+//!
+//! ```
+//! use list_book::third::List;
+//!
+//! let list = List::new().prepend(1).prepend(2);
+//! let shared = list.prepend(3);
+//! assert_eq!(Some(&2), list.head());
+//! assert_eq!(vec![&3, &2, &1], shared.iter().collect::<Vec<_>>());
+//! assert!(shared.tail().ptr_eq(&list));
+//! ```
+//! [third list]: https://rust-unofficial.github.io/too-many-lists/third.html
+//! [`list`]: struct.List.html
+//! [`prepend`]: struct.List.html#method.prepend
+//! [`tail`]: struct.List.html#method.tail
+//! [`clone`]: struct.List.html#impl-Clone
+//! [`sync`]: sync/index.html
+
+/// Defines the list over the `$rc` reference counted pointer.
+macro_rules! persistent_list {
+    ($rc:ident) => {
+        use std::fmt;
+
+        /// Persistent singly linked list.
+        pub struct List<T> {
+            head: Link<T>,
+        }
+
+        type Link<T> = Option<$rc<Node<T>>>;
+
+        struct Node<T> {
+            elem: T,
+            next: Link<T>,
+        }
+
+        impl<T> Default for List<T> {
+            fn default() -> Self {
+                Self { head: None }
+            }
+        }
+
+        impl<T> List<T> {
+            pub fn new() -> Self {
+                Self::default()
+            }
+            /// `prepend` returns the new list with `elem` in front of this
+            /// list.
+            pub fn prepend(&self, elem: T) -> Self {
+                Self {
+                    head: Some($rc::new(Node {
+                        elem,
+                        next: self.head.clone(),
+                    })),
+                }
+            }
+            /// `tail` returns the list without the first element, which is
+            /// empty for the empty list.
+            pub fn tail(&self) -> Self {
+                Self {
+                    head: self.head.as_ref().and_then(|node| node.next.clone()),
+                }
+            }
+            pub fn head(&self) -> Option<&T> {
+                self.head.as_ref().map(|node| &node.elem)
+            }
+            pub fn is_empty(&self) -> bool {
+                self.head.is_none()
+            }
+            /// `ptr_eq` returns `true` in case both lists share the same
+            /// nodes.
+            pub fn ptr_eq(&self, other: &Self) -> bool {
+                match (&self.head, &other.head) {
+                    (Some(a), Some(b)) => $rc::ptr_eq(a, b),
+                    (None, None) => true,
+                    _ => false,
+                }
+            }
+            pub fn iter(&self) -> Iter<'_, T> {
+                Iter(self.head.as_deref())
+            }
+        }
+
+        /// Shares the nodes in O(1).
+        impl<T> Clone for List<T> {
+            fn clone(&self) -> Self {
+                Self {
+                    head: self.head.clone(),
+                }
+            }
+        }
+
+        impl<T> Drop for List<T> {
+            fn drop(&mut self) {
+                // Stops at the node shared by the other list.  Unlike
+                // `try_unwrap`, `into_inner` never lets the concurrent drops
+                // of the last two lists both stop, and leave the rest to
+                // the recursive drop.
+                let mut cur_link = self.head.take();
+                while let Some(node) = cur_link {
+                    cur_link = $rc::into_inner(node).and_then(|mut node| node.next.take());
+                }
+            }
+        }
+
+        impl<T: fmt::Debug> fmt::Debug for List<T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_list().entries(self.iter()).finish()
+            }
+        }
+
+        pub struct Iter<'a, T>(Option<&'a Node<T>>);
+
+        impl<'a, T> Iterator for Iter<'a, T> {
+            type Item = &'a T;
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0.map(|node| {
+                    self.0 = node.next.as_deref();
+                    &node.elem
+                })
+            }
+        }
+
+        impl<'a, T> IntoIterator for &'a List<T> {
+            type Item = &'a T;
+            type IntoIter = Iter<'a, T>;
+            fn into_iter(self) -> Iter<'a, T> {
+                self.iter()
+            }
+        }
+    };
+}
+
+use std::rc::Rc;
+
+persistent_list!(Rc);
+
+/// [`List`] shared across the threads
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+///
+/// use list_book::third::sync::List;
+///
+/// let list = List::new().prepend(1).prepend(2);
+/// let handles: Vec<_> = (3..5)
+///     .map(|i| {
+///         let list = list.clone();
+///         thread::spawn(move || list.prepend(i).iter().sum::<i32>())
+///     })
+///     .collect();
+/// let sums: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+/// assert_eq!(vec![6, 7], sums);
+/// ```
+/// [`list`]: struct.List.html
+pub mod sync {
+    use std::sync::Arc;
+
+    persistent_list!(Arc);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+    #[test]
+    fn prepend_head_and_tail() {
+        struct Test {
+            name: &'static str,
+            data: Vec<i32>,
+            tails: usize,
+            want: Option<i32>,
+        }
+        let tests = [
+            Test {
+                name: "empty list",
+                data: vec![],
+                tails: 0,
+                want: None,
+            },
+            Test {
+                name: "tail of the empty list",
+                data: vec![],
+                tails: 2,
+                want: None,
+            },
+            Test {
+                name: "three elements",
+                data: vec![1, 2, 3],
+                tails: 0,
+                want: Some(3),
+            },
+            Test {
+                name: "tail of three elements",
+                data: vec![1, 2, 3],
+                tails: 1,
+                want: Some(2),
+            },
+            Test {
+                name: "three tails of three elements",
+                data: vec![1, 2, 3],
+                tails: 3,
+                want: None,
+            },
+        ];
+        for t in &tests {
+            let mut list = t.data.iter().fold(List::new(), |list, v| list.prepend(*v));
+            for _ in 0..t.tails {
+                list = list.tail();
+            }
+            assert_eq!(t.want.as_ref(), list.head(), "{}", t.name);
+            assert_eq!(t.want.is_none(), list.is_empty(), "{}", t.name);
+            let want: Vec<_> = t.data.iter().rev().skip(t.tails).collect();
+            let got: Vec<_> = list.iter().collect();
+            assert_eq!(want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn sharing() {
+        let base = List::new().prepend(String::from("a"));
+        let b = base.prepend(String::from("b"));
+        let c = base.prepend(String::from("c"));
+        assert!(b.tail().ptr_eq(&base));
+        assert!(c.tail().ptr_eq(&b.tail()));
+        assert!(!b.ptr_eq(&c));
+        assert!(b.clone().ptr_eq(&b));
+        assert!(List::<i32>::new().ptr_eq(&List::new()));
+        // Dropping the list keeps the shared tail.
+        drop(base);
+        drop(b);
+        assert_eq!("[\"c\", \"a\"]", format!("{:?}", c));
+    }
+    #[test]
+    fn long_list() {
+        let long = (0..1_000_000).fold(List::new(), |list, v| list.prepend(v));
+        let shared = long.prepend(-1);
+        // Drops without the stack overflow, keeping the shared nodes.
+        drop(long);
+        assert_eq!(1_000_001, shared.iter().count());
+        drop(shared);
+    }
+    #[test]
+    fn sync() {
+        use super::sync::List;
+        use std::thread;
+        let list = (0..1_000).fold(List::new(), |list, v| list.prepend(v));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    let list = list.prepend(i);
+                    assert!(list.tail().ptr_eq(&list.tail()));
+                    list.iter().sum::<i32>()
+                })
+            })
+            .collect();
+        let sums: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(vec![499_500, 499_501, 499_502, 499_503], sums);
+    }
+    #[test]
+    fn sync_long_list() {
+        use super::sync::List;
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+        for _ in 0..200 {
+            let long = (0..10_000).fold(List::new(), |list, v| list.prepend(v));
+            let barrier = Arc::new(Barrier::new(2));
+            // Drops the last two lists sharing the tail at the same time,
+            // on the small stacks to overflow on the recursive drop.
+            let handles: Vec<_> = (0..2)
+                .map(|i| {
+                    let list = long.prepend(i);
+                    let barrier = barrier.clone();
+                    thread::Builder::new()
+                        .stack_size(64 * 1024)
+                        .spawn(move || {
+                            barrier.wait();
+                            drop(list);
+                        })
+                        .unwrap()
+                })
+                .collect();
+            drop(long);
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        }
+    }
+}
@@ -50,7 +50,8 @@ use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use stjepang_blog::post20200125::{v1, v2, v3, v4};
+use stjepang_blog::post20200125::{v1, v2, v3, v4, v5};
+use stjepang_blog::post20200131::v4::Executor;
 
 use test::Bencher;
 
@@ -114,6 +115,63 @@ fn custom_v4_block_on_50_yields(b: &mut Bencher) {
     b.iter(|| v4::block_on(Yields(50)));
 }
 
+#[bench]
+fn custom_v5_block_on_0_yields(b: &mut Bencher) {
+    b.iter(|| v5::block_on(Yields(0)));
+}
+
+#[bench]
+fn custom_v5_block_on_10_yields(b: &mut Bencher) {
+    b.iter(|| v5::block_on(Yields(10)));
+}
+
+#[bench]
+fn custom_v5_block_on_50_yields(b: &mut Bencher) {
+    b.iter(|| v5::block_on(Yields(50)));
+}
+
+#[bench]
+fn custom_v5_nested_block_on_0_yields(b: &mut Bencher) {
+    b.iter(|| v5::block_on(async { v5::block_on(Yields(0)) }));
+}
+
+#[bench]
+fn custom_v5_nested_block_on_10_yields(b: &mut Bencher) {
+    b.iter(|| v5::block_on(async { v5::block_on(Yields(10)) }));
+}
+
+#[bench]
+fn custom_v5_nested_block_on_50_yields(b: &mut Bencher) {
+    b.iter(|| v5::block_on(async { v5::block_on(Yields(50)) }));
+}
+
+#[bench]
+fn custom_v5_block_on_with_0_yields(b: &mut Bencher) {
+    let executor = Executor::builder().threads(1).build();
+    b.iter(|| {
+        let handle = executor.spawn(Yields(0));
+        v5::block_on_with(&executor, handle).unwrap()
+    });
+}
+
+#[bench]
+fn custom_v5_block_on_with_10_yields(b: &mut Bencher) {
+    let executor = Executor::builder().threads(1).build();
+    b.iter(|| {
+        let handle = executor.spawn(Yields(10));
+        v5::block_on_with(&executor, handle).unwrap()
+    });
+}
+
+#[bench]
+fn custom_v5_block_on_with_50_yields(b: &mut Bencher) {
+    let executor = Executor::builder().threads(1).build();
+    b.iter(|| {
+        let handle = executor.spawn(Yields(50));
+        v5::block_on_with(&executor, handle).unwrap()
+    });
+}
+
 #[bench]
 fn futures_block_on_0_yields(b: &mut Bencher) {
     b.iter(|| futures::executor::block_on(Yields(0)));
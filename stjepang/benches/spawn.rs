@@ -10,6 +10,7 @@ use core::task::{Context, Poll};
 
 use stjepang_blog::post20200125::v4::block_on;
 use stjepang_blog::post20200131::{v1, v2, v3, v4};
+use v4::{Executor, Priority};
 
 use test::Bencher;
 
@@ -40,6 +41,40 @@ fn custom_v4_spawn_50_yields(b: &mut Bencher) {
     });
 }
 
+/// Latency of a high priority task among the flood of the yielding tasks.
+#[bench]
+fn custom_v4_spawn_high_among_50_yields(b: &mut Bencher) {
+    let executor = Executor::builder().threads(2).build();
+    b.iter(|| {
+        block_on(async {
+            for _ in 0..100 {
+                executor.spawn(Yields(50)).detach();
+            }
+            executor
+                .spawn_with_priority(Priority::High, Yields(0))
+                .await
+                .unwrap();
+        })
+    });
+}
+
+/// Latency of a normal priority task among the flood of the yielding tasks.
+#[bench]
+fn custom_v4_spawn_normal_among_50_yields(b: &mut Bencher) {
+    let executor = Executor::builder().threads(2).build();
+    b.iter(|| {
+        block_on(async {
+            for _ in 0..100 {
+                executor.spawn(Yields(50)).detach();
+            }
+            executor
+                .spawn_with_priority(Priority::Normal, Yields(0))
+                .await
+                .unwrap();
+        })
+    });
+}
+
 #[bench]
 fn custom_v3_spawn_0_yields(b: &mut Bencher) {
     b.iter(|| {
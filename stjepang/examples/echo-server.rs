@@ -0,0 +1,35 @@
+//! Echo server purely on our own [executor] and the [mio] reactor
+//!
+//! [executor]: https://stjepang.github.io/2020/01/31/build-your-own-executor.html
+//! [mio]: https://lib.rs/mio/latest/mio/index.html
+use std::{env, io, net::SocketAddr};
+
+use futures::io::copy;
+
+use stjepang_blog::post20200125::v5::block_on_with;
+use stjepang_blog::post20200131::v4::Executor;
+use stjepang_blog::reactor::{Async, TcpListener};
+
+fn main() -> io::Result<()> {
+    let addr: SocketAddr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("127.0.0.1:8080"))
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let executor = Executor::builder().name("echo").build();
+    block_on_with(&executor, async {
+        let listener = Async::<TcpListener>::bind(addr)?;
+        println!("listening on {}", listener.get_ref().local_addr()?);
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            executor
+                .spawn(async move {
+                    match copy(&stream, &mut &stream).await {
+                        Ok(n) => println!("{}: echoed {} bytes", peer, n),
+                        Err(err) => eprintln!("{}: {}", peer, err),
+                    }
+                })
+                .detach();
+        }
+    })
+}
@@ -3,3 +3,4 @@
 //! [blog]: https://stjepang.github.io/
 pub mod post20200125;
 pub mod post20200131;
+pub mod reactor;
@@ -0,0 +1,195 @@
+//! Build your own [block_on()] with reentrant block_on() call
+//!
+//! [block_on()]: https://stjepang.github.io/2020/01/25/build-your-own-block-on.html
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use crossbeam_utils::sync::Parker;
+
+use crate::post20200131::v4::Executor;
+
+/// Build your own [block_on()] with reentrant block_on() call
+///
+/// The nested `block_on()` call, e.g. `block_on()` call inside the
+/// `Future`, drives the inner future on the same thread, while the outer
+/// future waits for it to complete.
+///
+/// # Examples
+///
+/// ```
+/// use futures::channel::oneshot;
+/// use std::thread;
+/// use std::time::Duration;
+/// use stjepang_blog::post20200125::v5::block_on;
+///
+/// let (tx, rx) = oneshot::channel();
+///
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(1));
+///     tx.send("Hello block_on, which survives recursion").unwrap();
+/// });
+///
+/// let msg = block_on(async {
+///     // Blocks the outer future until the inner one completes.
+///     block_on(async { rx.await.unwrap() })
+/// });
+/// assert_eq!("Hello block_on, which survives recursion", msg);
+/// ```
+/// [block_on()]: https://stjepang.github.io/2020/01/25/build-your-own-block-on.html
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    run(future, || false)
+}
+
+/// `block_on_with` blocks on `future` and runs the tasks of `executor`
+/// on the current thread while the future is waiting to be woken up.
+///
+/// # Examples
+///
+/// ```
+/// use stjepang_blog::post20200125::v5::block_on_with;
+/// use stjepang_blog::post20200131::v4::Executor;
+///
+/// let executor = Executor::builder().threads(1).build();
+/// let handle = executor.spawn(async { 1 + 2 });
+/// assert_eq!(3, block_on_with(&executor, handle).unwrap());
+/// ```
+pub fn block_on_with<F: Future>(executor: &Executor, future: F) -> F::Output {
+    run(future, || executor.run_one())
+}
+
+/// `run` drives `future` to completion on the current thread.
+///
+/// It calls `run_one` while the future is pending and parks the thread
+/// once `run_one` returns `false`, i.e. there is nothing else to do.
+pub(crate) fn run<F: Future>(future: F, mut run_one: impl FnMut() -> bool) -> F::Output {
+    pin_utils::pin_mut!(future);
+
+    // Each nested `block_on()` call takes its own parker out of the cache,
+    // so that the inner call never steals the wakeup of the outer one.
+    thread_local! {
+        static CACHE: RefCell<Vec<Parking>> = const { RefCell::new(Vec::new()) };
+    }
+    struct Guard(Option<Parking>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            if let Some(parking) = self.0.take() {
+                let _ = CACHE.try_with(|cache| cache.borrow_mut().push(parking));
+            }
+        }
+    }
+    let parking = CACHE.with(|cache| cache.borrow_mut().pop());
+    let guard = Guard(Some(parking.unwrap_or_else(Parking::new)));
+    let parking = guard.0.as_ref().unwrap();
+
+    let cx = &mut Context::from_waker(&parking.waker);
+    loop {
+        match future.as_mut().poll(cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => {
+                while !parking.woken.swap(false, Ordering::SeqCst) {
+                    if !run_one() {
+                        parking.parker.park();
+                        // Polls the future next, which covers the wakeup.
+                        parking.woken.store(false, Ordering::SeqCst);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parker and its waker of a `block_on()` call.
+struct Parking {
+    parker: Parker,
+    woken: Arc<AtomicBool>,
+    waker: Waker,
+}
+
+impl Parking {
+    fn new() -> Self {
+        let parker = Parker::new();
+        let unparker = parker.unparker().clone();
+        let woken = Arc::new(AtomicBool::new(false));
+        let flag = woken.clone();
+        let waker = async_task::waker_fn(move || {
+            flag.store(true, Ordering::SeqCst);
+            unparker.unpark();
+        });
+        Self {
+            parker,
+            woken,
+            waker,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_on, block_on_with};
+    use crate::post20200131::v4::Executor;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    #[test]
+    fn nested_block_on() {
+        struct Test {
+            name: &'static str,
+            depth: usize,
+        }
+        let tests = [
+            Test {
+                name: "no nesting",
+                depth: 0,
+            },
+            Test {
+                name: "single nesting",
+                depth: 1,
+            },
+            Test {
+                name: "deep nesting",
+                depth: 8,
+            },
+        ];
+        fn nest(depth: usize) -> usize {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(1));
+                tx.send(depth).unwrap();
+            });
+            block_on(async move {
+                let got = rx.await.unwrap();
+                if depth == 0 {
+                    got
+                } else {
+                    got + nest(depth - 1)
+                }
+            })
+        }
+        for t in &tests {
+            let want = (0..=t.depth).sum::<usize>();
+            assert_eq!(want, nest(t.depth), "{}", t.name);
+        }
+    }
+    #[test]
+    fn block_on_with_runs_executor_tasks() {
+        let executor = Executor::builder().threads(1).build();
+        // Blocks the only worker thread.
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        let blocker = executor.spawn(async move {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+        let handle = executor.spawn(async { thread::current().id() });
+        let id = block_on_with(&executor, handle).unwrap();
+        assert_eq!(thread::current().id(), id);
+        tx.send(()).unwrap();
+        block_on(blocker).unwrap();
+        block_on(executor.shutdown());
+    }
+}
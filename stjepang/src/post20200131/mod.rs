@@ -1,7 +1,387 @@
 //! Build your own [executor]
 //!
+//! Each version provides the `Executor` built by the [`Builder`], and the
+//! global `spawn()` onto the default `Executor` instance.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! use stjepang_blog::post20200125::v4::block_on;
+//! use stjepang_blog::post20200131::v4::Executor;
+//!
+//! let started = Arc::new(AtomicUsize::new(0));
+//! let counter = started.clone();
+//! let executor = Executor::builder()
+//!     .threads(2)
+//!     .name("worker")
+//!     .stack_size(64 * 1024)
+//!     .on_thread_start(move || {
+//!         counter.fetch_add(1, Ordering::SeqCst);
+//!     })
+//!     .build();
+//!
+//! block_on(async {
+//!     let handle = executor.spawn(async { thread::current().name().unwrap().to_string() });
+//!     let name = handle.await.unwrap();
+//!     assert!(name.starts_with("worker-"));
+//!     executor.shutdown().await;
+//! });
+//! assert_eq!(2, started.load(Ordering::SeqCst));
+//! ```
 //! [executor]: https://stjepang.github.io/2020/01/31/build-your-own-executor.html
+//! [`builder`]: struct.Builder.html
 pub mod v1;
 pub mod v2;
 pub mod v3;
 pub mod v4;
+pub mod v5;
+
+use std::{
+    future::Future,
+    iter,
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Select, Sender, TryRecvError};
+
+type Hook = Arc<dyn Fn() + Send + Sync>;
+type Run<T> = Arc<dyn Fn(T) + Send + Sync>;
+
+/// Priority class of the task.
+///
+/// The worker threads pick the tasks of the higher class first, but let
+/// the lower classes go first once in a while so that they never starve.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// Latency sensitive tasks.
+    High,
+    /// Tasks spawned by `spawn()`.
+    #[default]
+    Normal,
+    /// Tasks which may wait, e.g. the tasks yielding too often.
+    Background,
+}
+
+impl Priority {
+    const ALL: [Self; 3] = [Self::High, Self::Normal, Self::Background];
+}
+
+/// Background class goes first once in `BACKGROUND_TICKS` picks.
+const BACKGROUND_TICKS: usize = 16;
+
+/// Normal class goes first once in `NORMAL_TICKS` picks.
+const NORMAL_TICKS: usize = 4;
+
+/// `Builder` to configure the worker threads of the executor `E`.
+pub struct Builder<E> {
+    threads: usize,
+    name: Option<String>,
+    stack_size: Option<usize>,
+    on_thread_start: Vec<Hook>,
+    _executor: PhantomData<fn() -> E>,
+}
+
+impl<E> Default for Builder<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Builder<E> {
+    /// `new` creates a `Builder` with a worker thread per CPU.
+    pub fn new() -> Self {
+        Self {
+            threads: num_cpus::get().max(1),
+            name: None,
+            stack_size: None,
+            on_thread_start: Vec::new(),
+            _executor: PhantomData,
+        }
+    }
+    /// `threads` sets the number of the worker threads.
+    ///
+    /// # Panics
+    ///
+    /// It panics if `threads` is zero.
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "zero worker threads");
+        self.threads = threads;
+        self
+    }
+    /// `name` sets the name prefix of the worker threads, which are named
+    /// `{prefix}-{index}`.
+    pub fn name(mut self, prefix: impl Into<String>) -> Self {
+        self.name = Some(prefix.into());
+        self
+    }
+    /// `stack_size` sets the stack size of the worker threads, in bytes.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+    /// `on_thread_start` adds the hook called on each worker thread before
+    /// it runs any task.  Hooks are called in the order they are added.
+    pub fn on_thread_start<F>(mut self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_thread_start.push(Arc::new(f));
+        self
+    }
+    /// `build` builds the executor and starts its worker threads.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the OS fails to create the worker thread.
+    pub fn build(self) -> E
+    where
+        E: From<Self>,
+    {
+        E::from(self)
+    }
+    /// `workers` starts the worker threads, which call `run` for each task
+    /// scheduled on the run queues.
+    fn workers<T, F>(&self, run: F) -> Workers<T>
+    where
+        T: Send + 'static,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let queues = Arc::new(Queues::new());
+        let (shutdown, shutdown_rx) = crossbeam_channel::bounded::<()>(0);
+        let run: Run<T> = Arc::new(run);
+        let threads = (0..self.threads)
+            .map(|i| {
+                let mut builder = thread::Builder::new();
+                if let Some(prefix) = &self.name {
+                    builder = builder.name(format!("{}-{}", prefix, i));
+                }
+                if let Some(size) = self.stack_size {
+                    builder = builder.stack_size(size);
+                }
+                let (queues, shutdown_rx) = (queues.clone(), shutdown_rx.clone());
+                let (run, hooks) = (run.clone(), self.on_thread_start.clone());
+                builder
+                    .spawn(move || {
+                        hooks.iter().for_each(|hook| hook());
+                        worker(&queues, &shutdown_rx, &*run);
+                    })
+                    .expect("cannot spawn worker thread")
+            })
+            .collect();
+        Workers {
+            queues,
+            run,
+            shutdown,
+            threads,
+        }
+    }
+}
+
+/// `worker` runs the tasks until the shutdown, and then runs each of the
+/// tasks left in the run queues once before it exits.
+fn worker<T>(queues: &Queues<T>, shutdown: &Receiver<()>, run: &dyn Fn(T)) {
+    let mut tick = 0usize;
+    loop {
+        tick = tick.wrapping_add(1);
+        // Checks the shutdown even if the run queues never run dry.
+        if let Err(TryRecvError::Disconnected) = shutdown.try_recv() {
+            break;
+        }
+        if let Some(task) = queues.pop(tick) {
+            run(task);
+            continue;
+        }
+        // Waits for any of the run queues, or the shutdown.
+        let mut select = Select::new();
+        queues.rxs.iter().for_each(|rx| {
+            select.recv(rx);
+        });
+        let stop = select.recv(shutdown);
+        if select.ready() == stop {
+            break;
+        }
+    }
+    // Runs each of them once, as the task waking itself up would keep
+    // the worker running forever otherwise.
+    let left: usize = queues.rxs.iter().map(Receiver::len).sum();
+    for _ in 0..left {
+        match queues.pop(1) {
+            Some(task) => run(task),
+            None => break,
+        }
+    }
+}
+
+/// Run queues of the executor, one per [`Priority`] class.
+///
+/// [`priority`]: enum.Priority.html
+struct Queues<T> {
+    txs: Vec<Sender<T>>,
+    rxs: Vec<Receiver<T>>,
+    /// Picks of `run_one` outside of the worker threads.
+    tick: AtomicUsize,
+}
+
+impl<T> Queues<T> {
+    fn new() -> Self {
+        let (txs, rxs) = Priority::ALL
+            .iter()
+            .map(|_| crossbeam_channel::unbounded())
+            .unzip();
+        Self {
+            txs,
+            rxs,
+            tick: AtomicUsize::new(0),
+        }
+    }
+    /// `pop` pops the task of the highest class, except that it lets the
+    /// lower class go first on every `NORMAL_TICKS` and `BACKGROUND_TICKS`
+    /// `tick`.
+    fn pop(&self, tick: usize) -> Option<T> {
+        let first = if tick % BACKGROUND_TICKS == 0 {
+            Priority::Background
+        } else if tick % NORMAL_TICKS == 0 {
+            Priority::Normal
+        } else {
+            Priority::High
+        };
+        iter::once(first)
+            .chain(Priority::ALL.iter().copied())
+            .find_map(|priority| self.rxs[priority as usize].try_recv().ok())
+    }
+}
+
+/// Worker threads and the run queues of the executor.
+struct Workers<T> {
+    queues: Arc<Queues<T>>,
+    run: Run<T>,
+    /// Dropped to signal the shutdown to the worker threads.
+    shutdown: Sender<()>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<T> Workers<T> {
+    /// `queue` returns the run queue of the `priority` class.
+    fn queue(&self, priority: Priority) -> &Sender<T> {
+        &self.queues.txs[priority as usize]
+    }
+    /// `run_one` runs a task from the run queues on the current thread, and
+    /// returns `false` if all of them are empty.
+    fn run_one(&self) -> bool {
+        let tick = self.queues.tick.fetch_add(1, Ordering::Relaxed);
+        match self.queues.pop(tick.wrapping_add(1)) {
+            Some(task) => {
+                (self.run)(task);
+                true
+            }
+            None => false,
+        }
+    }
+    /// `shutdown` signals the shutdown to the worker threads and returns
+    /// the future which resolves once all of them exit.
+    ///
+    /// The worker threads are joined on a dedicated thread so that the
+    /// caller doesn't block its executor.
+    fn shutdown(self) -> impl Future<Output = ()>
+    where
+        T: Send + 'static,
+    {
+        let Self {
+            queues,
+            shutdown,
+            threads,
+            ..
+        } = self;
+        drop(shutdown);
+        let (tx, rx) = futures_channel::oneshot::channel();
+        thread::spawn(move || {
+            for thread in threads {
+                let _ = thread.join();
+            }
+            // Drops the tasks woken up while the worker threads exit, as
+            // each of them holds the sender of its own run queue, which
+            // keeps the queue alive otherwise.
+            for rx in &queues.rxs {
+                rx.try_iter().for_each(drop);
+            }
+            drop(queues);
+            let _ = tx.send(());
+        });
+        async move {
+            let _ = rx.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, Queues};
+    #[test]
+    fn pop_with_anti_starvation() {
+        struct Test {
+            name: &'static str,
+            tasks: Vec<(Priority, u32)>,
+            ticks: Vec<usize>,
+            want: Vec<Option<u32>>,
+        }
+        let tests = [
+            Test {
+                name: "empty queues",
+                tasks: vec![],
+                ticks: vec![1, 4, 16],
+                want: vec![None, None, None],
+            },
+            Test {
+                name: "higher class first",
+                tasks: vec![
+                    (Priority::Background, 3),
+                    (Priority::Normal, 2),
+                    (Priority::High, 1),
+                ],
+                ticks: vec![1, 2, 3, 5],
+                want: vec![Some(1), Some(2), Some(3), None],
+            },
+            Test {
+                name: "normal class goes first on the normal tick",
+                tasks: vec![
+                    (Priority::High, 1),
+                    (Priority::Normal, 2),
+                    (Priority::High, 3),
+                ],
+                ticks: vec![4, 5, 6],
+                want: vec![Some(2), Some(1), Some(3)],
+            },
+            Test {
+                name: "background class goes first on the background tick",
+                tasks: vec![
+                    (Priority::High, 1),
+                    (Priority::Normal, 2),
+                    (Priority::Background, 3),
+                ],
+                ticks: vec![16, 17, 18],
+                want: vec![Some(3), Some(1), Some(2)],
+            },
+            Test {
+                name: "fall back to the higher class on the empty class",
+                tasks: vec![(Priority::High, 1), (Priority::Normal, 2)],
+                ticks: vec![16, 16],
+                want: vec![Some(1), Some(2)],
+            },
+        ];
+        for t in &tests {
+            let queues = Queues::new();
+            for (priority, task) in &t.tasks {
+                queues.txs[*priority as usize].send(*task).unwrap();
+            }
+            let got: Vec<_> = t.ticks.iter().map(|tick| queues.pop(*tick)).collect();
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+}
@@ -7,9 +7,13 @@ use std::{
     sync::atomic::{AtomicUsize, Ordering},
     sync::{Arc, Mutex},
     task::Context,
-    thread,
 };
 
+use crossbeam_channel::Sender;
+use once_cell::sync::Lazy;
+
+use super::{Builder, Priority, Workers};
+
 pub type JoinHandle<R> = Pin<Box<dyn Future<Output = R> + Send>>;
 
 /// `spawn()` for our own executor.
@@ -45,31 +49,87 @@ where
     F: Future<Output = R> + Send + 'static,
     R: Send + 'static,
 {
-    let (tx, rx) = futures_channel::oneshot::channel();
-
-    let future = async move {
-        let _ = tx.send(future.await);
-    };
-    let task = Arc::new(Task {
-        state: AtomicUsize::new(0),
-        future: Mutex::new(Box::pin(future)),
-    });
-    QUEUE.send(task).unwrap();
-
-    Box::pin(async move { rx.await.unwrap() })
+    EXECUTOR.spawn(future)
 }
 
-use once_cell::sync::Lazy;
+/// Executor with its own worker threads and the run queue.
+///
+/// # Examples
+///
+/// ```
+/// use stjepang_blog::post20200125::v4::block_on;
+/// use stjepang_blog::post20200131::v1::Executor;
+///
+/// let executor = Executor::builder().threads(1).name("v1").build();
+/// block_on(async {
+///     let handle = executor.spawn(async { 1 + 2 });
+///     assert_eq!(3, handle.await);
+///     executor.shutdown().await;
+/// });
+/// ```
+pub struct Executor {
+    workers: Workers<Arc<Task>>,
+}
 
-// Module wide run queue.
-static QUEUE: Lazy<crossbeam_channel::Sender<Arc<Task>>> = Lazy::new(|| {
-    let (tx, rx) = crossbeam_channel::unbounded::<Arc<Task>>();
-    for _ in 0..num_cpus::get().max(1) {
-        let rx = rx.clone();
-        thread::spawn(move || rx.iter().for_each(|task| task.run()));
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
     }
-    tx
-});
+}
+
+impl From<Builder<Executor>> for Executor {
+    fn from(builder: Builder<Executor>) -> Self {
+        Self {
+            workers: builder.workers(|task: Arc<Task>| task.run()),
+        }
+    }
+}
+
+impl Executor {
+    /// `new` creates an `Executor` with the default configuration.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+    /// `builder` returns the [`Builder`] to configure the `Executor`.
+    ///
+    /// [`builder`]: ../struct.Builder.html
+    pub fn builder() -> Builder<Self> {
+        Builder::new()
+    }
+    /// `spawn` spawns `future` onto the executor.
+    pub fn spawn<F, R>(&self, future: F) -> JoinHandle<R>
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = futures_channel::oneshot::channel();
+
+        let future = async move {
+            let _ = tx.send(future.await);
+        };
+        let task = Arc::new(Task {
+            state: AtomicUsize::new(0),
+            future: Mutex::new(Box::pin(future)),
+            queue: self.workers.queue(Priority::Normal).clone(),
+        });
+        task.queue.send(task.clone()).unwrap();
+
+        Box::pin(async move { rx.await.unwrap() })
+    }
+    /// `shutdown` stops the worker threads once they run the tasks left in
+    /// the run queue, and resolves after all of them exit.
+    ///
+    /// Tasks left in the run queues once the worker threads exit, and the
+    /// tasks woken up after the shutdown, are dropped.  It should be
+    /// awaited outside of the executor, as the awaiting task never gets
+    /// woken up otherwise.
+    pub fn shutdown(self) -> impl Future<Output = ()> {
+        self.workers.shutdown()
+    }
+}
+
+/// Default executor instance for `spawn()`.
+static EXECUTOR: Lazy<Executor> = Lazy::new(Executor::new);
 
 const WOKEN: usize = 0b01;
 const RUNNING: usize = 0b10;
@@ -77,6 +137,8 @@ const RUNNING: usize = 0b10;
 struct Task {
     state: AtomicUsize,
     future: Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    /// Run queue of the executor the task is spawned onto.
+    queue: Sender<Arc<Task>>,
 }
 
 impl Task {
@@ -84,7 +146,8 @@ impl Task {
         let task = self.clone();
         let waker = async_task::waker_fn(move || {
             if task.state.fetch_or(WOKEN, Ordering::SeqCst) == 0 {
-                QUEUE.send(task.clone()).unwrap();
+                // The task is dropped after the shutdown.
+                let _ = task.queue.send(task.clone());
             }
         });
 
@@ -94,7 +157,7 @@ impl Task {
 
         if poll.is_pending() && self.state.fetch_and(!RUNNING, Ordering::SeqCst) == WOKEN | RUNNING
         {
-            QUEUE.send(self).unwrap();
+            let _ = self.queue.clone().send(self);
         }
     }
 }
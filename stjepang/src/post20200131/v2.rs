@@ -1,28 +1,96 @@
 //! Build your own [executor], v2
 //!
 //! [executor]: https://stjepang.github.io/2020/01/31/build-your-own-executor.html
-use std::{future::Future, pin::Pin, thread};
+use std::{future::Future, pin::Pin};
+
+use once_cell::sync::Lazy;
+
+use super::{Builder, Priority, Workers};
 
 type Task = async_task::Task<()>;
 type JoinHandle<R> = Pin<Box<dyn Future<Output = R> + Send>>;
 
+/// `spawn()` onto the default [`Executor`].
+///
+/// [`executor`]: struct.Executor.html
 pub fn spawn<F, R>(future: F) -> JoinHandle<R>
 where
     F: Future<Output = R> + Send + 'static,
     R: Send + 'static,
 {
-    let (task, handle) = async_task::spawn(future, |t| QUEUE.send(t).unwrap(), ());
-    task.schedule();
-    Box::pin(async { handle.await.unwrap() })
+    EXECUTOR.spawn(future)
 }
 
-use once_cell::sync::Lazy;
+/// Executor with its own worker threads and the run queue.
+///
+/// # Examples
+///
+/// ```
+/// use stjepang_blog::post20200125::v4::block_on;
+/// use stjepang_blog::post20200131::v2::Executor;
+///
+/// let executor = Executor::builder().threads(1).name("v2").build();
+/// block_on(async {
+///     let handle = executor.spawn(async { 1 + 2 });
+///     assert_eq!(3, handle.await);
+///     executor.shutdown().await;
+/// });
+/// ```
+pub struct Executor {
+    workers: Workers<Task>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-static QUEUE: Lazy<crossbeam_channel::Sender<Task>> = Lazy::new(|| {
-    let (tx, rx) = crossbeam_channel::unbounded::<Task>();
-    for _ in 0..num_cpus::get().max(1) {
-        let rx = rx.clone();
-        thread::spawn(move || rx.iter().for_each(|task| task.run()));
+impl From<Builder<Executor>> for Executor {
+    fn from(builder: Builder<Executor>) -> Self {
+        Self {
+            workers: builder.workers(|task: Task| task.run()),
+        }
     }
-    tx
-});
+}
+
+impl Executor {
+    /// `new` creates an `Executor` with the default configuration.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+    /// `builder` returns the [`Builder`] to configure the `Executor`.
+    ///
+    /// [`builder`]: ../struct.Builder.html
+    pub fn builder() -> Builder<Self> {
+        Builder::new()
+    }
+    /// `spawn` spawns `future` onto the executor.
+    pub fn spawn<F, R>(&self, future: F) -> JoinHandle<R>
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let queue = self.workers.queue(Priority::Normal).clone();
+        let schedule = move |t| {
+            // The task is dropped after the shutdown.
+            let _ = queue.send(t);
+        };
+        let (task, handle) = async_task::spawn(future, schedule, ());
+        task.schedule();
+        Box::pin(async { handle.await.unwrap() })
+    }
+    /// `shutdown` stops the worker threads once they run the tasks left in
+    /// the run queue, and resolves after all of them exit.
+    ///
+    /// Tasks left in the run queues once the worker threads exit, and the
+    /// tasks woken up after the shutdown, are dropped.  It should be
+    /// awaited outside of the executor, as the awaiting task never gets
+    /// woken up otherwise.
+    pub fn shutdown(self) -> impl Future<Output = ()> {
+        self.workers.shutdown()
+    }
+}
+
+/// Default executor instance for `spawn()`.
+static EXECUTOR: Lazy<Executor> = Lazy::new(Executor::new);
@@ -6,43 +6,417 @@ use core::{
     pin::Pin,
     task::{Context, Poll},
 };
-use std::{panic::catch_unwind, thread};
+use std::{
+    any::Any,
+    cell::Cell,
+    error::Error,
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+
+use once_cell::sync::Lazy;
+
+pub use super::Priority;
+use super::{Builder, Workers};
+
+type Task = async_task::Task<Tag>;
 
-type Task = async_task::Task<()>;
-pub struct JoinHandle<R>(async_task::JoinHandle<R, ()>);
+/// `JoinHandle` to await the output of the task.
+///
+/// It resolves to [`JoinError`] in case the task panicked, or it's
+/// aborted or dropped before its completion.  Dropping the `JoinHandle`
+/// detaches the task, as [`detach`] does.
+///
+/// # Examples
+///
+/// ```
+/// use stjepang_blog::post20200125::v4::block_on;
+/// use stjepang_blog::post20200131::v4::spawn;
+///
+/// block_on(async {
+///     let err = spawn(async { panic!("oops") }).await.unwrap_err();
+///     assert!(err.is_panic());
+///     assert_eq!("task panicked: oops", err.to_string());
+///     assert_eq!("oops", *err.into_panic().downcast::<&str>().unwrap());
+///
+///     let handle = spawn(futures::future::pending::<()>());
+///     handle.abort();
+///     assert!(handle.await.unwrap_err().is_cancelled());
+/// });
+/// ```
+/// [`joinerror`]: struct.JoinError.html
+/// [`detach`]: struct.JoinHandle.html#method.detach
+pub struct JoinHandle<R>(pub(crate) async_task::JoinHandle<thread::Result<R>, Tag>);
+
+impl<R> JoinHandle<R> {
+    /// `detach` lets the task run to completion without awaiting its
+    /// output.
+    pub fn detach(self) {}
+    /// `abort` cancels the task.
+    ///
+    /// The task's future is dropped without being polled again, and the
+    /// `JoinHandle` resolves to the cancelled [`JoinError`].  It does
+    /// nothing in case the task has already been completed.
+    ///
+    /// [`joinerror`]: struct.JoinError.html
+    pub fn abort(&self) {
+        self.0.cancel();
+    }
+}
 
 impl<R> Future for JoinHandle<R> {
-    type Output = R;
+    type Output = Result<R, JoinError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match Pin::new(&mut self.0).poll(cx) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready(output) => Poll::Ready(output.expect("task failed")),
+            Poll::Ready(Some(Ok(output))) => Poll::Ready(Ok(output)),
+            Poll::Ready(Some(Err(payload))) => Poll::Ready(Err(JoinError::Panic(payload))),
+            Poll::Ready(None) => Poll::Ready(Err(JoinError::Cancelled)),
+        }
+    }
+}
+
+/// `JoinError` returned by the [`JoinHandle`].
+///
+/// [`joinhandle`]: struct.JoinHandle.html
+pub enum JoinError {
+    /// The task was aborted or dropped before its completion.
+    Cancelled,
+    /// The task panicked, with the panic payload.
+    Panic(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    /// `is_cancelled` returns `true` if the task was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Self::Cancelled)
+    }
+    /// `is_panic` returns `true` if the task panicked.
+    pub fn is_panic(&self) -> bool {
+        matches!(self, Self::Panic(_))
+    }
+    /// `into_panic` returns the panic payload, so that the caller can
+    /// resume the panic with `std::panic::resume_unwind`.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the task didn't panic.
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            Self::Panic(payload) => payload,
+            Self::Cancelled => panic!("`JoinError` is not a panic"),
         }
     }
 }
 
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "JoinError::Cancelled"),
+            Self::Panic(_) => write!(f, "JoinError::Panic(..)"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "task was cancelled"),
+            Self::Panic(payload) => match payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            {
+                Some(msg) => write!(f, "task panicked: {}", msg),
+                None => write!(f, "task panicked"),
+            },
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+/// Consecutive yields allowed before the task is pushed back to the
+/// background class.
+const YIELD_BUDGET: u32 = 8;
+
+thread_local! {
+    /// Tag of the task running on the current thread.
+    static RUNNING: Cell<*const Tag> = const { Cell::new(ptr::null()) };
+}
+
+/// Scheduling state of the task, stored inside the task.
+pub(crate) struct Tag {
+    priority: Priority,
+    /// Consecutive wakeups by the task itself while it's running.
+    yields: AtomicU32,
+}
+
+impl Tag {
+    pub(crate) fn new(priority: Priority) -> Self {
+        Self {
+            priority,
+            yields: AtomicU32::new(0),
+        }
+    }
+    /// `run` runs the task, so that its wakeups while running are counted
+    /// as the yields.
+    fn run(task: Task) {
+        let tag: *const Tag = task.tag();
+        RUNNING.with(|running| running.set(tag));
+        task.run();
+        RUNNING.with(|running| running.set(ptr::null()));
+    }
+    /// `priority` records the wakeup of the task and returns the class
+    /// to schedule it, which is the background class once the task has
+    /// yielded more than `YIELD_BUDGET` times in a row.
+    fn priority(&self) -> Priority {
+        if RUNNING.with(|running| ptr::eq(running.get(), self)) {
+            if self.yields.fetch_add(1, Ordering::Relaxed) >= YIELD_BUDGET {
+                return Priority::Background;
+            }
+        } else {
+            // Woken up by someone else, e.g. the I/O or the other task.
+            self.yields.store(0, Ordering::Relaxed);
+        }
+        self.priority
+    }
+}
+
+/// `CatchUnwind` catches the panic of the future, so that the worker
+/// thread hands the payload over to the `JoinHandle`.
+pub(crate) struct CatchUnwind<F>(pub(crate) F);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = thread::Result<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the inner future is never moved out of the pinned wrapper.
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.0) };
+        match catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+/// `spawn()` onto the default [`Executor`].
+///
+/// [`executor`]: struct.Executor.html
 pub fn spawn<F, R>(future: F) -> JoinHandle<R>
 where
     F: Future<Output = R> + Send + 'static,
     R: Send + 'static,
 {
-    let (task, handle) = async_task::spawn(future, |t| QUEUE.send(t).unwrap(), ());
-    task.schedule();
-    JoinHandle(handle)
+    EXECUTOR.spawn(future)
 }
 
-use once_cell::sync::Lazy;
+/// Executor with its own worker threads and the run queue.
+///
+/// # Examples
+///
+/// ```
+/// use stjepang_blog::post20200125::v4::block_on;
+/// use stjepang_blog::post20200131::v4::Executor;
+///
+/// let executor = Executor::builder().threads(1).name("v4").build();
+/// block_on(async {
+///     let handle = executor.spawn(async { 1 + 2 });
+///     assert_eq!(3, handle.await.unwrap());
+///     executor.shutdown().await;
+/// });
+/// ```
+pub struct Executor {
+    workers: Workers<Task>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Builder<Executor>> for Executor {
+    fn from(builder: Builder<Executor>) -> Self {
+        Self {
+            // `CatchUnwind` catches the panics inside the tasks.
+            workers: builder.workers(Tag::run),
+        }
+    }
+}
 
-static QUEUE: Lazy<crossbeam_channel::Sender<Task>> = Lazy::new(|| {
-    let (tx, rx) = crossbeam_channel::unbounded::<Task>();
-    for _ in 0..num_cpus::get().max(1) {
-        let rx = rx.clone();
-        thread::spawn(move || {
-            rx.iter().for_each(|task| {
-                let _ = catch_unwind(|| task.run());
-            })
+impl Executor {
+    /// `new` creates an `Executor` with the default configuration.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+    /// `builder` returns the [`Builder`] to configure the `Executor`.
+    ///
+    /// [`builder`]: ../struct.Builder.html
+    pub fn builder() -> Builder<Self> {
+        Builder::new()
+    }
+    /// `spawn` spawns `future` onto the executor, with the normal
+    /// [`Priority`].
+    ///
+    /// [`priority`]: enum.Priority.html
+    pub fn spawn<F, R>(&self, future: F) -> JoinHandle<R>
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_with_priority(Priority::Normal, future)
+    }
+    /// `spawn_with_priority` spawns `future` onto the executor, with the
+    /// `priority` class.
+    ///
+    /// The task which yields too often, i.e. wakes itself up while it's
+    /// running, is pushed back to the background class until it's woken
+    /// up by the others.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stjepang_blog::post20200125::v4::block_on;
+    /// use stjepang_blog::post20200131::v4::{Executor, Priority};
+    ///
+    /// let executor = Executor::builder().threads(1).build();
+    /// let background = executor.spawn_with_priority(Priority::Background, async { 1 });
+    /// let high = executor.spawn_with_priority(Priority::High, async { 2 });
+    /// block_on(async {
+    ///     assert_eq!(2, high.await.unwrap());
+    ///     assert_eq!(1, background.await.unwrap());
+    /// });
+    /// ```
+    pub fn spawn_with_priority<F, R>(&self, priority: Priority, future: F) -> JoinHandle<R>
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let queue = self.workers.queue(priority).clone();
+        let background = self.workers.queue(Priority::Background).clone();
+        let schedule = move |t: Task| {
+            let queue = match t.tag().priority() {
+                Priority::Background => &background,
+                _ => &queue,
+            };
+            // The task is dropped after the shutdown.
+            let _ = queue.send(t);
+        };
+        let (task, handle) = async_task::spawn(CatchUnwind(future), schedule, Tag::new(priority));
+        task.schedule();
+        JoinHandle(handle)
+    }
+    /// `run_one` runs a task from the run queues on the current thread, and
+    /// returns `false` if there is no task to run.
+    ///
+    /// It lets the thread blocked on the executor, e.g. by
+    /// [`block_on_with`], help the worker threads.
+    ///
+    /// [`block_on_with`]: ../../post20200125/v5/fn.block_on_with.html
+    pub fn run_one(&self) -> bool {
+        self.workers.run_one()
+    }
+    /// `shutdown` stops the worker threads once they run the tasks left in
+    /// the run queues, and resolves after all of them exit.
+    ///
+    /// Tasks left in the run queues once the worker threads exit, and the
+    /// tasks woken up after the shutdown, are dropped.  It should be
+    /// awaited outside of the executor, as the awaiting task never gets
+    /// woken up otherwise.
+    pub fn shutdown(self) -> impl Future<Output = ()> {
+        self.workers.shutdown()
+    }
+}
+
+/// Default executor instance for `spawn()`.
+static EXECUTOR: Lazy<Executor> = Lazy::new(Executor::new);
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, Tag, RUNNING, YIELD_BUDGET};
+    #[test]
+    fn yielding_task_pushed_back() {
+        struct Test {
+            name: &'static str,
+            priority: Priority,
+        }
+        let tests = [
+            Test {
+                name: "high priority",
+                priority: Priority::High,
+            },
+            Test {
+                name: "normal priority",
+                priority: Priority::Normal,
+            },
+        ];
+        for t in &tests {
+            let tag = Tag::new(t.priority);
+            RUNNING.with(|running| running.set(&tag));
+            for i in 0..YIELD_BUDGET {
+                assert_eq!(t.priority, tag.priority(), "{}: yield #{}", t.name, i);
+            }
+            assert_eq!(Priority::Background, tag.priority(), "{}", t.name);
+            assert_eq!(Priority::Background, tag.priority(), "{}", t.name);
+            // Woken up by the other.
+            RUNNING.with(|running| running.set(std::ptr::null()));
+            assert_eq!(t.priority, tag.priority(), "{}", t.name);
+        }
+    }
+    #[test]
+    fn woken_after_shutdown() {
+        use super::Executor;
+        use crate::post20200125::v4::block_on;
+        use std::sync::{Arc, Mutex};
+        use std::task::{Poll, Waker};
+        let executor = Executor::builder().threads(1).build();
+        let alive = Arc::new(());
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+        let (task_alive, task_waker) = (alive.clone(), waker.clone());
+        let pending = futures::future::poll_fn(move |cx| {
+            let _alive = &task_alive;
+            *task_waker.lock().unwrap() = Some(cx.waker().clone());
+            Poll::<()>::Pending
+        });
+        executor.spawn(pending).detach();
+        while waker.lock().unwrap().is_none() {
+            std::thread::yield_now();
+        }
+        block_on(executor.shutdown());
+        assert_eq!(2, Arc::strong_count(&alive));
+        // Dropped instead of being queued.
+        waker.lock().unwrap().take().unwrap().wake();
+        assert_eq!(1, Arc::strong_count(&alive));
+    }
+    #[test]
+    fn shutdown_with_self_waking_task() {
+        use super::Executor;
+        use crate::post20200125::v4::block_on;
+        use std::sync::{mpsc, Arc};
+        use std::task::Poll;
+        use std::time::Duration;
+        let executor = Executor::builder().threads(2).build();
+        let alive = Arc::new(());
+        let task_alive = alive.clone();
+        let yielding = futures::future::poll_fn(move |cx| {
+            let _alive = &task_alive;
+            cx.waker().wake_by_ref();
+            Poll::<()>::Pending
         });
+        executor.spawn(yielding).detach();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            block_on(executor.shutdown());
+            let _ = tx.send(());
+        });
+        rx.recv_timeout(Duration::from_secs(10))
+            .expect("shutdown never completes");
+        // Dropped on shutdown.
+        assert_eq!(1, Arc::strong_count(&alive));
     }
-    tx
-});
+}
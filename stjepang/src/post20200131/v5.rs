@@ -0,0 +1,298 @@
+//! Build your own [executor], v5, spawning local tasks
+//!
+//! The [`LocalExecutor`] runs the non-`Send` tasks on the thread which
+//! calls its [`block_on`], alongside the tasks of the multi-threaded
+//! [`Executor`].
+//!
+//! [executor]: https://stjepang.github.io/2020/01/31/build-your-own-executor.html
+//! [`localexecutor`]: struct.LocalExecutor.html
+//! [`block_on`]: struct.LocalExecutor.html#method.block_on
+//! [`executor`]: ../v4/struct.Executor.html
+use core::{
+    future::Future,
+    task::{Context, Poll, Waker},
+};
+use std::{
+    cell::RefCell,
+    future::poll_fn,
+    mem,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use super::v4::{CatchUnwind, Tag};
+pub use super::v4::{Executor, JoinError, JoinHandle};
+use super::Priority;
+use crate::post20200125::v5::run;
+
+type Task = async_task::Task<Tag>;
+
+thread_local! {
+    /// The local executor running `block_on` on the current thread.
+    static CURRENT: RefCell<Option<LocalExecutor>> = const { RefCell::new(None) };
+}
+
+/// `spawn_local()` onto the [`LocalExecutor`] running [`block_on`] on the
+/// current thread.
+///
+/// # Panics
+///
+/// It panics if it's called outside of [`block_on`].
+///
+/// # Examples
+///
+/// ```
+/// use std::rc::Rc;
+///
+/// use stjepang_blog::post20200131::v5::{spawn_local, LocalExecutor};
+///
+/// let executor = LocalExecutor::new();
+/// let got = executor.block_on(async {
+///     let shared = Rc::new(1);
+///     let cloned = shared.clone();
+///     let handle = spawn_local(async move { *cloned + 2 });
+///     handle.await.unwrap()
+/// });
+/// assert_eq!(3, got);
+/// ```
+/// [`localexecutor`]: struct.LocalExecutor.html
+/// [`block_on`]: struct.LocalExecutor.html#method.block_on
+pub fn spawn_local<F, R>(future: F) -> JoinHandle<R>
+where
+    F: Future<Output = R> + 'static,
+    R: 'static,
+{
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .expect("`spawn_local()` called outside of `LocalExecutor::block_on()`")
+            .spawn(future)
+    })
+}
+
+/// Executor of the non-`Send` tasks, which runs them on the thread calling
+/// its [`block_on`].
+///
+/// The `LocalExecutor` is cheap to clone, and it's not `Send` itself.
+///
+/// # Examples
+///
+/// Together with the multi-threaded [`Executor`]:
+///
+/// ```
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// use stjepang_blog::post20200131::v5::{spawn_local, Executor, LocalExecutor};
+///
+/// let executor = Executor::builder().threads(2).build();
+/// let local = LocalExecutor::new();
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let got = local.block_on(async {
+///     let handle = executor.spawn(async { 1 + 2 });
+///     let log = log.clone();
+///     spawn_local(async move {
+///         // The local task awaits the task on the worker thread.
+///         let got = handle.await.unwrap();
+///         log.borrow_mut().push(got);
+///         got
+///     })
+///     .await
+///     .unwrap()
+/// });
+/// assert_eq!(3, got);
+/// assert_eq!(vec![3], *log.borrow());
+/// ```
+/// [`block_on`]: struct.LocalExecutor.html#method.block_on
+/// [`executor`]: ../v4/struct.Executor.html
+#[derive(Clone)]
+pub struct LocalExecutor {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    queue: Sender<Task>,
+    rx: Receiver<Task>,
+    /// Waker of the `block_on` call, woken up with the local tasks.
+    notify: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Drops the scheduled tasks on the thread which spawned them.
+        self.rx.try_iter().for_each(drop);
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalExecutor {
+    /// `new` creates a new `LocalExecutor`.
+    pub fn new() -> Self {
+        let (queue, rx) = crossbeam_channel::unbounded();
+        Self {
+            inner: Rc::new(Inner {
+                queue,
+                rx,
+                notify: Arc::new(Mutex::new(None)),
+            }),
+        }
+    }
+    /// `spawn` spawns `future` onto the executor.
+    ///
+    /// The task runs once the current thread calls [`block_on`].
+    ///
+    /// [`block_on`]: struct.LocalExecutor.html#method.block_on
+    pub fn spawn<F, R>(&self, future: F) -> JoinHandle<R>
+    where
+        F: Future<Output = R> + 'static,
+        R: 'static,
+    {
+        let queue = self.inner.queue.clone();
+        let notify = self.inner.notify.clone();
+        // The task may be woken up from the other threads.
+        let schedule = move |t| {
+            if let Err(err) = queue.send(t) {
+                // Leaks the task instead of dropping it on the other
+                // thread, as the executor has already been dropped.
+                mem::forget(err.into_inner());
+                return;
+            }
+            if let Some(waker) = &*notify.lock().unwrap() {
+                waker.wake_by_ref();
+            }
+        };
+        let (task, handle) =
+            async_task::spawn_local(CatchUnwind(future), schedule, Tag::new(Priority::Normal));
+        task.schedule();
+        JoinHandle(handle)
+    }
+    /// `run_one` runs a local task and returns `false` if there is no task
+    /// to run.
+    pub fn run_one(&self) -> bool {
+        match self.inner.rx.try_recv() {
+            Ok(task) => {
+                task.run();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+    /// `block_on` blocks on `future` and runs the local tasks on the current
+    /// thread while the future is waiting to be woken up.
+    ///
+    /// The `future` and the local tasks can spawn other local tasks with
+    /// [`spawn_local`].  The nested `block_on` call is allowed.
+    ///
+    /// [`spawn_local`]: fn.spawn_local.html
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        struct Reset(Option<LocalExecutor>);
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                let _ = CURRENT.try_with(|current| *current.borrow_mut() = prev);
+            }
+        }
+        let prev = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        let _reset = Reset(prev);
+
+        pin_utils::pin_mut!(future);
+        let notify = self.inner.notify.clone();
+        let future = poll_fn(|cx: &mut Context<'_>| -> Poll<F::Output> {
+            let poll = future.as_mut().poll(cx);
+            // Registers it after the poll, as the nested `block_on` call
+            // replaces it.  The tasks scheduled in the meantime are run
+            // before the thread parks.
+            let mut waker = notify.lock().unwrap();
+            if !matches!(&*waker, Some(w) if w.will_wake(cx.waker())) {
+                *waker = Some(cx.waker().clone());
+            }
+            poll
+        });
+        run(future, || self.run_one())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spawn_local, Executor, LocalExecutor};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    #[test]
+    fn local_and_multithreaded_tasks() {
+        struct Test {
+            name: &'static str,
+            local: usize,
+            remote: usize,
+        }
+        let tests = [
+            Test {
+                name: "local tasks only",
+                local: 10,
+                remote: 0,
+            },
+            Test {
+                name: "local and remote tasks",
+                local: 10,
+                remote: 10,
+            },
+        ];
+        let executor = Executor::builder().threads(2).build();
+        for t in &tests {
+            let local = LocalExecutor::new();
+            let count = Rc::new(RefCell::new(0));
+            let got = local.block_on(async {
+                let remotes: Vec<_> = (0..t.remote)
+                    .map(|i| executor.spawn(async move { i }))
+                    .collect();
+                let mut handles = Vec::new();
+                for i in 0..t.local {
+                    let count = count.clone();
+                    handles.push(spawn_local(async move {
+                        *count.borrow_mut() += 1;
+                        i
+                    }));
+                }
+                for remote in remotes {
+                    let count = count.clone();
+                    handles.push(spawn_local(async move {
+                        let got = remote.await.unwrap();
+                        *count.borrow_mut() += 1;
+                        got
+                    }));
+                }
+                let mut sum = 0;
+                for handle in handles {
+                    sum += handle.await.unwrap();
+                }
+                sum
+            });
+            let want = (0..t.local).sum::<usize>() + (0..t.remote).sum::<usize>();
+            assert_eq!(want, got, "{}", t.name);
+            assert_eq!(t.local + t.remote, *count.borrow(), "{}", t.name);
+        }
+    }
+    #[test]
+    fn local_panic() {
+        let local = LocalExecutor::new();
+        let got = local.block_on(async {
+            let err = spawn_local(async { panic!("oops") }).await.unwrap_err();
+            // The other local tasks keep running.
+            let ok = spawn_local(async { 1 }).await.unwrap();
+            (err.is_panic(), ok)
+        });
+        assert_eq!((true, 1), got);
+    }
+    #[test]
+    #[should_panic(expected = "outside of `LocalExecutor::block_on()`")]
+    fn spawn_local_outside_of_block_on() {
+        spawn_local(async {});
+    }
+}
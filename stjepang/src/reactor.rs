@@ -0,0 +1,381 @@
+//! Minimal I/O reactor on [mio], with [`Async`] I/O handles
+//!
+//! The reactor runs [`mio::Poll`] on its own thread and wakes up the tasks
+//! blocked on the I/O handles once they become ready, so that the I/O
+//! futures run on any executor, e.g. our own [`Executor`].
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! use stjepang_blog::post20200125::v5::block_on;
+//! use stjepang_blog::post20200131::v4::spawn;
+//! use stjepang_blog::reactor::{Async, TcpListener, TcpStream};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! block_on(async {
+//!     let listener = Async::<TcpListener>::bind("127.0.0.1:0".parse().unwrap())?;
+//!     let addr = listener.get_ref().local_addr()?;
+//!     let server = spawn(async move {
+//!         let (mut stream, _) = listener.accept().await?;
+//!         let mut buf = [0; 5];
+//!         stream.read_exact(&mut buf).await?;
+//!         stream.write_all(&buf).await
+//!     });
+//!
+//!     let mut stream = Async::<TcpStream>::connect(addr).await?;
+//!     stream.write_all(b"hello").await?;
+//!     let mut buf = [0; 5];
+//!     stream.read_exact(&mut buf).await?;
+//!     assert_eq!(b"hello", &buf);
+//!     server.await.unwrap()
+//! })
+//! # }
+//! ```
+//! [mio]: https://lib.rs/mio/latest/mio/index.html
+//! [`async`]: struct.Async.html
+//! [`mio::poll`]: https://docs.rs/mio/0.7/mio/struct.Poll.html
+//! [`executor`]: ../post20200131/v4/struct.Executor.html
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr},
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use futures_io::{AsyncRead, AsyncWrite};
+use mio::{event, Events, Interest, Registry, Token};
+use once_cell::sync::Lazy;
+
+pub use mio::net::{TcpListener, TcpStream};
+
+/// The reactor, which starts its thread on the first I/O handle.
+static REACTOR: Lazy<Reactor> = Lazy::new(|| {
+    let poll = mio::Poll::new().expect("cannot create mio::Poll");
+    let registry = poll
+        .registry()
+        .try_clone()
+        .expect("cannot clone mio::Registry");
+    thread::Builder::new()
+        .name("stjepang-reactor".to_string())
+        .spawn(move || REACTOR.run(poll))
+        .expect("cannot spawn reactor thread");
+    Reactor {
+        registry,
+        sources: Mutex::new(HashMap::new()),
+        next: AtomicUsize::new(0),
+    }
+});
+
+struct Reactor {
+    registry: Registry,
+    /// Registered I/O sources, keyed by the token.
+    sources: Mutex<HashMap<usize, Arc<Source>>>,
+    next: AtomicUsize,
+}
+
+impl Reactor {
+    /// `run` polls the I/O events forever and wakes up the tasks blocked
+    /// on the ready sources.
+    fn run(&self, mut poll: mio::Poll) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            if let Err(err) = poll.poll(&mut events, None) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                panic!("mio::Poll failed: {}", err);
+            }
+            for event in &events {
+                let source = match self.sources.lock().unwrap().get(&event.token().0) {
+                    None => continue,
+                    Some(source) => source.clone(),
+                };
+                let mut wakers = source.wakers.lock().unwrap();
+                let mut ready = Vec::new();
+                // Error and the closed events wake up both of the sides.
+                if event.is_readable() || event.is_read_closed() || event.is_error() {
+                    ready.append(&mut wakers.readers);
+                }
+                if event.is_writable() || event.is_write_closed() || event.is_error() {
+                    ready.append(&mut wakers.writers);
+                }
+                drop(wakers);
+                ready.into_iter().for_each(Waker::wake);
+            }
+        }
+    }
+    fn register(&self, io: &mut impl event::Source) -> io::Result<Arc<Source>> {
+        let key = self.next.fetch_add(1, Ordering::Relaxed);
+        let source = Arc::new(Source {
+            key,
+            wakers: Mutex::new(Wakers::default()),
+        });
+        self.sources.lock().unwrap().insert(key, source.clone());
+        let interest = Interest::READABLE | Interest::WRITABLE;
+        if let Err(err) = self.registry.register(io, Token(key), interest) {
+            self.sources.lock().unwrap().remove(&key);
+            return Err(err);
+        }
+        Ok(source)
+    }
+    fn deregister(&self, io: &mut impl event::Source, source: &Source) {
+        let _ = self.registry.deregister(io);
+        self.sources.lock().unwrap().remove(&source.key);
+    }
+}
+
+/// I/O source registered to the reactor.
+struct Source {
+    key: usize,
+    wakers: Mutex<Wakers>,
+}
+
+/// Tasks blocked on the source.
+#[derive(Default)]
+struct Wakers {
+    readers: Vec<Waker>,
+    writers: Vec<Waker>,
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Read,
+    Write,
+}
+
+impl Source {
+    /// `poll_io` runs the non-blocking `op`, and registers the task to be
+    /// woken up once the source is ready in case `op` would block.
+    ///
+    /// It runs `op` under the lock, so that the reactor never wakes up
+    /// the task between `op` and the registration, as the events are
+    /// edge-triggered.
+    fn poll_io<R>(
+        &self,
+        cx: &mut Context<'_>,
+        dir: Direction,
+        mut op: impl FnMut() -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        let mut wakers = self.wakers.lock().unwrap();
+        loop {
+            match op() {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                res => return Poll::Ready(res),
+            }
+        }
+        let wakers = match dir {
+            Direction::Read => &mut wakers.readers,
+            Direction::Write => &mut wakers.writers,
+        };
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Async I/O handle of the non-blocking [mio] I/O source `T`.
+///
+/// [mio]: https://lib.rs/mio/latest/mio/index.html
+pub struct Async<T: event::Source> {
+    source: Arc<Source>,
+    io: T,
+}
+
+impl<T: event::Source> Async<T> {
+    /// `new` registers the non-blocking `io` to the reactor.
+    pub fn new(mut io: T) -> io::Result<Self> {
+        let source = REACTOR.register(&mut io)?;
+        Ok(Self { source, io })
+    }
+    /// `get_ref` returns the reference to the inner I/O source.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+    /// `read_with` runs the non-blocking read `op` until it doesn't
+    /// return `io::ErrorKind::WouldBlock`, waiting for the source to be
+    /// readable in between.
+    pub async fn read_with<R>(&self, mut op: impl FnMut(&T) -> io::Result<R>) -> io::Result<R> {
+        poll_fn(|cx| self.source.poll_io(cx, Direction::Read, || op(&self.io))).await
+    }
+    /// `write_with` runs the non-blocking write `op` until it doesn't
+    /// return `io::ErrorKind::WouldBlock`, waiting for the source to be
+    /// writable in between.
+    pub async fn write_with<R>(&self, mut op: impl FnMut(&T) -> io::Result<R>) -> io::Result<R> {
+        poll_fn(|cx| self.source.poll_io(cx, Direction::Write, || op(&self.io))).await
+    }
+}
+
+impl<T: event::Source> Drop for Async<T> {
+    fn drop(&mut self) {
+        REACTOR.deregister(&mut self.io, &self.source);
+    }
+}
+
+impl Async<TcpListener> {
+    /// `bind` creates the listener bound to `addr`.
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Self::new(TcpListener::bind(addr)?)
+    }
+    /// `accept` accepts a new incoming connection.
+    pub async fn accept(&self) -> io::Result<(Async<TcpStream>, SocketAddr)> {
+        let (stream, addr) = self.read_with(TcpListener::accept).await?;
+        Ok((Async::new(stream)?, addr))
+    }
+}
+
+impl Async<TcpStream> {
+    /// `connect` connects to the peer at `addr`.
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let stream = Self::new(TcpStream::connect(addr)?)?;
+        // The stream becomes writable once it's connected or failed.
+        stream
+            .write_with(|io| match io.take_error()? {
+                Some(err) => Err(err),
+                None => io.peer_addr().map(drop).map_err(|err| match err.kind() {
+                    io::ErrorKind::NotConnected => io::ErrorKind::WouldBlock.into(),
+                    _ => err,
+                }),
+            })
+            .await?;
+        Ok(stream)
+    }
+}
+
+impl AsyncRead for Async<TcpStream> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl AsyncRead for &Async<TcpStream> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let io = &self.io;
+        self.source
+            .poll_io(cx, Direction::Read, || (&*io).read(buf))
+    }
+}
+
+impl AsyncWrite for Async<TcpStream> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+}
+
+impl AsyncWrite for &Async<TcpStream> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let io = &self.io;
+        self.source
+            .poll_io(cx, Direction::Write, || (&*io).write(buf))
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let io = &self.io;
+        self.source.poll_io(cx, Direction::Write, || (&*io).flush())
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.io.shutdown(Shutdown::Write))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Async, TcpListener, TcpStream};
+    use crate::post20200125::v5::block_on;
+    use crate::post20200131::v4::Executor;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    #[test]
+    fn echo() {
+        struct Test {
+            name: &'static str,
+            clients: usize,
+            size: usize,
+        }
+        let tests = [
+            Test {
+                name: "single small message",
+                clients: 1,
+                size: 16,
+            },
+            Test {
+                name: "multiple clients with large messages",
+                clients: 8,
+                size: 1 << 20,
+            },
+        ];
+        let executor = Executor::builder().threads(2).build();
+        for t in &tests {
+            let listener = Async::<TcpListener>::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+            let clients = t.clients;
+            let server = executor.spawn(async move {
+                let mut streams = Vec::new();
+                for _ in 0..clients {
+                    streams.push(listener.accept().await?.0);
+                }
+                let echoes = streams
+                    .iter()
+                    .map(|stream| async move { futures::io::copy(stream, &mut { stream }).await });
+                let mut total = 0;
+                for echoed in futures::future::join_all(echoes).await {
+                    total += echoed?;
+                }
+                Ok::<_, std::io::Error>(total)
+            });
+            let clients: Vec<_> = (0..t.clients)
+                .map(|i| {
+                    let size = t.size;
+                    executor.spawn(async move {
+                        let stream = Async::<TcpStream>::connect(addr).await?;
+                        let msg: Vec<u8> = (0..size).map(|j| (i + j) as u8).collect();
+                        let (mut reader, mut writer) = (&stream, &stream);
+                        let write = async {
+                            writer.write_all(&msg).await?;
+                            writer.close().await
+                        };
+                        let mut got = Vec::new();
+                        let read = reader.read_to_end(&mut got);
+                        let (written, read) = futures::future::join(write, read).await;
+                        written?;
+                        read?;
+                        Ok::<_, std::io::Error>(got == msg)
+                    })
+                })
+                .collect();
+            for got in block_on(futures::future::join_all(clients)) {
+                assert!(got.unwrap().unwrap(), "{}", t.name);
+            }
+            let want = (t.clients * t.size) as u64;
+            assert_eq!(want, block_on(server).unwrap().unwrap(), "{}", t.name);
+        }
+    }
+}
@@ -2,36 +2,54 @@
 ///
 /// [tokio echo server]: https://github.com/tokio-rs/book/blob/master/overview.md
 use std::error::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Instant;
+
+use codec_book::{Compat, Framed, LinesCodec};
+use futures::{SinkExt, StreamExt};
 use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio_book::aggregator::{Aggregator, Sink};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut runtime = Runtime::new()?;
 
     runtime.block_on(async {
         let mut listener = TcpListener::bind("127.0.0.1:8080").await?;
+        // Per-connection throughput in bytes/sec.
+        let (throughput, _) = Aggregator::builder()
+            .sink(Sink::Log("echo throughput (bytes/s)"))
+            .spawn();
 
         loop {
-            let (mut s, _addr) = listener.accept().await?;
+            let (s, _addr) = listener.accept().await?;
+            let throughput = throughput.clone();
 
             tokio::spawn(async move {
-                let mut buf = [0; 1024]; // char buf[1024];
+                let mut lines = Framed::new(Compat::new(s), LinesCodec::with_max_length(1024));
+                let start = Instant::now();
+                let mut total = 0;
 
-                loop {
-                    let n = match s.read(&mut buf).await {
-                        Ok(n) if n == 0 => return,
-                        Ok(n) => n,
+                while let Some(line) = lines.next().await {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) if e.is_recoverable() => {
+                            eprintln!("dropped the line: {}", e);
+                            continue;
+                        }
                         Err(e) => {
                             eprintln!("failed to read from socket: {:?}", e);
-                            return;
+                            break;
                         }
                     };
-                    if let Err(e) = s.write_all(&buf[..n]).await {
+                    let n = line.len() + 1;
+                    if let Err(e) = lines.send(line).await {
                         println!("failed to write to socket: {:?}", e);
-                        return;
+                        break;
                     }
+                    total += n as u64;
                 }
+                let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+                throughput.record((total as f64 / elapsed) as u64);
             });
         }
     })
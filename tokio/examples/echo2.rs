@@ -1,24 +1,57 @@
 //! Example: An Echo Server
 //!
+//! It's on the [`EchoServer`], which reports the per-connection
+//! throughput, and shuts down gracefully on Ctrl-C.
+//!
+//! ```sh
+//! $ cargo run --example echo2 127.0.0.1:0 16
+//! listening on 127.0.0.1:40107 up to 16 connections
+//! ```
 //! [an echo server]: https://github.com/tokio-rs/book/blob/master/getting-started/echo.md
+//! [`echoserver`]: ../tokio_book/server/struct.EchoServer.html
+use std::env::args;
 use std::error::Error;
-use tokio::io::copy;
-use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio_book::aggregator::{Aggregator, Sink};
+use tokio_book::EchoServer;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = args().skip(1);
+    let addr = args
+        .next()
+        .unwrap_or_else(|| String::from("127.0.0.1:8081"));
+    let max: usize = args
+        .next()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(1_024);
     let mut runtime = Runtime::new()?;
     runtime.block_on(async {
-        let mut l = TcpListener::bind("127.0.0.1:8081").await?;
-        loop {
-            let (mut s, addr) = l.accept().await?;
-            tokio::spawn(async move {
-                let (mut rx, mut tx) = s.split();
-                match copy(&mut rx, &mut tx).await {
-                    Ok(n) => println!("echoed {} bytes to {}", n, addr),
-                    Err(e) => eprintln!("copy error: {:?}", e),
-                }
-            });
-        }
+        // Per-connection throughput in bytes/sec.
+        let (throughput, _) = Aggregator::builder()
+            .sink(Sink::Log("echo2 throughput (bytes/s)"))
+            .spawn();
+        let server = EchoServer::builder()
+            .addr(addr)
+            .max_connections(max)
+            .throughput(throughput)
+            .bind()
+            .await?;
+        println!(
+            "listening on {} up to {} connections",
+            server.local_addr()?,
+            max
+        );
+        let handle = server.handle();
+        server
+            .serve(async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await?;
+        println!(
+            "served {} connections, echoed {} bytes",
+            handle.accepted(),
+            handle.bytes_written()
+        );
+        Ok(())
     })
 }
@@ -0,0 +1,412 @@
+// SPDX-License-Identifier: GPL-2.0
+//! [`Actor`] with the bounded mailbox and the supervised restart
+//!
+//! It's the generalized version of the `ping` and `pong` tasks in the
+//! [spawning] example.  The actor owns its state, and handles the typed
+//! messages one at a time in the mailbox order.  The callers talk to it
+//! through the [`Addr`], which waits for the mailbox capacity and then
+//! for the reply.  The [`Supervisor`] restarts the actor with the fresh
+//! state in case it panics.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::future::BoxFuture;
+//! use tokio_book::actor::{Actor, Supervisor};
+//!
+//! #[derive(Default)]
+//! struct Counter(usize);
+//!
+//! impl Actor for Counter {
+//!     type Message = usize;
+//!     type Reply = usize;
+//!     fn handle(&mut self, n: usize) -> BoxFuture<'_, usize> {
+//!         self.0 += n;
+//!         Box::pin(async move { self.0 })
+//!     }
+//! }
+//!
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(async {
+//!     let addr = Supervisor::new(Counter::default).mailbox(16).spawn();
+//!     addr.tell(1).await.unwrap();
+//!     assert_eq!(3, addr.call(2).await.unwrap());
+//! });
+//! ```
+//! [spawning]: https://tokio.rs/docs/futures/spawning/
+//! [`actor`]: trait.Actor.html
+//! [`addr`]: struct.Addr.html
+//! [`supervisor`]: struct.Supervisor.html
+use std::{error, fmt, panic::AssertUnwindSafe};
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::sync::{mpsc, oneshot};
+
+/// Default mailbox capacity.
+const MAILBOX: usize = 32;
+
+/// Default maximum number of the restarts.
+const MAX_RESTARTS: usize = 3;
+
+/// Actor, which handles the typed messages one at a time.
+pub trait Actor: Send + 'static {
+    type Message: Send + 'static;
+    type Reply: Send + 'static;
+    /// `handle` handles `msg` and returns the reply.
+    ///
+    /// The next message is handled once the returned future completes.
+    fn handle(&mut self, msg: Self::Message) -> BoxFuture<'_, Self::Reply>;
+}
+
+/// Error of the [`Addr`] calls.
+///
+/// [`addr`]: struct.Addr.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// The mailbox is full, only returned by [`try_tell`].
+    ///
+    /// [`try_tell`]: struct.Addr.html#method.try_tell
+    Full,
+    /// The actor is stopped and doesn't take the message anymore.
+    Closed,
+    /// The actor panicked, or stopped, before replying.
+    Crashed,
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Self::Full => "mailbox is full",
+            Self::Closed => "actor is stopped",
+            Self::Crashed => "actor crashed before replying",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl error::Error for CallError {}
+
+struct Envelope<A: Actor> {
+    msg: A::Message,
+    reply: Option<oneshot::Sender<A::Reply>>,
+}
+
+/// Address of the actor to send the messages to.
+///
+/// It's cheap to clone, and the actor stops once all the addresses are
+/// dropped and its mailbox is drained.
+pub struct Addr<A: Actor> {
+    tx: mpsc::Sender<Envelope<A>>,
+}
+
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<A: Actor> Addr<A> {
+    /// `call` sends `msg` to the actor and waits for the reply.
+    ///
+    /// It waits for the mailbox capacity first, which applies the
+    /// backpressure to the callers.
+    pub async fn call(&self, msg: A::Message) -> Result<A::Reply, CallError> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Envelope {
+            msg,
+            reply: Some(tx),
+        })
+        .await?;
+        rx.await.map_err(|_| CallError::Crashed)
+    }
+    /// `tell` sends `msg` to the actor without waiting for the reply.
+    pub async fn tell(&self, msg: A::Message) -> Result<(), CallError> {
+        self.send(Envelope { msg, reply: None }).await
+    }
+    /// `try_tell` sends `msg` to the actor in case the mailbox has the
+    /// capacity.
+    pub fn try_tell(&self, msg: A::Message) -> Result<(), CallError> {
+        let envelope = Envelope { msg, reply: None };
+        self.tx.clone().try_send(envelope).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => CallError::Full,
+            mpsc::error::TrySendError::Closed(_) => CallError::Closed,
+        })
+    }
+    async fn send(&self, envelope: Envelope<A>) -> Result<(), CallError> {
+        self.tx
+            .clone()
+            .send(envelope)
+            .await
+            .map_err(|_| CallError::Closed)
+    }
+}
+
+/// Supervisor, which spawns the actor and restarts it on panic.
+pub struct Supervisor<F> {
+    factory: F,
+    mailbox: usize,
+    max_restarts: usize,
+}
+
+impl<A, F> Supervisor<F>
+where
+    A: Actor,
+    F: Fn() -> A + Send + 'static,
+{
+    /// `new` creates a supervisor, which creates the actor with `factory`
+    /// on start and on every restart.
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory,
+            mailbox: MAILBOX,
+            max_restarts: MAX_RESTARTS,
+        }
+    }
+    /// `mailbox` sets the mailbox capacity, at least one.
+    pub fn mailbox(mut self, capacity: usize) -> Self {
+        self.mailbox = capacity.max(1);
+        self
+    }
+    /// `max_restarts` sets the maximum number of the restarts, after which
+    /// the actor stops on panic.
+    pub fn max_restarts(mut self, max: usize) -> Self {
+        self.max_restarts = max;
+        self
+    }
+    /// `spawn` spawns the actor onto the current runtime.
+    pub fn spawn(self) -> Addr<A> {
+        let (tx, rx) = mpsc::channel(self.mailbox);
+        tokio::spawn(self.run(rx));
+        Addr { tx }
+    }
+    async fn run(self, mut rx: mpsc::Receiver<Envelope<A>>) {
+        let mut actor = (self.factory)();
+        let mut restarts = 0;
+        while let Some(Envelope { msg, reply }) = rx.recv().await {
+            // Calls `handle` inside, to catch the panic before the future
+            // is returned, too.
+            let handled = AssertUnwindSafe(async { actor.handle(msg).await })
+                .catch_unwind()
+                .await;
+            match handled {
+                Ok(resp) => {
+                    if let Some(reply) = reply {
+                        // The caller may have gone.
+                        let _ = reply.send(resp);
+                    }
+                }
+                Err(_) if restarts < self.max_restarts => {
+                    restarts += 1;
+                    eprintln!("[actor]: restarting, restarts={}", restarts);
+                    actor = (self.factory)();
+                }
+                Err(_) => {
+                    eprintln!("[actor]: stopped after {} restarts", restarts);
+                    // Closes the mailbox before the caller sees the crash.
+                    drop(rx);
+                    drop(reply);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Actor, CallError, Supervisor};
+    use futures::future::BoxFuture;
+    use tokio::runtime::Runtime;
+    use tokio::sync::oneshot;
+
+    enum Msg {
+        Push(usize),
+        Get,
+        Panic,
+        /// Panics before returning the future.
+        PanicEarly,
+        /// Blocks the actor until the sender is dropped.
+        Block(oneshot::Receiver<()>),
+    }
+
+    #[derive(Default)]
+    struct Log(Vec<usize>);
+
+    impl Actor for Log {
+        type Message = Msg;
+        type Reply = Vec<usize>;
+        fn handle(&mut self, msg: Msg) -> BoxFuture<'_, Vec<usize>> {
+            if let Msg::PanicEarly = msg {
+                panic!("oops");
+            }
+            Box::pin(async move {
+                match msg {
+                    Msg::Push(v) => self.0.push(v),
+                    Msg::Get | Msg::PanicEarly => (),
+                    Msg::Panic => panic!("oops"),
+                    Msg::Block(rx) => {
+                        let _ = rx.await;
+                    }
+                }
+                self.0.clone()
+            })
+        }
+    }
+
+    #[test]
+    fn ordering() {
+        struct Test {
+            name: &'static str,
+            mailbox: usize,
+            callers: usize,
+            msgs: usize,
+        }
+        let tests = [
+            Test {
+                name: "single caller on one message mailbox",
+                mailbox: 1,
+                callers: 1,
+                msgs: 100,
+            },
+            Test {
+                name: "single caller on 32 messages mailbox",
+                mailbox: 32,
+                callers: 1,
+                msgs: 100,
+            },
+            Test {
+                name: "16 callers on one message mailbox",
+                mailbox: 1,
+                callers: 16,
+                msgs: 100,
+            },
+            Test {
+                name: "16 callers on 32 messages mailbox",
+                mailbox: 32,
+                callers: 16,
+                msgs: 100,
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        for t in &tests {
+            let got = rt.block_on(async {
+                let addr = Supervisor::new(Log::default).mailbox(t.mailbox).spawn();
+                let callers: Vec<_> = (0..t.callers)
+                    .map(|caller| {
+                        let addr = addr.clone();
+                        let msgs = t.msgs;
+                        tokio::spawn(async move {
+                            for i in 0..msgs {
+                                addr.tell(Msg::Push(caller * msgs + i)).await.unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+                for caller in callers {
+                    caller.await.unwrap();
+                }
+                addr.call(Msg::Get).await.unwrap()
+            });
+            assert_eq!(t.callers * t.msgs, got.len(), "{}", t.name);
+            // Each caller's messages are handled in order.
+            for caller in 0..t.callers {
+                let range = caller * t.msgs..(caller + 1) * t.msgs;
+                let mine: Vec<_> = got.iter().copied().filter(|v| range.contains(v)).collect();
+                assert_eq!(range.collect::<Vec<_>>(), mine, "{}", t.name);
+            }
+        }
+    }
+
+    #[test]
+    fn backpressure() {
+        struct Test {
+            name: &'static str,
+            mailbox: usize,
+        }
+        let tests = [
+            Test {
+                name: "one message mailbox",
+                mailbox: 1,
+            },
+            Test {
+                name: "four messages mailbox",
+                mailbox: 4,
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        for t in &tests {
+            rt.block_on(async {
+                let addr = Supervisor::new(Log::default).mailbox(t.mailbox).spawn();
+                let (unblock, blocked) = oneshot::channel();
+                let blocking = tokio::spawn({
+                    let addr = addr.clone();
+                    async move { addr.call(Msg::Block(blocked)).await }
+                });
+                // Fills up the mailbox behind the blocking message.
+                while addr.try_tell(Msg::Get).is_ok() {
+                    tokio::time::delay_for(std::time::Duration::from_millis(1)).await;
+                }
+                assert_eq!(
+                    Err(CallError::Full),
+                    addr.try_tell(Msg::Push(0)),
+                    "{}",
+                    t.name
+                );
+                // The caller waits for the capacity.
+                let mut pending = tokio::spawn({
+                    let addr = addr.clone();
+                    async move { addr.call(Msg::Push(1)).await }
+                });
+                let timeout = std::time::Duration::from_millis(50);
+                assert!(
+                    tokio::time::timeout(timeout, &mut pending).await.is_err(),
+                    "{}",
+                    t.name
+                );
+                drop(unblock);
+                blocking.await.unwrap().unwrap();
+                assert_eq!(vec![1], pending.await.unwrap().unwrap(), "{}", t.name);
+            });
+        }
+    }
+
+    #[test]
+    fn restart() {
+        struct Test {
+            name: &'static str,
+            panic: fn() -> Msg,
+        }
+        let tests = [
+            Test {
+                name: "panic in the future",
+                panic: || Msg::Panic,
+            },
+            Test {
+                name: "panic before the future",
+                panic: || Msg::PanicEarly,
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        for t in &tests {
+            rt.block_on(async {
+                let addr = Supervisor::new(Log::default).max_restarts(2).spawn();
+                for i in 0..2 {
+                    let got = addr.call(Msg::Push(i)).await;
+                    assert_eq!(Ok(vec![i]), got, "{}", t.name);
+                    let got = addr.call((t.panic)()).await;
+                    assert_eq!(Err(CallError::Crashed), got, "{}", t.name);
+                    // Restarted with the fresh state.
+                    let got = addr.call(Msg::Get).await;
+                    assert_eq!(Ok(vec![]), got, "{}", t.name);
+                }
+                // Stops after the maximum restarts.
+                let got = addr.call((t.panic)()).await;
+                assert_eq!(Err(CallError::Crashed), got, "{}", t.name);
+                let got = addr.call(Msg::Get).await;
+                assert_eq!(Err(CallError::Closed), got, "{}", t.name);
+            });
+        }
+    }
+}
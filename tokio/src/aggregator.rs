@@ -0,0 +1,379 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Background [`Aggregator`] of the values, e.g. the byte counts
+//!
+//! It's the reusable version of the `sum` task in the [spawning] example.
+//! The connection handlers record the values through the cheap
+//! [`Aggregator`] handle, and the background task reports the
+//! [`Stats`] of the sliding window to the [`Sink`] every interval.  The
+//! final stats are reported once all the handles are dropped.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use futures::{channel::mpsc, StreamExt};
+//! use tokio_book::aggregator::{Aggregator, Sink};
+//!
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(async {
+//!     let (tx, mut rx) = mpsc::unbounded();
+//!     let (aggregator, task) = Aggregator::<usize>::builder()
+//!         .interval(Duration::from_secs(5))
+//!         .sink(Sink::Channel(tx))
+//!         .spawn();
+//!     for bytes in 1..=100 {
+//!         aggregator.record(bytes);
+//!     }
+//!     drop(aggregator);
+//!     task.await.unwrap();
+//!
+//!     let stats = rx.next().await.unwrap();
+//!     assert_eq!(100, stats.count);
+//!     assert_eq!(5_050, stats.sum);
+//!     assert_eq!(Some(1), stats.min);
+//!     assert_eq!(Some(100), stats.max);
+//!     assert_eq!(Some(99), stats.p99);
+//! });
+//! ```
+//! [spawning]: https://tokio.rs/docs/futures/spawning/
+//! [`aggregator`]: struct.Aggregator.html
+//! [`stats`]: struct.Stats.html
+//! [`sink`]: enum.Sink.html
+use std::{collections::VecDeque, fmt, iter::Sum, time::Duration};
+
+use futures::{channel::mpsc, StreamExt};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+/// Default report interval.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Handle to record the values to the background aggregator task.
+///
+/// It's cheap to clone, and the task reports the final stats and exits
+/// once all the handles are dropped.
+pub struct Aggregator<T> {
+    tx: mpsc::UnboundedSender<T>,
+}
+
+impl<T> Clone for Aggregator<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<T> Aggregator<T>
+where
+    T: Copy + Ord + Sum<T> + Send + 'static,
+{
+    /// `builder` returns the [`Builder`] of the aggregator task.
+    ///
+    /// [`builder`]: struct.Builder.html
+    pub fn builder() -> Builder<T> {
+        Builder::default()
+    }
+    /// `record` records `value` to the current window.
+    pub fn record(&self, value: T) {
+        // The task is gone only when the runtime is shutting down.
+        let _ = self.tx.unbounded_send(value);
+    }
+}
+
+/// Builder of the [`Aggregator`] task.
+///
+/// [`aggregator`]: struct.Aggregator.html
+pub struct Builder<T> {
+    interval: Duration,
+    window: Option<Duration>,
+    sink: Sink<T>,
+}
+
+impl<T> Default for Builder<T> {
+    fn default() -> Self {
+        Self {
+            interval: INTERVAL,
+            window: None,
+            sink: Sink::Log("aggregator"),
+        }
+    }
+}
+
+impl<T> Builder<T>
+where
+    T: Copy + Ord + Sum<T> + Send + 'static,
+{
+    /// `interval` sets the report interval, 5 seconds by default.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// `window` sets the span of the sliding window, which is the report
+    /// interval by default.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = Some(window);
+        self
+    }
+    /// `sink` sets the sink of the stats, the log by default.
+    pub fn sink(mut self, sink: Sink<T>) -> Self {
+        self.sink = sink;
+        self
+    }
+    /// `spawn` spawns the aggregator task onto the current runtime.
+    pub fn spawn(self) -> (Aggregator<T>, JoinHandle<()>)
+    where
+        T: fmt::Display,
+    {
+        let (tx, rx) = mpsc::unbounded();
+        let window = self.window.unwrap_or(self.interval);
+        let task = tokio::spawn(run(rx, self.sink, self.interval, window));
+        (Aggregator { tx }, task)
+    }
+}
+
+/// Callback of the [`Sink`].
+///
+/// [`sink`]: enum.Sink.html
+pub type Callback<T> = Box<dyn FnMut(&Stats<T>) + Send>;
+
+/// Sink of the [`Stats`].
+///
+/// [`stats`]: struct.Stats.html
+pub enum Sink<T> {
+    /// Calls back with the stats.
+    Callback(Callback<T>),
+    /// Sends the stats over the channel.
+    Channel(mpsc::UnboundedSender<Stats<T>>),
+    /// Prints the stats to stdout with the name.
+    Log(&'static str),
+}
+
+impl<T: fmt::Display> Sink<T> {
+    fn report(&mut self, stats: Stats<T>) {
+        match self {
+            Self::Callback(f) => f(&stats),
+            Self::Channel(tx) => {
+                let _ = tx.unbounded_send(stats);
+            }
+            Self::Log(name) => println!("[{}]: {}", name, stats),
+        }
+    }
+}
+
+/// Stats of the values in the sliding window.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stats<T> {
+    /// Span of the window.
+    pub window: Duration,
+    pub count: usize,
+    pub sum: T,
+    pub min: Option<T>,
+    pub max: Option<T>,
+    /// 99th percentile by the nearest-rank method.
+    pub p99: Option<T>,
+}
+
+impl<T> Stats<T>
+where
+    T: Copy + Ord + Sum<T>,
+{
+    fn new(window: Duration, values: impl Iterator<Item = T>) -> Self {
+        let mut values: Vec<_> = values.collect();
+        values.sort_unstable();
+        let count = values.len();
+        // Rounds up without `usize::div_ceil`, which needs Rust 1.73.
+        #[allow(clippy::manual_div_ceil)]
+        let rank = (count * 99 + 99) / 100;
+        Self {
+            window,
+            count,
+            sum: values.iter().copied().sum(),
+            min: values.first().copied(),
+            max: values.last().copied(),
+            p99: rank.checked_sub(1).map(|i| values[i]),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Stats<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Opt<'a, T>(&'a Option<T>);
+        impl<T: fmt::Display> fmt::Display for Opt<'_, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.0 {
+                    Some(v) => v.fmt(f),
+                    None => f.write_str("-"),
+                }
+            }
+        }
+        write!(
+            f,
+            "window={:?} count={} sum={} min={} max={} p99={}",
+            self.window,
+            self.count,
+            self.sum,
+            Opt(&self.min),
+            Opt(&self.max),
+            Opt(&self.p99),
+        )
+    }
+}
+
+async fn run<T>(
+    mut rx: mpsc::UnboundedReceiver<T>,
+    mut sink: Sink<T>,
+    interval: Duration,
+    window: Duration,
+) where
+    T: Copy + Ord + Sum<T> + fmt::Display,
+{
+    let mut ticks = time::interval_at(Instant::now() + interval, interval);
+    let mut values = VecDeque::new();
+    loop {
+        tokio::select! {
+            value = rx.next() => match value {
+                Some(value) => values.push_back((Instant::now(), value)),
+                None => break,
+            },
+            now = ticks.tick() => {
+                // Slides the window.
+                while matches!(values.front(), Some((at, _)) if now - *at > window) {
+                    values.pop_front();
+                }
+                sink.report(Stats::new(window, values.iter().map(|(_, v)| *v)));
+            }
+        }
+    }
+    sink.report(Stats::new(window, values.into_iter().map(|(_, v)| v)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aggregator, Sink, Stats};
+    use futures::{channel::mpsc, StreamExt};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::runtime::{Builder, Runtime};
+    use tokio::time;
+    #[test]
+    fn stats() {
+        struct Test {
+            name: &'static str,
+            values: Vec<u64>,
+            want: Stats<u64>,
+        }
+        let window = Duration::from_secs(1);
+        let tests = [
+            Test {
+                name: "empty",
+                values: vec![],
+                want: Stats {
+                    window,
+                    count: 0,
+                    sum: 0,
+                    min: None,
+                    max: None,
+                    p99: None,
+                },
+            },
+            Test {
+                name: "single value",
+                values: vec![7],
+                want: Stats {
+                    window,
+                    count: 1,
+                    sum: 7,
+                    min: Some(7),
+                    max: Some(7),
+                    p99: Some(7),
+                },
+            },
+            Test {
+                name: "unsorted values",
+                values: vec![3, 1, 2],
+                want: Stats {
+                    window,
+                    count: 3,
+                    sum: 6,
+                    min: Some(1),
+                    max: Some(3),
+                    p99: Some(3),
+                },
+            },
+            Test {
+                name: "1,000 values",
+                values: (1..=1_000).rev().collect(),
+                want: Stats {
+                    window,
+                    count: 1_000,
+                    sum: 500_500,
+                    min: Some(1),
+                    max: Some(1_000),
+                    p99: Some(990),
+                },
+            },
+        ];
+        for t in &tests {
+            let got = Stats::new(window, t.values.iter().copied());
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn sinks() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let got = Arc::new(Mutex::new(Vec::new()));
+            let cloned = got.clone();
+            let callback = Sink::Callback(Box::new(move |stats: &Stats<usize>| {
+                cloned.lock().unwrap().push(stats.sum)
+            }));
+            let (tx, rx) = mpsc::unbounded();
+            for sink in [callback, Sink::Channel(tx), Sink::Log("sinks")] {
+                let (aggregator, task) = Aggregator::builder().sink(sink).spawn();
+                for i in 0..10 {
+                    aggregator.clone().record(i);
+                }
+                drop(aggregator);
+                task.await.unwrap();
+            }
+            assert_eq!(vec![45], *got.lock().unwrap());
+            let sums: Vec<_> = rx.map(|stats| stats.sum).collect().await;
+            assert_eq!(vec![45], sums);
+        });
+    }
+    #[test]
+    fn sliding_window() {
+        // Runs on the paused clock, which advances to the next timer once
+        // the runtime is idle.
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        let got = rt.block_on(async {
+            time::pause();
+            let (tx, rx) = mpsc::unbounded();
+            let (aggregator, task) = Aggregator::builder()
+                .interval(Duration::from_millis(100))
+                .window(Duration::from_millis(200))
+                .sink(Sink::Channel(tx))
+                .spawn();
+            // Records a value in the middle of each interval.
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+            for i in 1..=4 {
+                aggregator.record(i);
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+            }
+            drop(aggregator);
+            task.await.unwrap();
+            rx.collect::<Vec<_>>().await
+        });
+        // The window covers the last two values on every tick.
+        let counts: Vec<_> = got.iter().map(|stats| stats.count).collect();
+        let maxes: Vec<_> = got.iter().map(|stats| stats.max).collect();
+        assert_eq!(vec![1, 2, 2, 2, 2], counts);
+        assert_eq!(vec![Some(1), Some(2), Some(3), Some(4), Some(4)], maxes);
+    }
+}
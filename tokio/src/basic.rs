@@ -1,5 +1,10 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{self, Future};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 // https://tokio.rs/docs/futures/basic/
 pub struct HelloWorld {
@@ -8,23 +13,23 @@ pub struct HelloWorld {
 }
 
 impl HelloWorld {
-    #[allow(dead_code)]
     pub fn new(limit: u32) -> Self {
         Self { limit, count: 0 }
     }
 }
 
 impl Future for HelloWorld {
-    type Item = String;
-    type Error = ();
-    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+    type Output = String;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         const NAME: &str = "basic::HelloWorld";
         self.count += 1;
         if self.count < self.limit {
             eprintln!("[{}]: count={}", NAME, self.count);
-            Ok(futures::Async::NotReady)
+            // Asks to be polled again, as nobody else wakes it up.
+            cx.waker().wake_by_ref();
+            Poll::Pending
         } else {
-            Ok(futures::Async::Ready("hello world".to_string()))
+            Poll::Ready("hello world".to_string())
         }
     }
 }
@@ -33,24 +38,21 @@ pub struct Display<T>(pub T);
 
 impl<T> Future for Display<T>
 where
-    T: Future,
-    T::Item: std::fmt::Display,
+    T: Future + Unpin,
+    T::Output: fmt::Display,
 {
-    type Item = ();
-    type Error = T::Error;
-
-    fn poll(&mut self) -> futures::Poll<(), T::Error> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         const NAME: &str = "basic::Display";
-        let value = match self.0.poll() {
-            Ok(futures::Async::Ready(value)) => value,
-            Ok(futures::Async::NotReady) => {
-                eprintln!("[{}]: Async::NotReady", NAME);
-                return Ok(futures::Async::NotReady);
+        let value = match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(value) => value,
+            Poll::Pending => {
+                eprintln!("[{}]: Poll::Pending", NAME);
+                return Poll::Pending;
             }
-            Err(err) => return Err(err),
         };
         println!("[{}]: {}", NAME, value);
-        Ok(futures::Async::Ready(()))
+        Poll::Ready(())
     }
 }
 
@@ -58,43 +60,63 @@ pub struct BetterDisplay<T>(pub T);
 
 impl<T> Future for BetterDisplay<T>
 where
-    T: Future,
-    T::Item: std::fmt::Display,
+    T: Future + Unpin,
+    T::Output: fmt::Display,
 {
-    type Item = ();
-    type Error = T::Error;
-
-    fn poll(&mut self) -> futures::Poll<(), T::Error> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         const NAME: &str = "basic::BetterDisplay";
-        let value = futures::try_ready!(self.0.poll());
+        let value = futures::ready!(Pin::new(&mut self.0).poll(cx));
         println!("[{}]: {}", NAME, value);
-        Ok(futures::Async::Ready(()))
+        Poll::Ready(())
     }
 }
 
-#[allow(dead_code)]
-pub fn display(count: u32) -> impl Future<Item = (), Error = ()> {
+pub fn display(count: u32) -> impl Future<Output = ()> {
     Display(HelloWorld::new(count))
 }
 
-#[allow(dead_code)]
-pub fn better_display(count: u32) -> impl Future<Item = (), Error = ()> {
+pub fn better_display(count: u32) -> impl Future<Output = ()> {
     BetterDisplay(HelloWorld::new(count))
 }
 
 #[cfg(test)]
 mod tests {
-    use tokio;
+    use tokio::runtime::Runtime;
+    #[test]
+    fn hello_world() {
+        struct Test {
+            name: &'static str,
+            limit: u32,
+        }
+        let tests = [
+            Test {
+                name: "ready on the first poll",
+                limit: 1,
+            },
+            Test {
+                name: "ready on the fifth poll",
+                limit: 5,
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        for t in &tests {
+            let got = rt.block_on(super::HelloWorld::new(t.limit));
+            assert_eq!("hello world", got, "{}", t.name);
+        }
+    }
     #[test]
     fn run_hello_display() {
-        let count = 1;
-        let fut = super::Display(super::HelloWorld::new(count));
-        tokio::run(fut);
+        let mut rt = Runtime::new().unwrap();
+        for count in 1..3 {
+            rt.block_on(super::display(count));
+        }
     }
     #[test]
     fn run_hello_better_display() {
-        let count = 1;
-        let fut = super::BetterDisplay(super::HelloWorld::new(count));
-        tokio::run(fut);
+        let mut rt = Runtime::new().unwrap();
+        for count in 1..3 {
+            rt.block_on(super::better_display(count));
+        }
     }
 }
@@ -1,31 +1,38 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{self, Future};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::FutureExt;
 
 // https://tokio.rs/docs/futures/combinators/
 pub struct HelloWorld;
 
 impl Future for HelloWorld {
-    type Item = String;
-    type Error = ();
-    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+    type Output = String;
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
         const NAME: &str = "combinator::HelloWorld";
         eprintln!("[{}] poll()", NAME);
-        Ok(futures::Async::Ready(format!("[{}]: hello world", NAME)))
+        Poll::Ready(format!("[{}]: hello world", NAME))
     }
 }
 
-#[allow(dead_code)]
-pub fn hello() -> impl Future<Item = (), Error = ()> {
+pub fn hello() -> impl Future<Output = ()> {
     const NAME: &str = "combinator::hello";
     HelloWorld.map(|msg| println!("[{}]: {}", NAME, msg))
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::FutureExt;
+    use tokio::runtime::Runtime;
     #[test]
     fn map() {
-        use super::Future;
-        let fut = super::HelloWorld;
-        tokio::run(fut.map(|msg| println!("{}", msg)));
+        let mut rt = Runtime::new().unwrap();
+        let got = rt.block_on(super::HelloWorld.map(|msg| msg.len()));
+        assert_eq!("[combinator::HelloWorld]: hello world".len(), got);
+        rt.block_on(super::hello());
     }
 }
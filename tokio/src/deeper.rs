@@ -1,217 +1,434 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{Async, Future, Poll};
-use std::net::SocketAddr;
-use tokio::net::tcp::{ConnectFuture, TcpStream};
+use std::{
+    collections::HashMap,
+    error, fmt,
+    future::Future,
+    io, mem,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    vec,
+};
+
+use futures::future;
+
+use tokio::net::TcpStream;
 
 #[derive(Debug)]
 pub struct Doubler<T> {
     inner: T,
 }
 
-#[allow(dead_code)]
 pub fn double<T>(inner: T) -> Doubler<T> {
     Doubler { inner }
 }
 
-impl<T> Future for Doubler<T>
+impl<T, E> Future for Doubler<T>
 where
-    T: Future<Item = usize>,
+    T: Future<Output = Result<usize, E>> + Unpin,
 {
-    type Item = usize;
-    type Error = T::Error;
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.inner.poll()? {
-            Async::Ready(v) => Ok(Async::Ready(v * 2)),
-            Async::NotReady => Ok(Async::NotReady),
+    type Output = Result<usize, E>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match futures::ready!(Pin::new(&mut self.inner).poll(cx)) {
+            Ok(v) => Poll::Ready(Ok(v * 2)),
+            Err(err) => Poll::Ready(Err(err)),
         }
     }
 }
 
+type ConnectFuture = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+/// Future of the resolved addresses, returned by the [`Resolver`].
+///
+/// [`resolver`]: trait.Resolver.html
+pub type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Pluggable name resolver of [`ResolveAndConnect`].
+///
+/// [`resolveandconnect`]: struct.ResolveAndConnect.html
+pub trait Resolver {
+    /// `resolve` resolves `host`, e.g. "localhost:80", to the addresses.
+    fn resolve(&self, host: &str) -> ResolveFuture;
+}
+
+/// Default resolver, backed by `tokio::net::lookup_host`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioResolver;
+
+impl Resolver for TokioResolver {
+    fn resolve(&self, host: &str) -> ResolveFuture {
+        let host = host.to_string();
+        Box::pin(async move { Ok(tokio::net::lookup_host(host).await?.collect()) })
+    }
+}
+
+/// In-memory resolver with the static host table, e.g. for tests.
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<SocketAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// `host` adds `host` resolved to `addrs`.
+    pub fn host(mut self, host: &str, addrs: &[SocketAddr]) -> Self {
+        self.hosts.insert(host.to_string(), addrs.to_vec());
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, host: &str) -> ResolveFuture {
+        let addrs = self.hosts.get(host).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("unknown host: {}", host))
+        });
+        Box::pin(future::ready(addrs))
+    }
+}
+
 enum State {
-    #[allow(dead_code)]
     Resolving(ResolveFuture),
-    Connecting(ConnectFuture),
+    Connecting {
+        addr: SocketAddr,
+        conn: ConnectFuture,
+        rest: vec::IntoIter<SocketAddr>,
+    },
+    Done,
 }
 
+/// Future to resolve the host and to connect to one of its addresses.
+///
+/// It tries the resolved addresses in order and fails with the
+/// [`ConnectError`] once none of them connects.
+///
+/// [`connecterror`]: struct.ConnectError.html
 pub struct ResolveAndConnect {
     state: State,
+    errors: Vec<(SocketAddr, io::Error)>,
+}
+
+impl ResolveAndConnect {
+    /// `new` resolves `host` with `resolver` and connects to it.
+    pub fn new<R: Resolver + ?Sized>(resolver: &R, host: &str) -> Self {
+        Self {
+            state: State::Resolving(resolver.resolve(host)),
+            errors: Vec::new(),
+        }
+    }
+    fn next(&mut self, mut rest: vec::IntoIter<SocketAddr>) -> Option<io::Error> {
+        match rest.next() {
+            Some(addr) => {
+                let conn = Box::pin(TcpStream::connect(addr));
+                self.state = State::Connecting { addr, conn, rest };
+                None
+            }
+            None => {
+                self.state = State::Done;
+                let errors = mem::take(&mut self.errors);
+                Some(ConnectError { errors }.into())
+            }
+        }
+    }
 }
 
 impl Future for ResolveAndConnect {
-    type Item = TcpStream;
-    type Error = std::io::Error;
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    type Output = io::Result<TcpStream>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
         loop {
-            let addr = match self.state {
-                State::Resolving(ref mut fut) => futures::try_ready!(fut.poll()),
-                State::Connecting(ref mut fut) => return fut.poll(),
+            let rest = match &mut this.state {
+                State::Resolving(fut) => match futures::ready!(fut.as_mut().poll(cx)) {
+                    Ok(addrs) => addrs.into_iter(),
+                    Err(err) => {
+                        this.state = State::Done;
+                        return Poll::Ready(Err(err));
+                    }
+                },
+                State::Connecting { addr, conn, rest } => {
+                    match futures::ready!(conn.as_mut().poll(cx)) {
+                        Ok(stream) => {
+                            this.state = State::Done;
+                            return Poll::Ready(Ok(stream));
+                        }
+                        Err(err) => {
+                            this.errors.push((*addr, err));
+                            mem::replace(rest, Vec::new().into_iter())
+                        }
+                    }
+                }
+                State::Done => panic!("`ResolveAndConnect` polled after completion"),
             };
-            let connecting = TcpStream::connect(&addr);
-            self.state = State::Connecting(connecting);
+            if let Some(err) = this.next(rest) {
+                return Poll::Ready(Err(err));
+            }
         }
     }
 }
 
-#[allow(dead_code)]
-pub fn resolve_and_connect(host: &'static str) -> ResolveAndConnect {
-    let state = State::Resolving(resolve(host));
-    ResolveAndConnect { state }
+/// `resolve_and_connect` resolves `host` with the [`TokioResolver`] and
+/// connects to it.
+///
+/// [`tokioresolver`]: struct.TokioResolver.html
+pub fn resolve_and_connect(host: &str) -> ResolveAndConnect {
+    ResolveAndConnect::new(&TokioResolver, host)
+}
+
+/// Error of [`ResolveAndConnect`] with the errors of all the attempts.
+///
+/// It's wrapped in `io::Error` and available through `io::Error::get_ref`.
+///
+/// [`resolveandconnect`]: struct.ResolveAndConnect.html
+#[derive(Debug)]
+pub struct ConnectError {
+    errors: Vec<(SocketAddr, io::Error)>,
 }
 
-struct ResolveFuture {
-    host: &'static str,
+impl ConnectError {
+    /// `errors` returns the address and the error of each attempt.
+    pub fn errors(&self) -> &[(SocketAddr, io::Error)] {
+        &self.errors
+    }
 }
 
-impl Future for ResolveFuture {
-    type Item = SocketAddr;
-    type Error = std::io::Error;
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // It only support the address, e.g. "127.0.0.1", for now.
-        use std::io::{self, ErrorKind};
-        match self.host.parse::<SocketAddr>() {
-            Ok(addr) => Ok(Async::Ready(addr)),
-            Err(err) => Err(io::Error::new(ErrorKind::InvalidInput, format!("{}", err))),
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.errors.is_empty() {
+            return f.write_str("no address to connect");
+        }
+        f.write_str("cannot connect to any address: ")?;
+        for (i, (addr, err)) in self.errors.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {}", addr, err)?;
         }
+        Ok(())
     }
 }
 
-fn resolve(host: &'static str) -> ResolveFuture {
-    ResolveFuture { host }
+impl error::Error for ConnectError {}
+
+impl From<ConnectError> for io::Error {
+    fn from(err: ConnectError) -> Self {
+        let kind = match err.errors.last() {
+            Some((_, err)) => err.kind(),
+            None => io::ErrorKind::NotFound,
+        };
+        io::Error::new(kind, err)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{ConnectError, ResolveAndConnect, Resolver, StaticResolver, TokioResolver};
+    use std::io;
+    use std::net::{SocketAddr, TcpListener};
+    use tokio::runtime::Runtime;
     #[test]
     fn double_ok() {
-        use futures::{Async, Future};
+        use futures::FutureExt;
         struct Test {
             name: &'static str,
             data: usize,
-            want: Result<Async<usize>, ()>,
-        };
+            want: Option<Result<usize, ()>>,
+        }
         let tests = [
             Test {
                 name: "1usize",
                 data: 1,
-                want: Ok(Async::Ready(2)),
+                want: Some(Ok(2)),
             },
             Test {
                 name: "2usize",
                 data: 2,
-                want: Ok(Async::Ready(4)),
+                want: Some(Ok(4)),
             },
             Test {
                 name: "16usize",
                 data: 16,
-                want: Ok(Async::Ready(32)),
+                want: Some(Ok(32)),
             },
             Test {
                 name: "10_001usize",
                 data: 10_001,
-                want: Ok(Async::Ready(20_002)),
+                want: Some(Ok(20_002)),
             },
         ];
         for t in &tests {
-            let got = super::double(futures::future::ok::<usize, ()>(t.data)).poll();
-            debug_assert_eq!(t.want, got, "{}", t.name);
+            let got = super::double(futures::future::ok::<usize, ()>(t.data)).now_or_never();
+            assert_eq!(t.want, got, "{}", t.name);
         }
     }
     #[test]
     fn double_err() {
-        use futures::{Async, Future};
+        use futures::FutureExt;
         struct Test {
             name: &'static str,
             data: std::io::ErrorKind,
-            want: Result<Async<usize>, std::io::ErrorKind>,
-        };
+            want: Option<Result<usize, std::io::ErrorKind>>,
+        }
         let tests = [
             Test {
                 name: "InvalidInput",
                 data: std::io::ErrorKind::InvalidInput,
-                want: Err(std::io::ErrorKind::InvalidInput),
+                want: Some(Err(std::io::ErrorKind::InvalidInput)),
             },
             Test {
                 name: "InvalidData",
                 data: std::io::ErrorKind::InvalidData,
-                want: Err(std::io::ErrorKind::InvalidData),
+                want: Some(Err(std::io::ErrorKind::InvalidData)),
             },
         ];
         for t in &tests {
-            let got =
-                super::double(futures::future::err::<usize, std::io::ErrorKind>(t.data)).poll();
-            debug_assert_eq!(t.want, got, "{}", t.name);
+            let got = super::double(futures::future::err::<usize, std::io::ErrorKind>(t.data))
+                .now_or_never();
+            assert_eq!(t.want, got, "{}", t.name);
         }
     }
     #[test]
     fn resolve_ok() {
-        use futures::{Async, Future};
-        use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+        use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
         struct Test {
             name: &'static str,
             addr: &'static str,
-            want: Async<SocketAddr>,
-        };
+            want: SocketAddr,
+        }
         let tests = [
             Test {
                 name: "IPv4 localhost:8080",
                 addr: "127.0.0.1:8080",
-                want: Async::Ready(SocketAddr::V4(SocketAddrV4::new(
-                    Ipv4Addr::new(127, 0, 0, 1),
-                    8080,
-                ))),
+                want: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080)),
             },
             Test {
                 name: "IPv4 8.8.8.8:52",
                 addr: "8.8.8.8:52",
-                want: Async::Ready(SocketAddr::V4(SocketAddrV4::new(
-                    Ipv4Addr::new(8, 8, 8, 8),
-                    52,
-                ))),
+                want: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(8, 8, 8, 8), 52)),
             },
             Test {
                 name: "IPv4 1.2.3.4:56789",
                 addr: "1.2.3.4:56789",
-                want: Async::Ready(SocketAddr::V4(SocketAddrV4::new(
-                    Ipv4Addr::new(1, 2, 3, 4),
-                    56789,
-                ))),
+                want: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 56789)),
             },
             Test {
                 name: "IPv6 ::1:80",
                 addr: "[::1]:80",
-                want: Async::Ready(SocketAddr::V6(SocketAddrV6::new(
+                want: SocketAddr::V6(SocketAddrV6::new(
                     Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1),
                     80,
                     0,
                     0,
-                ))),
+                )),
             },
             Test {
                 name: "IPv6 2002::53:53",
                 addr: "[2002::53]:53",
-                want: Async::Ready(SocketAddr::V6(SocketAddrV6::new(
+                want: SocketAddr::V6(SocketAddrV6::new(
                     Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0x53),
                     53,
                     0,
                     0,
-                ))),
+                )),
             },
             Test {
                 name: "IPv6 2002::1:80",
                 addr: "[2002::1]:80",
-                want: Async::Ready(SocketAddr::V6(SocketAddrV6::new(
+                want: SocketAddr::V6(SocketAddrV6::new(
                     Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 1),
                     80,
                     0,
                     0,
-                ))),
+                )),
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
-            match super::resolve(t.addr).poll() {
-                Ok(got) => debug_assert_eq!(t.want, got, "{}", t.name),
+            match rt.block_on(TokioResolver.resolve(t.addr)) {
+                Ok(got) => assert_eq!(vec![t.want], got, "{}", t.name),
                 Err(err) => panic!("{}: {}", t.name, err),
             }
         }
     }
+    #[test]
+    fn resolve_and_connect() {
+        // Listener to be refused once it's dropped.
+        let refusing = || {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listening = l.local_addr().unwrap();
+        let (refused1, refused2) = (refusing(), refusing());
+        let resolver = StaticResolver::new()
+            .host("listening:80", &[listening])
+            .host("refusing-first:80", &[refused1, listening])
+            .host("refusing:80", &[refused1, refused2])
+            .host("empty:80", &[]);
+        struct Test {
+            name: &'static str,
+            host: &'static str,
+            want: Result<SocketAddr, io::ErrorKind>,
+            errors: Vec<SocketAddr>,
+        }
+        let tests = [
+            Test {
+                name: "single address",
+                host: "listening:80",
+                want: Ok(listening),
+                errors: vec![],
+            },
+            Test {
+                name: "second address",
+                host: "refusing-first:80",
+                want: Ok(listening),
+                errors: vec![],
+            },
+            Test {
+                name: "all addresses refused",
+                host: "refusing:80",
+                want: Err(io::ErrorKind::ConnectionRefused),
+                errors: vec![refused1, refused2],
+            },
+            Test {
+                name: "no address",
+                host: "empty:80",
+                want: Err(io::ErrorKind::NotFound),
+                errors: vec![],
+            },
+            Test {
+                name: "unknown host",
+                host: "unknown:80",
+                want: Err(io::ErrorKind::NotFound),
+                errors: vec![],
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        for t in &tests {
+            let got = rt.block_on(ResolveAndConnect::new(&resolver, t.host));
+            match (&t.want, got) {
+                (Ok(want), Ok(stream)) => {
+                    assert_eq!(*want, stream.peer_addr().unwrap(), "{}", t.name)
+                }
+                (Err(want), Err(err)) => {
+                    assert_eq!(*want, err.kind(), "{}", t.name);
+                    let got: Vec<_> = err
+                        .get_ref()
+                        .and_then(|err| err.downcast_ref::<ConnectError>())
+                        .map(|err| err.errors().iter().map(|(addr, _)| *addr).collect())
+                        .unwrap_or_default();
+                    assert_eq!(t.errors, got, "{}", t.name);
+                }
+                (want, got) => panic!("{}: want {:?}, got {:?}", t.name, want, got),
+            }
+        }
+    }
+    #[test]
+    fn resolve_and_connect_localhost() {
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("localhost:{}", l.local_addr().unwrap().port());
+        let mut rt = Runtime::new().unwrap();
+        let stream = rt.block_on(super::resolve_and_connect(&host)).unwrap();
+        assert_eq!(l.local_addr().unwrap(), stream.peer_addr().unwrap());
+    }
 }
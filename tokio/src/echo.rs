@@ -1,73 +1,108 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{Future, Poll, Stream};
-use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
-
-pub enum Handler {
-    Greeting(TcpStream),
-    Handling(TcpStream),
-}
+use std::{future::Future, io, pin::Pin};
 
-impl Future for Handler {
-    type Item = ();
-    type Error = ();
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        Ok(futures::Async::NotReady)
-    }
-}
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
 
-pub fn server2(addr: &SocketAddr) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+pub fn server2(mut l: TcpListener) -> Pin<Box<dyn Future<Output = ()> + Send>> {
     const NAME: &str = "echo::server2";
-    let l = match TcpListener::bind(addr) {
-        Ok(l) => l,
-        Err(err) => {
-            eprintln!("[{}]: cannot bind: {}", NAME, err);
-            return Box::new(futures::future::err(()));
-        }
-    };
     // https://tokio.rs/docs/going-deeper/returning/
-    let s = l
-        .incoming()
-        .map_err(|err| eprintln!("[{}]: cannot accept: {}", NAME, err))
-        .for_each(|_sock| Ok(()));
-    Box::new(s)
+    Box::pin(async move {
+        loop {
+            match l.accept().await {
+                // Drops the socket.
+                Ok((_sock, _)) => (),
+                Err(err) => eprintln!("[{}]: cannot accept: {}", NAME, err),
+            }
+        }
+    })
 }
 
-pub fn server1(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
+pub async fn server1(mut l: TcpListener) {
     const NAME: &str = "echo::server1";
-    let l = TcpListener::bind(addr)
-        .unwrap_or_else(|err| panic!(format!("[{}]: cannot bind: {}", NAME, err)));
-    l.incoming()
-        .map_err(|err| eprintln!("[{}]: accept failed: {}", NAME, err))
-        .for_each(|sock| {
-            tokio::spawn(
-                greeting(sock)
-                    .map_err(|err| eprintln!("[{}]: greeting error: {}", NAME, err))
-                    .map(|sock| {
-                        tokio::spawn(
-                            handle(sock)
-                                .map_err(|err| eprintln!("[{}]: handle error: {}", NAME, err))
-                                .map(|sock| match sock.peer_addr() {
-                                    Ok(peer) => {
-                                        println!("[{}]: taken care of {}", NAME, peer);
-                                    }
-                                    Err(err) => {
-                                        eprintln!("[{}]: peer_addr(): {:?}", NAME, err);
-                                    }
-                                }),
-                        );
-                    }),
-            );
-            Ok(())
-        })
+    loop {
+        let sock = match l.accept().await {
+            Ok((sock, _)) => sock,
+            Err(err) => {
+                eprintln!("[{}]: accept failed: {}", NAME, err);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let sock = match greeting(sock).await {
+                Ok(sock) => sock,
+                Err(err) => return eprintln!("[{}]: greeting error: {}", NAME, err),
+            };
+            match handle(sock).await {
+                Ok(sock) => match sock.peer_addr() {
+                    Ok(peer) => println!("[{}]: taken care of {}", NAME, peer),
+                    Err(err) => eprintln!("[{}]: peer_addr(): {:?}", NAME, err),
+                },
+                Err(err) => eprintln!("[{}]: handle error: {}", NAME, err),
+            }
+        });
+    }
+}
+
+async fn greeting(mut sock: TcpStream) -> io::Result<TcpStream> {
+    sock.write_all(b"Howdy!\n").await?;
+    Ok(sock)
 }
 
-fn greeting(sock: TcpStream) -> impl Future<Item = TcpStream, Error = tokio::io::Error> {
-    tokio::io::write_all(sock, "Howdy!\n").map(|(sock, _)| sock)
+async fn handle(mut sock: TcpStream) -> io::Result<TcpStream> {
+    let (mut reader, mut writer) = sock.split();
+    tokio::io::copy(&mut reader, &mut writer).await?;
+    Ok(sock)
 }
 
-fn handle(sock: TcpStream) -> impl Future<Item = TcpStream, Error = tokio::io::Error> {
-    use tokio::io::AsyncRead;
-    let (reader, writer) = sock.split();
-    tokio::io::copy(reader, writer).map(|(_, r, w)| r.unsplit(w))
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::runtime::Runtime;
+    #[test]
+    fn server1() {
+        struct Test {
+            name: &'static str,
+            data: &'static [u8],
+        }
+        let tests = [
+            Test {
+                name: "no data",
+                data: b"",
+            },
+            Test {
+                name: "hello world",
+                data: b"hello world",
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = l.local_addr().unwrap();
+            tokio::spawn(super::server1(l));
+            for t in &tests {
+                let mut s = TcpStream::connect(addr).await.unwrap();
+                s.write_all(t.data).await.unwrap();
+                s.shutdown(std::net::Shutdown::Write).unwrap();
+                let mut got = Vec::new();
+                s.read_to_end(&mut got).await.unwrap();
+                let want = [&b"Howdy!\n"[..], t.data].concat();
+                assert_eq!(want, got, "{}", t.name);
+            }
+        });
+    }
+    #[test]
+    fn server2() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = l.local_addr().unwrap();
+            tokio::spawn(super::server2(l));
+            let mut s = TcpStream::connect(addr).await.unwrap();
+            let mut got = Vec::new();
+            s.read_to_end(&mut got).await.unwrap();
+            assert!(got.is_empty());
+        });
+    }
 }
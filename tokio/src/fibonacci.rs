@@ -1,5 +1,14 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{self, Future};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use tokio::time::{self, Interval};
 
 // https://tokio.rs/docs/futures/streams/
 pub struct Fibonacci {
@@ -8,7 +17,6 @@ pub struct Fibonacci {
 }
 
 impl Fibonacci {
-    #[allow(dead_code)]
     pub fn new() -> Self {
         Self::default()
     }
@@ -20,49 +28,45 @@ impl Default for Fibonacci {
     }
 }
 
-impl futures::Stream for Fibonacci {
+impl Stream for Fibonacci {
     type Item = u64;
-    type Error = ();
-    fn poll(&mut self) -> futures::Poll<Option<u64>, ()> {
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<u64>> {
         let curr = self.curr;
         let next = curr + self.next;
         self.curr = self.next;
         self.next = next;
-        Ok(futures::Async::Ready(Some(curr)))
+        Poll::Ready(Some(curr))
     }
 }
 
 pub struct SlowFibonacci {
-    interval: tokio::timer::Interval,
+    interval: Interval,
     curr: u64,
     next: u64,
 }
 
 impl SlowFibonacci {
-    #[allow(dead_code)]
-    pub fn new(duration: std::time::Duration) -> Self {
+    /// `new` creates the stream, which yields the number every `duration`.
+    ///
+    /// It should be called within the Tokio runtime.
+    pub fn new(duration: Duration) -> Self {
         Self {
-            interval: tokio::timer::Interval::new_interval(duration),
+            interval: time::interval(duration),
             curr: 1,
             next: 1,
         }
     }
 }
 
-impl futures::Stream for SlowFibonacci {
+impl Stream for SlowFibonacci {
     type Item = u64;
-    type Error = ();
-    fn poll(&mut self) -> futures::Poll<Option<u64>, ()> {
-        futures::try_ready!(self
-            .interval
-            .poll()
-            // ignore error
-            .map_err(|_| ()));
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<u64>> {
+        futures::ready!(self.interval.poll_tick(cx));
         let curr = self.curr;
         let next = curr + self.next;
         self.curr = self.next;
         self.next = next;
-        Ok(futures::Async::Ready(Some(curr)))
+        Poll::Ready(Some(curr))
     }
 }
 
@@ -73,7 +77,6 @@ pub struct Display<T> {
 }
 
 impl<T> Display<T> {
-    #[allow(dead_code)]
     pub fn new(stream: T, max: usize) -> Self {
         Self {
             stream,
@@ -85,34 +88,34 @@ impl<T> Display<T> {
 
 impl<T> Future for Display<T>
 where
-    T: futures::Stream,
-    T::Item: std::fmt::Display,
+    T: Stream + Unpin,
+    T::Item: fmt::Display,
 {
-    type Item = ();
-    type Error = T::Error;
-    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         while self.curr < self.max {
-            let value = match futures::try_ready!(self.stream.poll()) {
+            let value = match futures::ready!(self.stream.poll_next_unpin(cx)) {
                 Some(value) => value,
                 None => break,
             };
             println!("value #{} = {}", self.curr, value);
             self.curr += 1;
         }
-        Ok(futures::Async::Ready(()))
+        Poll::Ready(())
     }
 }
 
-#[allow(dead_code)]
-pub fn fibonacci() -> impl futures::Stream<Item = u64, Error = ()> {
-    futures::stream::unfold((1, 1), |(curr, next)| {
+pub fn fibonacci() -> impl Stream<Item = u64> {
+    futures::stream::unfold((1, 1), |(curr, next)| async move {
         let new_next = curr + next;
-        Some(Ok((curr, (next, new_next))))
+        Some((curr, (next, new_next)))
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::{stream, StreamExt};
+    use tokio::runtime::Runtime;
     #[test]
     fn into_iterator() {
         struct Test {
@@ -129,39 +132,42 @@ mod tests {
                 data: vec![1, 2, 3, 4, 5],
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
-            use futures::Stream;
             let name = t.name;
-            let data = t.data.clone();
-            tokio::run(futures::stream::iter_ok(data).for_each(move |i| {
-                println!("[{}]: {}", name, i);
-                Ok(())
-            }));
+            let got: Vec<_> = rt.block_on(
+                stream::iter(t.data.clone())
+                    .inspect(|i| println!("[{}]: {}", name, i))
+                    .collect(),
+            );
+            assert_eq!(t.data, got, "{}", t.name);
         }
     }
     #[test]
     fn fibonacci() {
         struct Test {
             name: &'static str,
-            count: u64,
+            count: usize,
+            last: u64,
         }
         let tests = [
             Test {
                 name: "10 entries",
                 count: 10,
+                last: 55,
             },
             Test {
                 name: "50 entries",
                 count: 50,
+                last: 12_586_269_025,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
-            use futures::Stream;
-            let name = t.name;
-            tokio::run(super::fibonacci().take(t.count).for_each(move |i| {
-                println!("[{}]: {}", name, i);
-                Ok(())
-            }));
+            let fib: Vec<_> = rt.block_on(super::fibonacci().take(t.count).collect());
+            let want: Vec<_> = rt.block_on(super::Fibonacci::new().take(t.count).collect());
+            assert_eq!(want, fib, "{}", t.name);
+            assert_eq!(Some(&t.last), fib.last(), "{}", t.name);
         }
     }
     #[test]
@@ -183,12 +189,18 @@ mod tests {
                 count: 50,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
             let msec = std::time::Duration::from_millis(t.delay);
-            let fib = super::SlowFibonacci::new(msec);
-            let stream = super::Display::new(fib, t.count);
-            println!("{}", t.name);
-            tokio::run(stream);
+            let start = std::time::Instant::now();
+            rt.block_on(async {
+                let fib = super::SlowFibonacci::new(msec);
+                println!("{}", t.name);
+                super::Display::new(fib, t.count).await
+            });
+            // The first tick completes immediately.
+            let want = msec * (t.count - 1) as u32;
+            assert!(start.elapsed() >= want, "{}", t.name);
         }
     }
     #[test]
@@ -211,11 +223,12 @@ mod tests {
                 count: 10,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
             let fib = super::Fibonacci::new();
             let stream = super::Display::new(fib, t.count);
             println!("{}", t.name);
-            tokio::run(stream);
+            rt.block_on(stream);
         }
     }
 }
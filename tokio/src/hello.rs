@@ -1,73 +1,76 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{self, Future};
-use std::net::SocketAddr;
-use tokio::{self, net};
+use std::{io, net::SocketAddr};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 
 // https://tokio.rs/docs/getting-started/echo/
-#[allow(dead_code)]
-pub fn server(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
-    use futures::Stream;
-    use tokio::io::AsyncRead;
+pub async fn server(mut l: TcpListener) {
     const NAME: &str = "hello::server";
-    net::tcp::TcpListener::bind(addr)
-        .unwrap()
-        .incoming()
-        .for_each(|sock| {
-            println!("[{}]: connection from {:?}", NAME, sock);
-            let (rx, tx) = sock.split();
-            let amount = tokio::io::copy(rx, tx);
-            let msg = amount.then(|ret| {
-                match ret {
-                    Ok((len, _, _)) => println!("[{}]: wrote {} bytes", NAME, len),
-                    Err(err) => println!("[{}]: error: {}", NAME, err),
-                }
-                Ok(())
-            });
-            tokio::spawn(msg);
-            Ok(())
-        })
-        .map_err(|err| {
-            eprintln!("[{}]: accept error: {:?}", NAME, err);
-        })
+    loop {
+        let mut sock = match l.accept().await {
+            Ok((sock, _)) => sock,
+            Err(err) => {
+                eprintln!("[{}]: accept error: {:?}", NAME, err);
+                continue;
+            }
+        };
+        println!("[{}]: connection from {:?}", NAME, sock);
+        tokio::spawn(async move {
+            let (mut rx, mut tx) = sock.split();
+            match tokio::io::copy(&mut rx, &mut tx).await {
+                Ok(len) => println!("[{}]: wrote {} bytes", NAME, len),
+                Err(err) => println!("[{}]: error: {}", NAME, err),
+            }
+        });
+    }
 }
 
 // https://tokio.rs/docs/getting-started/hello-world/
-#[allow(dead_code)]
-pub fn client(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
+pub async fn client(addr: SocketAddr) -> io::Result<()> {
     const NAME: &str = "hello::client";
-    net::tcp::TcpStream::connect(addr)
-        .and_then(|stream| {
-            println!("[{}]: created stream", NAME);
-            tokio::io::write_all(stream, "hello world\n").then(|ret| {
-                println!("[{}]: wrote to stream; success={:?}", NAME, ret.is_ok());
-                Ok(())
-            })
-        })
-        .map_err(|e| {
-            println!("connection error: {:?}", e);
-        })
+    let mut stream = TcpStream::connect(addr).await?;
+    println!("[{}]: created stream", NAME);
+    let ret = stream.write_all(b"hello world\n").await;
+    println!("[{}]: wrote to stream; success={:?}", NAME, ret.is_ok());
+    ret
 }
 
 // https://tokio.rs/docs/futures/combinators/
-#[allow(dead_code)]
-pub fn client_and_then(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
+pub async fn client_and_then(addr: SocketAddr) -> io::Result<()> {
     const NAME: &str = "hello::client_and_then";
-    net::tcp::TcpStream::connect(addr)
-        .and_then(|sock| tokio::io::write_all(sock, b"hello world"))
-        .map(|_| println!("[{}]: write complete", NAME))
-        .map_err(|err| eprintln!("[{}]: write error: {}", NAME, err))
+    let mut sock = TcpStream::connect(addr).await?;
+    sock.write_all(b"hello world").await?;
+    println!("[{}]: write complete", NAME);
+    Ok(())
 }
 
 // https://tokio.rs/docs/futures/combinators/
-#[allow(dead_code)]
-pub fn client_and_then_and_then(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
+pub async fn client_and_then_and_then(addr: SocketAddr) -> io::Result<Vec<u8>> {
     const NAME: &str = "hello::client_and_then_and_then";
-    net::tcp::TcpStream::connect(addr)
-        .and_then(|sock| tokio::io::write_all(sock, b"hello world"))
-        .and_then(|(sock, _)| tokio::io::read_exact(sock, vec![0; 10]))
-        .and_then(|(_, buf)| {
-            println!("[{}]: got {:?}", NAME, buf);
-            Ok(())
-        })
-        .map_err(|err| eprintln!("[{}]: error {}", NAME, err))
+    let mut sock = TcpStream::connect(addr).await?;
+    sock.write_all(b"hello world").await?;
+    let mut buf = vec![0; 10];
+    sock.read_exact(&mut buf).await?;
+    println!("[{}]: got {:?}", NAME, buf);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+    #[test]
+    fn clients() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = l.local_addr().unwrap();
+            tokio::spawn(super::server(l));
+            super::client(addr).await.unwrap();
+            super::client_and_then(addr).await.unwrap();
+            let got = super::client_and_then_and_then(addr).await.unwrap();
+            assert_eq!(b"hello worl".to_vec(), got);
+        });
+    }
 }
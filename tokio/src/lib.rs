@@ -1,3 +1,20 @@
 //! [Tokio 2.0 book]
 //!
 //! [tokio 2.0 book]: https://github.com/tokio-rs/book/blob/master/overview.md
+pub mod actor;
+pub mod aggregator;
+pub mod basic;
+pub mod combinator;
+pub mod deeper;
+pub mod echo;
+pub mod fibonacci;
+pub mod hello;
+pub mod peer;
+pub mod server;
+pub mod spawn;
+pub mod stream_ext;
+
+pub use basic::{Display, HelloWorld};
+pub use deeper::{Doubler, ResolveAndConnect};
+pub use fibonacci::{Fibonacci, SlowFibonacci};
+pub use server::EchoServer;
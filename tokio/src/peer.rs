@@ -1,38 +1,47 @@
 // SPDX-License-Identifier: GPL-2.0
-use bytes;
-use futures::{self, Future};
-use std::net::SocketAddr;
-use tokio::{self, net};
+use std::{
+    future::Future,
+    io::{self, Cursor},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
+
+pub type ConnectFuture = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
 
 // https://tokio.rs/docs/futures/getting_asynchronous/
 pub enum HelloWorld {
-    #[allow(dead_code)]
-    Connecting(net::tcp::ConnectFuture),
-    Connected(net::tcp::TcpStream, std::io::Cursor<bytes::Bytes>),
+    Connecting(ConnectFuture),
+    Connected(TcpStream, Cursor<&'static [u8]>),
 }
 
 impl Future for HelloWorld {
-    type Item = ();
-    type Error = std::io::Error;
-    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
-        use bytes::Buf;
-        use tokio::io::AsyncWrite;
+    type Output = io::Result<()>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         const NAME: &str = "peer::HelloWorld";
         println!("[{}]: poll()", NAME);
         loop {
-            match self {
-                Self::Connecting(ref mut f) => {
-                    let sock = futures::try_ready!(f.poll());
-                    let data = std::io::Cursor::new(bytes::Bytes::from_static(b"hello world"));
+            match &mut *self {
+                Self::Connecting(f) => {
+                    let sock = futures::ready!(f.as_mut().poll(cx))?;
+                    let data = Cursor::new(&b"hello world"[..]);
                     println!("[{}]: Connecting", NAME);
                     *self = Self::Connected(sock, data);
                 }
-                Self::Connected(ref mut sock, ref mut data) => {
+                Self::Connected(sock, data) => {
                     println!("[{}]: Connected", NAME);
-                    while data.has_remaining() {
-                        futures::try_ready!(sock.write_buf(data));
+                    while (data.position() as usize) < data.get_ref().len() {
+                        let buf = &data.get_ref()[data.position() as usize..];
+                        let n = futures::ready!(Pin::new(&mut *sock).poll_write(cx, buf))?;
+                        if n == 0 {
+                            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                        }
+                        data.set_position(data.position() + n as u64);
                     }
-                    return Ok(futures::Async::Ready(()));
+                    return Poll::Ready(Ok(()));
                 }
             }
         }
@@ -40,39 +49,70 @@ impl Future for HelloWorld {
 }
 
 pub struct GetPeerAddr {
-    pub conn: net::tcp::ConnectFuture,
+    pub conn: ConnectFuture,
 }
 
 impl Future for GetPeerAddr {
-    type Item = ();
-    type Error = ();
-    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+    type Output = Option<SocketAddr>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         const NAME: &str = "peer::GetPeerAddr";
-        match self.conn.poll() {
-            Ok(futures::Async::Ready(sock)) => {
-                println!("[{}]: peer address = {}", NAME, sock.peer_addr().unwrap());
-                Ok(futures::Async::Ready(()))
+        match self.conn.as_mut().poll(cx) {
+            Poll::Ready(Ok(sock)) => {
+                let peer = sock.peer_addr().ok();
+                println!("[{}]: peer address = {:?}", NAME, peer);
+                Poll::Ready(peer)
             }
-            Ok(futures::Async::NotReady) => {
-                eprintln!("[{}]: NotReady", NAME);
-                Ok(futures::Async::NotReady)
+            Poll::Pending => {
+                eprintln!("[{}]: Pending", NAME);
+                Poll::Pending
             }
-            Err(err) => {
+            Poll::Ready(Err(err)) => {
                 eprintln!("[{}]: failed to connect: {}", NAME, err);
-                Ok(futures::Async::Ready(()))
+                Poll::Ready(None)
             }
         }
     }
 }
 
-#[allow(dead_code)]
-pub fn hello(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
-    let conn = net::tcp::TcpStream::connect(&addr);
-    HelloWorld::Connecting(conn).map_err(|err| eprintln!("{0}", err))
+pub fn hello(addr: SocketAddr) -> impl Future<Output = ()> {
+    let conn = Box::pin(TcpStream::connect(addr));
+    async move {
+        if let Err(err) = HelloWorld::Connecting(conn).await {
+            eprintln!("{0}", err);
+        }
+    }
 }
 
-#[allow(dead_code)]
-pub fn peer(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
-    let conn = net::tcp::TcpStream::connect(&addr);
+pub fn peer(addr: SocketAddr) -> impl Future<Output = Option<SocketAddr>> {
+    let conn = Box::pin(TcpStream::connect(addr));
     GetPeerAddr { conn }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+    #[test]
+    fn hello_and_peer() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = l.local_addr().unwrap();
+            let server = tokio::spawn(async move {
+                let (mut sock, _) = l.accept().await.unwrap();
+                let mut got = Vec::new();
+                sock.read_to_end(&mut got).await.unwrap();
+                got
+            });
+            super::hello(addr).await;
+            assert_eq!(b"hello world".to_vec(), server.await.unwrap());
+
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = l.local_addr().unwrap();
+            assert_eq!(Some(addr), super::peer(addr).await);
+            drop(l);
+            assert_eq!(None, super::peer(addr).await);
+        });
+    }
+}
@@ -0,0 +1,516 @@
+// SPDX-License-Identifier: GPL-2.0
+//! [`EchoServer`] with the connection limit and the graceful shutdown
+//!
+//! # Examples
+//!
+//! ```
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//! use tokio::net::TcpStream;
+//! use tokio::sync::oneshot;
+//! use tokio_book::EchoServer;
+//!
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(async {
+//!     // Port 0 to pick the available port.
+//!     let server = EchoServer::builder()
+//!         .addr("127.0.0.1:0")
+//!         .max_connections(16)
+//!         .bind()
+//!         .await
+//!         .unwrap();
+//!     let addr = server.local_addr().unwrap();
+//!     let handle = server.handle();
+//!     let (shutdown, signal) = oneshot::channel::<()>();
+//!     let server = tokio::spawn(server.serve(async {
+//!         let _ = signal.await;
+//!     }));
+//!
+//!     let mut s = TcpStream::connect(addr).await.unwrap();
+//!     s.write_all(b"hello").await.unwrap();
+//!     let mut buf = [0; 5];
+//!     s.read_exact(&mut buf).await.unwrap();
+//!     assert_eq!(b"hello", &buf);
+//!     drop(s);
+//!
+//!     drop(shutdown);
+//!     server.await.unwrap().unwrap();
+//!     assert_eq!(1, handle.accepted());
+//!     assert_eq!(5, handle.bytes_written());
+//! });
+//! ```
+//! [`echoserver`]: struct.EchoServer.html
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Semaphore};
+use tokio::time::timeout;
+
+use crate::aggregator::Aggregator;
+
+/// Default address to listen on.
+const ADDR: &str = "127.0.0.1:8080";
+
+/// Default maximum number of the concurrent connections.
+const MAX_CONNECTIONS: usize = 1_024;
+
+/// Default idle timeout of the reads and the writes.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Pause after the accept error, not to spin on e.g. `EMFILE`.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Builder of the [`EchoServer`].
+///
+/// [`echoserver`]: struct.EchoServer.html
+pub struct Builder {
+    addr: String,
+    max_connections: usize,
+    idle_timeout: Duration,
+    throughput: Option<Aggregator<u64>>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            addr: ADDR.to_string(),
+            max_connections: MAX_CONNECTIONS,
+            idle_timeout: IDLE_TIMEOUT,
+            throughput: None,
+        }
+    }
+}
+
+impl Builder {
+    /// `addr` sets the address to listen on, e.g. "127.0.0.1:0" to pick
+    /// the available port.
+    pub fn addr(mut self, addr: impl Into<String>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+    /// `max_connections` sets the maximum number of the concurrent
+    /// connections, at least one.
+    ///
+    /// The server stops accepting while the limit is reached.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max.max(1);
+        self
+    }
+    /// `idle_timeout` sets the timeout of each read and write, after which
+    /// the connection is closed.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+    /// `throughput` records the throughput of each connection, in bytes
+    /// per second, to `aggregator` once it's closed.
+    pub fn throughput(mut self, aggregator: Aggregator<u64>) -> Self {
+        self.throughput = Some(aggregator);
+        self
+    }
+    /// `bind` binds the server to the address.
+    pub async fn bind(self) -> io::Result<EchoServer> {
+        let listener = TcpListener::bind(&*self.addr).await?;
+        Ok(EchoServer {
+            listener,
+            max_connections: self.max_connections,
+            idle_timeout: self.idle_timeout,
+            shared: Arc::new(Shared {
+                throughput: self.throughput,
+                ..Shared::default()
+            }),
+        })
+    }
+}
+
+/// Echo server with the connection limit, the idle timeout and the
+/// graceful shutdown.
+pub struct EchoServer {
+    listener: TcpListener,
+    max_connections: usize,
+    idle_timeout: Duration,
+    shared: Arc<Shared>,
+}
+
+impl EchoServer {
+    /// `builder` returns the [`Builder`] of the server.
+    ///
+    /// [`builder`]: struct.Builder.html
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+    /// `local_addr` returns the bound address, e.g. the port picked for the
+    /// port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// `handle` returns the [`Handle`] to read the byte counters.
+    ///
+    /// [`handle`]: struct.Handle.html
+    pub fn handle(&self) -> Handle {
+        Handle {
+            shared: self.shared.clone(),
+        }
+    }
+    /// `serve` serves the connections until `signal` completes.
+    ///
+    /// On shutdown, it stops accepting, closes the connections once the
+    /// in-flight echo completes, and waits for them to be closed.
+    pub async fn serve(mut self, signal: impl Future<Output = ()>) -> io::Result<()> {
+        const NAME: &str = "server::EchoServer";
+        let semaphore = Arc::new(Semaphore::new(self.max_connections));
+        // The connections get `None` once the sender is dropped.
+        let (shutdown, closed) = watch::channel(());
+        tokio::pin!(signal);
+        loop {
+            let accept = async {
+                let permit = semaphore.clone().acquire_owned().await;
+                self.listener.accept().await.map(|conn| (conn, permit))
+            };
+            let ((stream, peer), permit) = tokio::select! {
+                _ = &mut signal => break,
+                conn = accept => match conn {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        eprintln!("[{}]: accept error: {}", NAME, err);
+                        tokio::time::delay_for(ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                },
+            };
+            let conn = self.shared.open(peer);
+            let idle_timeout = self.idle_timeout;
+            let shared = self.shared.clone();
+            let mut closed = closed.clone();
+            tokio::spawn(async move {
+                // Consumes the initial value.
+                closed.recv().await;
+                if let Err(err) = echo(stream, &conn, &shared, idle_timeout, closed).await {
+                    eprintln!("[{}]: {}: {}", NAME, peer, err);
+                }
+                shared.close(&conn);
+                drop(permit);
+            });
+        }
+        drop(self.listener);
+        drop(shutdown);
+        // Drains the connections by taking all the permits back.
+        for _ in 0..self.max_connections {
+            semaphore.acquire().await.forget();
+        }
+        Ok(())
+    }
+}
+
+async fn echo(
+    mut stream: TcpStream,
+    conn: &Counters,
+    total: &Shared,
+    idle_timeout: Duration,
+    mut closed: watch::Receiver<()>,
+) -> io::Result<()> {
+    let mut buf = [0; 4_096];
+    loop {
+        let n = tokio::select! {
+            n = timeout(idle_timeout, stream.read(&mut buf)) => n??,
+            _ = closed.recv() => return Ok(()),
+        };
+        if n == 0 {
+            return Ok(());
+        }
+        conn.read.fetch_add(n as u64, Ordering::Relaxed);
+        total.read.fetch_add(n as u64, Ordering::Relaxed);
+        timeout(idle_timeout, stream.write_all(&buf[..n])).await??;
+        conn.written.fetch_add(n as u64, Ordering::Relaxed);
+        total.written.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// Byte counters of the connection.
+struct Counters {
+    id: u64,
+    peer: SocketAddr,
+    started: Instant,
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+/// Counters shared with the [`Handle`].
+///
+/// [`handle`]: struct.Handle.html
+#[derive(Default)]
+struct Shared {
+    accepted: AtomicU64,
+    read: AtomicU64,
+    written: AtomicU64,
+    conns: Mutex<BTreeMap<u64, Arc<Counters>>>,
+    throughput: Option<Aggregator<u64>>,
+}
+
+impl Shared {
+    fn open(&self, peer: SocketAddr) -> Arc<Counters> {
+        let id = self.accepted.fetch_add(1, Ordering::Relaxed);
+        let conn = Arc::new(Counters {
+            id,
+            peer,
+            started: Instant::now(),
+            read: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        });
+        self.conns.lock().unwrap().insert(id, conn.clone());
+        conn
+    }
+    fn close(&self, conn: &Counters) {
+        self.conns.lock().unwrap().remove(&conn.id);
+        if let Some(throughput) = &self.throughput {
+            let elapsed = conn.started.elapsed().as_secs_f64().max(f64::EPSILON);
+            let written = conn.written.load(Ordering::Relaxed);
+            throughput.record((written as f64 / elapsed) as u64);
+        }
+    }
+}
+
+/// Handle of the [`EchoServer`] to read its byte counters.
+///
+/// [`echoserver`]: struct.EchoServer.html
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+impl Handle {
+    /// `accepted` returns the number of the accepted connections.
+    pub fn accepted(&self) -> u64 {
+        self.shared.accepted.load(Ordering::Relaxed)
+    }
+    /// `bytes_read` returns the total bytes read from the connections.
+    pub fn bytes_read(&self) -> u64 {
+        self.shared.read.load(Ordering::Relaxed)
+    }
+    /// `bytes_written` returns the total bytes written to the connections.
+    pub fn bytes_written(&self) -> u64 {
+        self.shared.written.load(Ordering::Relaxed)
+    }
+    /// `connections` returns the live connections in the accepted order.
+    pub fn connections(&self) -> Vec<Connection> {
+        self.shared
+            .conns
+            .lock()
+            .unwrap()
+            .values()
+            .map(|conn| Connection {
+                peer: conn.peer,
+                read: conn.read.load(Ordering::Relaxed),
+                written: conn.written.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// Snapshot of the live connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Connection {
+    pub peer: SocketAddr,
+    pub read: u64,
+    pub written: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EchoServer;
+    use crate::aggregator::{Aggregator, Sink};
+    use futures::{channel::mpsc, StreamExt};
+    use std::io;
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::runtime::Runtime;
+    use tokio::sync::oneshot;
+
+    async fn echo(s: &mut TcpStream, data: &[u8]) -> io::Result<Vec<u8>> {
+        s.write_all(data).await?;
+        let mut buf = vec![0; data.len()];
+        s.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn echo_and_counters() {
+        struct Test {
+            name: &'static str,
+            clients: usize,
+            data: &'static [u8],
+        }
+        let tests = [
+            Test {
+                name: "single client",
+                clients: 1,
+                data: b"hello",
+            },
+            Test {
+                name: "16 clients",
+                clients: 16,
+                data: b"hello world",
+            },
+        ];
+        let mut rt = Runtime::new().unwrap();
+        for t in &tests {
+            rt.block_on(async {
+                let server = EchoServer::builder()
+                    .addr("127.0.0.1:0")
+                    .bind()
+                    .await
+                    .unwrap();
+                let addr = server.local_addr().unwrap();
+                assert_ne!(0, addr.port(), "{}", t.name);
+                let handle = server.handle();
+                let (shutdown, signal) = oneshot::channel::<()>();
+                let server = tokio::spawn(server.serve(async {
+                    let _ = signal.await;
+                }));
+                let mut clients = Vec::new();
+                for _ in 0..t.clients {
+                    let mut s = TcpStream::connect(addr).await.unwrap();
+                    assert_eq!(t.data, &echo(&mut s, t.data).await.unwrap()[..]);
+                    clients.push(s);
+                }
+                let conns = handle.connections();
+                assert_eq!(t.clients, conns.len(), "{}", t.name);
+                for (conn, client) in conns.iter().zip(&clients) {
+                    assert_eq!(client.local_addr().unwrap(), conn.peer, "{}", t.name);
+                    assert_eq!(t.data.len() as u64, conn.read, "{}", t.name);
+                    assert_eq!(t.data.len() as u64, conn.written, "{}", t.name);
+                }
+                drop(clients);
+                drop(shutdown);
+                server.await.unwrap().unwrap();
+                let want = (t.clients * t.data.len()) as u64;
+                assert_eq!(t.clients as u64, handle.accepted(), "{}", t.name);
+                assert_eq!(want, handle.bytes_read(), "{}", t.name);
+                assert_eq!(want, handle.bytes_written(), "{}", t.name);
+                assert!(handle.connections().is_empty(), "{}", t.name);
+            });
+        }
+    }
+
+    #[test]
+    fn throughput() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let (tx, rx) = mpsc::unbounded();
+            let (throughput, task) = Aggregator::builder().sink(Sink::Channel(tx)).spawn();
+            let server = EchoServer::builder()
+                .addr("127.0.0.1:0")
+                .throughput(throughput)
+                .bind()
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            let (shutdown, signal) = oneshot::channel::<()>();
+            let server = tokio::spawn(server.serve(async {
+                let _ = signal.await;
+            }));
+            for _ in 0..3 {
+                let mut s = TcpStream::connect(addr).await.unwrap();
+                echo(&mut s, b"hello").await.unwrap();
+            }
+            drop(shutdown);
+            server.await.unwrap().unwrap();
+            task.await.unwrap();
+            let stats: Vec<_> = rx.collect().await;
+            assert_eq!(3, stats.last().unwrap().count);
+        });
+    }
+
+    #[test]
+    fn max_connections() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server = EchoServer::builder()
+                .addr("127.0.0.1:0")
+                .max_connections(2)
+                .bind()
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            let (_shutdown, signal) = oneshot::channel::<()>();
+            tokio::spawn(server.serve(async {
+                let _ = signal.await;
+            }));
+            let mut first = TcpStream::connect(addr).await.unwrap();
+            let mut second = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(b"1".to_vec(), echo(&mut first, b"1").await.unwrap());
+            assert_eq!(b"2".to_vec(), echo(&mut second, b"2").await.unwrap());
+            // The third one is in the backlog, and not served yet.
+            let mut third = TcpStream::connect(addr).await.unwrap();
+            let wait = Duration::from_millis(100);
+            let pending = tokio::time::timeout(wait, echo(&mut third, b"3")).await;
+            assert!(pending.is_err());
+            // It's served once the first one is closed.
+            drop(first);
+            let mut buf = [0; 1];
+            third.read_exact(&mut buf).await.unwrap();
+            assert_eq!(b"3", &buf);
+        });
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server = EchoServer::builder()
+                .addr("127.0.0.1:0")
+                .idle_timeout(Duration::from_millis(100))
+                .bind()
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            let handle = server.handle();
+            let (_shutdown, signal) = oneshot::channel::<()>();
+            tokio::spawn(server.serve(async {
+                let _ = signal.await;
+            }));
+            let mut s = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(b"hi".to_vec(), echo(&mut s, b"hi").await.unwrap());
+            let start = Instant::now();
+            // Closed by the server after the idle timeout.
+            let mut buf = Vec::new();
+            assert_eq!(0, s.read_to_end(&mut buf).await.unwrap());
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            assert!(handle.connections().is_empty());
+        });
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server = EchoServer::builder()
+                .addr("127.0.0.1:0")
+                .bind()
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            let (shutdown, signal) = oneshot::channel::<()>();
+            let server = tokio::spawn(server.serve(async {
+                let _ = signal.await;
+            }));
+            let mut s = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(b"hi".to_vec(), echo(&mut s, b"hi").await.unwrap());
+            shutdown.send(()).unwrap();
+            // Closes the idle connection and stops accepting.
+            server.await.unwrap().unwrap();
+            let mut buf = Vec::new();
+            assert_eq!(0, s.read_to_end(&mut buf).await.unwrap());
+            assert!(TcpStream::connect(addr).await.is_err());
+        });
+    }
+}
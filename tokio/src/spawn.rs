@@ -1,143 +1,136 @@
 // SPDX-License-Identifier: GPL-2.0
-use futures::{self, future, sync, Future};
-use std::net::SocketAddr;
-use tokio;
+use std::{time::Duration, time::Instant};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future, FutureExt, SinkExt, StreamExt,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::aggregator::Aggregator;
 
 /// https://tokio.rs/docs/futures/spawning/
-#[allow(dead_code)]
-pub fn server(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
-    use futures::Stream;
+pub async fn server(mut l: TcpListener) {
     const NAME: &str = "spawn::server";
-    tokio::net::tcp::TcpListener::bind(addr)
-        .unwrap()
-        .incoming()
-        .for_each(|sock| {
-            let peer = sock.peer_addr().unwrap();
-            tokio::spawn({
-                println!("[{}]: handling {}", NAME, sock.peer_addr().unwrap());
-                tokio::io::write_all(sock, "hello world")
-                    // Drop the socket
-                    .map(|_| ())
-                    .map_err(|err| eprintln!("[{}]: {:?}", NAME, err))
-            });
-            println!("[{}]: spawned {} handler", NAME, peer);
-            Ok(())
-        })
-        .map_err(|err| eprintln!("[{}]: {}", NAME, err))
+    loop {
+        let (mut sock, peer) = match l.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                eprintln!("[{}]: {}", NAME, err);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            println!("[{}]: handling {}", NAME, peer);
+            // Drop the socket
+            if let Err(err) = sock.write_all(b"hello world").await {
+                eprintln!("[{}]: {:?}", NAME, err);
+            }
+        });
+        println!("[{}]: spawned {} handler", NAME, peer);
+    }
 }
 
 /// Background processing example explained in
 /// https://tokio.rs/docs/futures/spawning/
-#[allow(dead_code)]
-pub fn background(addr: &SocketAddr) -> impl Future<Item = (), Error = ()> {
+///
+/// The byte count of each connection is recorded to the [`Aggregator`],
+/// which reports the stats every interval.
+///
+/// [`aggregator`]: ../aggregator/struct.Aggregator.html
+pub async fn background(mut l: TcpListener, aggregator: Aggregator<usize>) {
     const NAME: &str = "spawn::background";
-    let addr = *addr;
-    future::lazy(move || {
-        use futures::{Sink, Stream};
-        let (tx, rx) = sync::mpsc::channel(1_024);
-        tokio::spawn(sum(rx));
-        println!("[{}] listen on {:?}", NAME, addr);
-        tokio::net::tcp::TcpListener::bind(&addr)
-            .unwrap()
-            .incoming()
-            .for_each(move |sock| {
-                println!("[{}]: from {}", NAME, sock.peer_addr().unwrap());
-                tokio::spawn({
-                    let tx = tx.clone();
-                    tokio::io::read_to_end(sock, vec![])
-                        .and_then(move |(_, buf)| {
-                            tx.send(buf.len())
-                                .map_err(|_| tokio::io::ErrorKind::Other.into())
-                        })
-                        .map(|_| ())
-                        .map_err(|err| eprintln!("[{}]: {:?}", NAME, err))
-                });
-                Ok(())
-            })
-            .map_err(|err| eprintln!("[{}]: {:?}", NAME, err))
-    })
-}
-
-fn sum(rx: sync::mpsc::Receiver<usize>) -> impl Future<Item = (), Error = ()> {
-    use futures::Stream;
-    const NAME: &str = "spawn::sum";
-    #[derive(Eq, PartialEq)]
-    enum Item {
-        Value(usize),
-        Tick,
-        Done,
-    }
-    // summary interval tick(5sec).
-    let tick_dur = std::time::Duration::from_secs(5);
-    let interval = tokio::timer::Interval::new_interval(tick_dur)
-        .map(|_| Item::Tick)
-        .map_err(|err| eprintln!("[{}]: {}", NAME, err));
-    // Turn the stream into a sequence of:
-    // Item(Value), Item(Value), Tick, Item(Value)... Done.
-    let items = rx
-        .map(Item::Value)
-        .chain(futures::stream::once(Ok(Item::Done)))
-        .select(interval)
-        .take_while(|item| future::ok(*item != Item::Done));
-    // our logic future.
-    items
-        .fold(0, |num, item| match item {
-            Item::Value(v) => future::ok(num + v),
-            Item::Tick => {
-                println!("[{}]: bytes read = {}", NAME, num);
-                future::ok(0)
+    println!("[{}] listen on {:?}", NAME, l.local_addr());
+    loop {
+        let (mut sock, peer) = match l.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                eprintln!("[{}]: {:?}", NAME, err);
+                continue;
+            }
+        };
+        println!("[{}]: from {}", NAME, peer);
+        let aggregator = aggregator.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![];
+            match sock.read_to_end(&mut buf).await {
+                Ok(n) => aggregator.record(n),
+                Err(err) => eprintln!("[{}]: {:?}", NAME, err),
             }
-            _ => unreachable!(),
-        })
-        .map(|_| ())
+        });
+    }
 }
 
 /// Coordinating access to a resource example explained in
 /// https://tokio.rs/docs/futures/spawning/
-#[allow(dead_code)]
-pub fn coordinate(requesters: usize) -> impl Future<Item = (), Error = ()> {
+pub fn coordinate(requesters: usize) {
     const NAME: &str = "spawn::coordinate";
-    future::lazy(move || {
-        let (tx, rx) = sync::mpsc::channel(1_024);
-        for i in 0..requesters {
-            tokio::spawn(ping(i, tx.clone()).and_then(move |(dur, _)| {
+    let (tx, rx) = mpsc::channel(1_024);
+    for i in 0..requesters {
+        tokio::spawn(ping(i, tx.clone()).map(move |resp| {
+            if let Some((_, dur)) = resp {
                 println!("[{}:{}]: duration = {:?}", NAME, i, dur);
-                Ok(())
-            }));
-        }
-        tokio::spawn(pong(rx));
-        Ok(())
-    })
+            }
+        }));
+    }
+    tokio::spawn(pong(rx));
 }
 
-type Message = (usize, sync::oneshot::Sender<(usize, std::time::Duration)>);
+type Message = (usize, oneshot::Sender<(usize, Duration)>);
 
-#[allow(dead_code)]
-fn ping(
-    id: usize,
-    tx: sync::mpsc::Sender<Message>,
-) -> impl Future<Item = (usize, std::time::Duration), Error = ()> {
-    use futures::Sink;
+async fn ping(id: usize, mut tx: mpsc::Sender<Message>) -> Option<(usize, Duration)> {
     const NAME: &str = "spawn::ping";
-    let (resp_tx, resp_rx) = sync::oneshot::channel();
-    tx.send((id, resp_tx))
-        .map_err(|err| eprintln!("[{}]: send error: {}", NAME, err))
-        .and_then(|_tx| resp_rx.map_err(|err| eprintln!("[{}] recv error: {}", NAME, err)))
+    let (resp_tx, resp_rx) = oneshot::channel();
+    if let Err(err) = tx.send((id, resp_tx)).await {
+        eprintln!("[{}]: send error: {}", NAME, err);
+        return None;
+    }
+    match resp_rx.await {
+        Ok(resp) => Some(resp),
+        Err(err) => {
+            eprintln!("[{}] recv error: {}", NAME, err);
+            None
+        }
+    }
 }
 
-#[allow(dead_code)]
-fn pong(rx: sync::mpsc::Receiver<Message>) -> impl Future<Item = (), Error = ()> {
-    use futures::Stream;
+async fn pong(rx: mpsc::Receiver<Message>) {
     rx.for_each(|(id, tx)| {
-        let start = std::time::Instant::now();
+        let start = Instant::now();
         let rtt = start.elapsed();
         tx.send((id, rtt)).unwrap();
-        Ok(())
+        future::ready(())
     })
+    .await
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::aggregator::{Aggregator, Sink};
+    use futures::{
+        channel::{mpsc, oneshot},
+        future, stream, SinkExt, StreamExt,
+    };
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::runtime::Runtime;
+    #[test]
+    fn server() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = l.local_addr().unwrap();
+            tokio::spawn(super::server(l));
+            for _ in 0..3 {
+                let mut s = TcpStream::connect(addr).await.unwrap();
+                let mut got = Vec::new();
+                s.read_to_end(&mut got).await.unwrap();
+                assert_eq!(b"hello world".to_vec(), got);
+            }
+        });
+    }
     #[test]
     fn lazy() {
         struct Test {
@@ -154,18 +147,25 @@ mod tests {
                 count: 100,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
             let name = t.name;
-            let count = t.count;
-            tokio::run(futures::future::lazy(move || {
-                for i in 0..count {
-                    tokio::spawn(futures::future::lazy(move || {
-                        println!("[{}]: task #{}", name, i);
-                        Ok(())
-                    }));
+            let got = rt.block_on(async {
+                let tasks: Vec<_> = (0..t.count)
+                    .map(|i| {
+                        tokio::spawn(future::lazy(move |_| {
+                            println!("[{}]: task #{}", name, i);
+                            i
+                        }))
+                    })
+                    .collect();
+                let mut got = Vec::new();
+                for task in tasks {
+                    got.push(task.await.unwrap());
                 }
-                Ok(())
-            }));
+                got
+            });
+            assert_eq!((0..t.count).collect::<Vec<_>>(), got, "{}", t.name);
         }
     }
     #[test]
@@ -188,258 +188,100 @@ mod tests {
                 count: 1_000,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
-            use futures::future::lazy;
             let name = t.name;
-            let count = t.count;
-            tokio::run(lazy(move || {
-                use futures::Future;
-                for _ in 0..count {
-                    let (tx, rx) = futures::sync::oneshot::channel();
-                    tokio::spawn(lazy(move || {
-                        tx.send(format!("{}", name))
-                            .map_err(move |err| panic!("[{}] error: {}", name, err))
-                    }));
-                    tokio::spawn(lazy(move || {
-                        rx.and_then(|msg| {
-                            println!("[{}] got it!", msg);
-                            Ok(())
-                        })
-                        .map_err(move |err| panic!("[{}] error: {}", name, err))
+            rt.block_on(async {
+                let mut rxs = Vec::new();
+                for _ in 0..t.count {
+                    let (tx, rx) = oneshot::channel();
+                    tokio::spawn(async move {
+                        tx.send(name.to_string())
+                            .unwrap_or_else(|err| panic!("[{}] error: {}", name, err))
+                    });
+                    rxs.push(tokio::spawn(async move {
+                        let msg = rx.await.unwrap();
+                        println!("[{}] got it!", msg);
+                        msg
                     }));
                 }
-                Ok(())
-            }))
+                for rx in rxs {
+                    assert_eq!(name, rx.await.unwrap(), "{}", name);
+                }
+            });
         }
     }
     #[test]
     fn mpsc() {
-        use futures::future::lazy;
-        tokio::run(lazy(|| {
-            use futures::{future::Future, sink::Sink, stream, Stream};
-            let (tx, rx) = futures::sync::mpsc::channel(1_024);
-            tokio::spawn({
-                stream::iter_ok(0..10)
-                    .fold(tx, |tx, i| {
-                        tx.send(format!("message {} from spawned task", i))
-                            .map_err(|err| eprintln!("error = {}", err))
-                    })
-                    .map(|_| ())
+        let mut rt = Runtime::new().unwrap();
+        let got: Vec<_> = rt.block_on(async {
+            let (mut tx, rx) = mpsc::channel(1_024);
+            tokio::spawn(async move {
+                let mut msgs =
+                    stream::iter(0..10).map(|i| Ok(format!("message {} from spawned task", i)));
+                if let Err(err) = tx.send_all(&mut msgs).await {
+                    eprintln!("error = {}", err);
+                }
             });
-            rx.for_each(|msg| {
-                println!("Got {}", msg);
-                Ok(())
-            })
-        }));
+            rx.inspect(|msg| println!("Got {}", msg)).collect().await
+        });
+        assert_eq!(10, got.len());
     }
     #[test]
-    fn sum() {
+    fn background() {
         #[derive(Clone)]
         struct Test {
             name: &'static str,
-            bufsiz: usize,
             producers: usize,
             data: usize,
         }
         let tests = [
             Test {
-                name: "one producer on one byte channel buffer",
-                bufsiz: 1,
-                producers: 1,
-                data: 256,
-            },
-            Test {
-                name: "one producer on two bytes channel buffer",
-                bufsiz: 2,
+                name: "one producer",
                 producers: 1,
                 data: 256,
             },
             Test {
-                name: "one producer on 512 bytes channel buffer",
-                bufsiz: 512,
-                producers: 1,
-                data: 256,
-            },
-            Test {
-                name: "one producer on 1,024 bytes channel buffer",
-                bufsiz: 1_024,
-                producers: 1,
-                data: 256,
-            },
-            Test {
-                name: "one producer on 4K bytes channel buffer",
-                bufsiz: 4_096,
-                producers: 1,
-                data: 256,
-            },
-            Test {
-                name: "two producers on one byte channel buffer",
-                bufsiz: 1,
-                producers: 2,
-                data: 256,
-            },
-            Test {
-                name: "two producers on two bytes channel buffer",
-                bufsiz: 2,
+                name: "two producers",
                 producers: 2,
                 data: 256,
             },
             Test {
-                name: "two producers on 512 bytes channel buffer",
-                bufsiz: 512,
-                producers: 2,
-                data: 256,
-            },
-            Test {
-                name: "two producers on 1,024 bytes channel buffer",
-                bufsiz: 1_024,
-                producers: 2,
-                data: 256,
-            },
-            Test {
-                name: "two producers on 4K bytes channel buffer",
-                bufsiz: 4_096,
-                producers: 2,
-                data: 256,
-            },
-            Test {
-                name: "four producers on one byte channel buffer",
-                bufsiz: 1,
-                producers: 4,
-                data: 256,
-            },
-            Test {
-                name: "four producers on two bytes channel buffer",
-                bufsiz: 2,
-                producers: 4,
-                data: 256,
-            },
-            Test {
-                name: "four producers on 512 bytes channel buffer",
-                bufsiz: 512,
-                producers: 4,
-                data: 256,
-            },
-            Test {
-                name: "four producers on 1,024 bytes channel buffer",
-                bufsiz: 1_024,
-                producers: 4,
-                data: 256,
-            },
-            Test {
-                name: "four producers on 4K bytes channel buffer",
-                bufsiz: 4_096,
-                producers: 4,
-                data: 256,
-            },
-            Test {
-                name: "16 producers on one byte channel buffer",
-                bufsiz: 1,
-                producers: 16,
-                data: 256,
-            },
-            Test {
-                name: "16 producers on two bytes channel buffer",
-                bufsiz: 2,
-                producers: 16,
-                data: 256,
-            },
-            Test {
-                name: "16 producers on 512 bytes channel buffer",
-                bufsiz: 512,
+                name: "16 producers",
                 producers: 16,
-                data: 256,
+                data: 1_024,
             },
             Test {
-                name: "16 producers on 1,024 bytes channel buffer",
-                bufsiz: 1_024,
-                producers: 16,
-                data: 256,
-            },
-            Test {
-                name: "16 producers on 4K bytes channel buffer",
-                bufsiz: 4_096,
-                producers: 16,
-                data: 256,
-            },
-            Test {
-                name: "64 producers on one byte channel buffer",
-                bufsiz: 1,
-                producers: 64,
-                data: 256,
-            },
-            Test {
-                name: "64 producers on two bytes channel buffer",
-                bufsiz: 2,
+                name: "64 producers",
                 producers: 64,
-                data: 256,
-            },
-            Test {
-                name: "64 producers on 512 bytes channel buffer",
-                bufsiz: 512,
-                producers: 64,
-                data: 256,
-            },
-            Test {
-                name: "64 producers on 1,024 bytes channel buffer",
-                bufsiz: 1_024,
-                producers: 64,
-                data: 256,
-            },
-            Test {
-                name: "64 producers on 4K bytes channel buffer",
-                bufsiz: 4_096,
-                producers: 64,
-                data: 256,
-            },
-            Test {
-                name: "256 producers on one byte channel buffer",
-                bufsiz: 1,
-                producers: 256,
-                data: 256,
-            },
-            Test {
-                name: "256 producers on two bytes channel buffer",
-                bufsiz: 2,
-                producers: 256,
-                data: 256,
-            },
-            Test {
-                name: "256 producers on 512 bytes channel buffer",
-                bufsiz: 512,
-                producers: 256,
-                data: 256,
-            },
-            Test {
-                name: "256 producers on 1,024 bytes channel buffer",
-                bufsiz: 1_024,
-                producers: 256,
-                data: 256,
-            },
-            Test {
-                name: "256 producers on 4K bytes channel buffer",
-                bufsiz: 4_096,
-                producers: 256,
-                data: 256,
+                data: 4_096,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
-            let t = t.clone();
-            tokio::run(futures::future::lazy(move || {
-                use futures::{Future, Sink};
-                let (tx, rx) = futures::sync::mpsc::channel(t.bufsiz);
+            rt.block_on(async {
+                let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = l.local_addr().unwrap();
+                let (tx, rx) = mpsc::unbounded();
+                let (aggregator, _) = Aggregator::builder()
+                    .interval(Duration::from_millis(20))
+                    .window(Duration::from_secs(60))
+                    .sink(Sink::Channel(tx))
+                    .spawn();
+                tokio::spawn(super::background(l, aggregator));
                 for _ in 0..t.producers {
-                    tokio::spawn({
-                        tx.clone()
-                            .send(t.data)
-                            .map(|_| ())
-                            .map_err(|err| eprintln!("{}", err))
-                    });
+                    let mut s = TcpStream::connect(addr).await.unwrap();
+                    s.write_all(&vec![0; t.data]).await.unwrap();
                 }
-                tokio::spawn(super::sum(rx));
+                // Waits for all the handlers to record the bytes.
+                let count = t.producers;
+                let mut rx = rx.skip_while(|stats| future::ready(stats.count < count));
+                let stats = rx.next().await.unwrap();
+                assert_eq!(t.producers, stats.count, "{}", t.name);
+                assert_eq!(t.producers * t.data, stats.sum, "{}", t.name);
+                assert_eq!(Some(t.data), stats.p99, "{}", t.name);
                 println!("{}: done", t.name);
-                Ok(())
-            }));
+            });
         }
     }
     #[test]
@@ -677,21 +519,24 @@ mod tests {
                 requesters: 16_384,
             },
         ];
+        let mut rt = Runtime::new().unwrap();
         for t in &tests {
-            let t = t.clone();
-            tokio::run(futures::future::lazy(move || {
-                use super::Future;
-                let (tx, rx) = super::sync::mpsc::channel(t.bufsiz);
+            rt.block_on(async {
+                let (tx, rx) = mpsc::channel(t.bufsiz);
                 tokio::spawn(super::pong(rx));
-                for i in 0..t.requesters {
-                    let name = t.name;
-                    tokio::spawn(super::ping(i, tx.clone()).map(move |(got, _dur)| {
-                        debug_assert_eq!(got, i, "{}", name);
-                        ()
-                    }));
+                let pings: Vec<_> = (0..t.requesters)
+                    .map(|i| tokio::spawn(super::ping(i, tx.clone())))
+                    .collect();
+                for (i, ping) in pings.into_iter().enumerate() {
+                    let (got, _dur) = ping.await.unwrap().unwrap();
+                    assert_eq!(i, got, "{}", t.name);
                 }
-                Ok(())
-            }));
+            });
         }
     }
+    #[test]
+    fn coordinate() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async { super::coordinate(16) });
+    }
 }
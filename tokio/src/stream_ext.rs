@@ -0,0 +1,509 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Time based [`StreamExt`] adapters: throttling, batching and timeouts
+//!
+//! It picks up where the [streams] chapter stops.  The adapters wrap any
+//! `Unpin` stream, e.g. [`Fibonacci`] or [`SlowFibonacci`], and pace it by
+//! the Tokio timer, so they should be polled within the Tokio runtime.
+//! Pin the stream with `Box::pin` in case it's not `Unpin`.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use futures::StreamExt as _;
+//! use tokio_book::{stream_ext::StreamExt as _, Fibonacci};
+//!
+//! let mut rt = tokio::runtime::Runtime::new().unwrap();
+//! rt.block_on(async {
+//!     let chunks: Vec<_> = Fibonacci::new()
+//!         .throttle(Duration::from_millis(1))
+//!         .chunks_timeout(3, Duration::from_secs(1))
+//!         .take(2)
+//!         .collect()
+//!         .await;
+//!     assert_eq!(vec![vec![1, 1, 2], vec![3, 5, 8]], chunks);
+//! });
+//! ```
+//! [streams]: https://tokio.rs/docs/futures/streams/
+//! [`streamext`]: trait.StreamExt.html
+//! [`fibonacci`]: ../fibonacci/struct.Fibonacci.html
+//! [`slowfibonacci`]: ../fibonacci/struct.SlowFibonacci.html
+use std::{
+    error, fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt as _};
+use tokio::time::{self, Delay, Instant};
+
+/// Max number of the ready items `debounce` takes in a single poll.
+const BURST: usize = 32;
+
+/// Extension of the `Stream` with the time based adapters.
+pub trait StreamExt: Stream + Sized {
+    /// `throttle` yields at most one item every `rate`.
+    ///
+    /// The items are delayed, not dropped, and the first item is yielded
+    /// right away.
+    fn throttle(self, rate: Duration) -> Throttle<Self> {
+        Throttle {
+            stream: self,
+            rate,
+            delay: None,
+        }
+    }
+    /// `chunks_timeout` batches the items into the vector of up to `max`
+    /// items.
+    ///
+    /// The partial chunk is yielded once `timeout` has elapsed since its
+    /// first item, or once the stream ends.
+    fn chunks_timeout(self, max: usize, timeout: Duration) -> ChunksTimeout<Self> {
+        ChunksTimeout {
+            stream: Some(self),
+            max: max.max(1),
+            timeout,
+            items: Vec::new(),
+            delay: None,
+        }
+    }
+    /// `debounce` yields the item only after `quiet` without the newer
+    /// item, which replaces the pending one.
+    ///
+    /// The pending item is yielded right away once the stream ends.  The
+    /// endless burst of the ready items never yields, but it gives the
+    /// other tasks the chance to run every 32 items.
+    fn debounce(self, quiet: Duration) -> Debounce<Self> {
+        Debounce {
+            stream: Some(self),
+            quiet,
+            pending: None,
+            delay: None,
+        }
+    }
+    /// `timeout_each` yields [`Elapsed`] every time the next item doesn't
+    /// come within `timeout`, and keeps waiting for it.
+    ///
+    /// [`elapsed`]: struct.Elapsed.html
+    fn timeout_each(self, timeout: Duration) -> TimeoutEach<Self> {
+        TimeoutEach {
+            stream: self,
+            timeout,
+            delay: None,
+        }
+    }
+}
+
+impl<S: Stream> StreamExt for S {}
+
+/// Error of the [`TimeoutEach`] stream.
+///
+/// [`timeouteach`]: struct.TimeoutEach.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl error::Error for Elapsed {}
+
+/// Stream returned by [`throttle`].
+///
+/// [`throttle`]: trait.StreamExt.html#method.throttle
+pub struct Throttle<S> {
+    stream: S,
+    rate: Duration,
+    delay: Option<Delay>,
+}
+
+impl<S> Stream for Throttle<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        if let Some(delay) = self.delay.as_mut() {
+            futures::ready!(Pin::new(delay).poll(cx));
+            self.delay = None;
+        }
+        let item = futures::ready!(self.stream.poll_next_unpin(cx));
+        if item.is_some() {
+            self.delay = Some(time::delay_for(self.rate));
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// Stream returned by [`chunks_timeout`].
+///
+/// [`chunks_timeout`]: trait.StreamExt.html#method.chunks_timeout
+pub struct ChunksTimeout<S: Stream> {
+    /// `None` once the stream ends.
+    stream: Option<S>,
+    max: usize,
+    timeout: Duration,
+    items: Vec<S::Item>,
+    /// Deadline of the current chunk.
+    delay: Option<Delay>,
+}
+
+impl<S: Stream> ChunksTimeout<S> {
+    fn flush(&mut self) -> Vec<S::Item> {
+        self.delay = None;
+        mem::take(&mut self.items)
+    }
+}
+
+impl<S> Stream for ChunksTimeout<S>
+where
+    S: Stream + Unpin,
+    S::Item: Unpin,
+{
+    type Item = Vec<S::Item>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while let Some(stream) = this.stream.as_mut() {
+            match stream.poll_next_unpin(cx) {
+                Poll::Ready(Some(item)) => {
+                    if this.items.is_empty() {
+                        this.delay = Some(time::delay_for(this.timeout));
+                    }
+                    this.items.push(item);
+                    if this.items.len() >= this.max {
+                        return Poll::Ready(Some(this.flush()));
+                    }
+                }
+                Poll::Ready(None) => this.stream = None,
+                Poll::Pending => {
+                    if let Some(delay) = this.delay.as_mut() {
+                        futures::ready!(Pin::new(delay).poll(cx));
+                        return Poll::Ready(Some(this.flush()));
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        if this.items.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Ready(Some(this.flush()))
+        }
+    }
+}
+
+/// Stream returned by [`debounce`].
+///
+/// [`debounce`]: trait.StreamExt.html#method.debounce
+pub struct Debounce<S: Stream> {
+    /// `None` once the stream ends.
+    stream: Option<S>,
+    quiet: Duration,
+    pending: Option<S::Item>,
+    /// Deadline of the pending item.
+    delay: Option<Delay>,
+}
+
+impl<S> Stream for Debounce<S>
+where
+    S: Stream + Unpin,
+    S::Item: Unpin,
+{
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        let mut burst = 0;
+        while let Some(stream) = this.stream.as_mut() {
+            if burst == BURST {
+                // Yields to the other tasks, and comes back right away.
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            match stream.poll_next_unpin(cx) {
+                Poll::Ready(Some(item)) => {
+                    burst += 1;
+                    let deadline = Instant::now() + this.quiet;
+                    match this.delay.as_mut() {
+                        Some(delay) => delay.reset(deadline),
+                        None => this.delay = Some(time::delay_until(deadline)),
+                    }
+                    this.pending = Some(item);
+                }
+                Poll::Ready(None) => this.stream = None,
+                Poll::Pending => {
+                    if let (Some(_), Some(delay)) = (&this.pending, &mut this.delay) {
+                        futures::ready!(Pin::new(delay).poll(cx));
+                        return Poll::Ready(this.pending.take());
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        Poll::Ready(this.pending.take())
+    }
+}
+
+/// Stream returned by [`timeout_each`].
+///
+/// [`timeout_each`]: trait.StreamExt.html#method.timeout_each
+pub struct TimeoutEach<S> {
+    stream: S,
+    timeout: Duration,
+    /// Deadline of the next item, started on the first poll.
+    delay: Option<Delay>,
+}
+
+impl<S> Stream for TimeoutEach<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = Result<S::Item, Elapsed>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let timeout = self.timeout;
+        if let Poll::Ready(item) = self.stream.poll_next_unpin(cx) {
+            self.delay = None;
+            return Poll::Ready(item.map(Ok));
+        }
+        let delay = self.delay.get_or_insert_with(|| time::delay_for(timeout));
+        futures::ready!(Pin::new(&mut *delay).poll(cx));
+        delay.reset(Instant::now() + timeout);
+        Poll::Ready(Some(Err(Elapsed(()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Elapsed, StreamExt as _};
+    use crate::{Fibonacci, SlowFibonacci};
+    use futures::{stream, FutureExt, Stream, StreamExt as _};
+    use std::time::Duration;
+    use tokio::runtime::{Builder, Runtime};
+    use tokio::time::{self, Instant};
+
+    /// Runtime to run the tests with the paused clock, which advances to
+    /// the next timer once the runtime is idle.
+    fn paused() -> Runtime {
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async { time::pause() });
+        rt
+    }
+
+    /// `timed` collects the items of the stream created by `f` with the
+    /// milliseconds since the start.
+    async fn timed<S, F>(f: F) -> Vec<(u128, S::Item)>
+    where
+        S: Stream + Unpin,
+        F: FnOnce() -> S,
+    {
+        let start = Instant::now();
+        f().map(|item| ((Instant::now() - start).as_millis(), item))
+            .collect()
+            .await
+    }
+
+    fn ms(msec: u64) -> Duration {
+        Duration::from_millis(msec)
+    }
+
+    #[test]
+    fn throttle() {
+        struct Test {
+            name: &'static str,
+            rate: u64,
+            count: usize,
+            want: Vec<(u128, u64)>,
+        }
+        let tests = [
+            Test {
+                name: "single item",
+                rate: 10,
+                count: 1,
+                want: vec![(0, 1)],
+            },
+            Test {
+                name: "five items every 10msec",
+                rate: 10,
+                count: 5,
+                want: vec![(0, 1), (10, 1), (20, 2), (30, 3), (40, 5)],
+            },
+            Test {
+                name: "three items every second",
+                rate: 1_000,
+                count: 3,
+                want: vec![(0, 1), (1_000, 1), (2_000, 2)],
+            },
+        ];
+        let mut rt = paused();
+        for t in &tests {
+            let got = rt.block_on(timed(|| {
+                Fibonacci::new().throttle(ms(t.rate)).take(t.count)
+            }));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+        // The slower source isn't delayed any further.
+        let got = rt.block_on(timed(|| {
+            SlowFibonacci::new(ms(20)).throttle(ms(10)).take(3)
+        }));
+        assert_eq!(vec![(0, 1), (20, 1), (40, 2)], got, "slow source");
+    }
+
+    #[test]
+    fn chunks_timeout() {
+        struct Test {
+            name: &'static str,
+            max: usize,
+            timeout: u64,
+            count: usize,
+            want: Vec<(u128, Vec<u64>)>,
+        }
+        let tests = [
+            Test {
+                name: "full chunks",
+                max: 3,
+                timeout: 100,
+                count: 6,
+                want: vec![(20, vec![1, 1, 2]), (50, vec![3, 5, 8])],
+            },
+            Test {
+                name: "timed out chunks",
+                max: 10,
+                timeout: 25,
+                count: 6,
+                want: vec![(25, vec![1, 1, 2]), (50, vec![3, 5, 8])],
+            },
+            Test {
+                name: "partial chunk at the end",
+                max: 4,
+                timeout: 100,
+                count: 6,
+                want: vec![(30, vec![1, 1, 2, 3]), (50, vec![5, 8])],
+            },
+            Test {
+                name: "zero max is one",
+                max: 0,
+                timeout: 100,
+                count: 2,
+                want: vec![(0, vec![1]), (10, vec![1])],
+            },
+        ];
+        let mut rt = paused();
+        for t in &tests {
+            let got = rt.block_on(timed(|| {
+                SlowFibonacci::new(ms(10))
+                    .take(t.count)
+                    .chunks_timeout(t.max, ms(t.timeout))
+            }));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+        // The ready items are batched right away.
+        let got = rt.block_on(timed(|| Fibonacci::new().take(5).chunks_timeout(2, ms(10))));
+        let want = vec![(0, vec![1, 1]), (0, vec![2, 3]), (0, vec![5])];
+        assert_eq!(want, got, "ready source");
+    }
+
+    #[test]
+    fn debounce() {
+        struct Test {
+            name: &'static str,
+            quiet: u64,
+            want: Vec<(u128, u64)>,
+        }
+        let tests = [
+            Test {
+                name: "shorter than the source interval",
+                quiet: 5,
+                want: vec![(5, 1), (15, 1), (25, 2), (35, 3), (40, 5)],
+            },
+            Test {
+                name: "longer than the source interval",
+                quiet: 15,
+                want: vec![(40, 5)],
+            },
+        ];
+        let mut rt = paused();
+        for t in &tests {
+            let got = rt.block_on(timed(|| {
+                SlowFibonacci::new(ms(10)).take(5).debounce(ms(t.quiet))
+            }));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+        // Only the last of the burst is yielded.
+        let got = rt.block_on(timed(|| {
+            let slow = SlowFibonacci::new(ms(50)).skip(1).take(1);
+            Fibonacci::new().take(10).chain(slow).debounce(ms(10))
+        }));
+        assert_eq!(vec![(10, 55), (50, 1)], got, "burst");
+        // The burst longer than a single poll.  The paused clock jumps on
+        // each yield, so only the items are compared.
+        let got = rt.block_on(
+            Fibonacci::new()
+                .take(90)
+                .debounce(ms(10))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(vec![2_880_067_194_370_816_120], got, "long burst");
+    }
+
+    #[test]
+    fn debounce_endless_burst() {
+        let mut rt = paused();
+        rt.block_on(async {
+            // Returns after the single burst, without the overflow.
+            let mut fib = Fibonacci::new().debounce(ms(10));
+            assert_eq!(None, fib.next().now_or_never());
+            let mut ones = stream::repeat(1).debounce(ms(10));
+            for _ in 0..100 {
+                assert_eq!(None, ones.next().now_or_never());
+            }
+        });
+    }
+
+    #[test]
+    fn timeout_each() {
+        struct Test {
+            name: &'static str,
+            timeout: u64,
+            want: Vec<(u128, Result<u64, Elapsed>)>,
+        }
+        let elapsed = Err(Elapsed(()));
+        let tests = [
+            Test {
+                name: "longer than the source interval",
+                timeout: 15,
+                want: vec![(0, Ok(1)), (10, Ok(1)), (20, Ok(2))],
+            },
+            Test {
+                name: "shorter than the source interval",
+                timeout: 4,
+                want: vec![
+                    (0, Ok(1)),
+                    (4, elapsed),
+                    (8, elapsed),
+                    (10, Ok(1)),
+                    (14, elapsed),
+                    (18, elapsed),
+                    (20, Ok(2)),
+                ],
+            },
+        ];
+        let mut rt = paused();
+        for t in &tests {
+            let got = rt.block_on(timed(|| {
+                SlowFibonacci::new(ms(10))
+                    .take(3)
+                    .timeout_each(ms(t.timeout))
+            }));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+}